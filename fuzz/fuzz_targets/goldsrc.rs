@@ -0,0 +1,15 @@
+#![no_main]
+
+use bsp_rs::goldsrc::{GoldSrcBSP, MAX_MAP_HULLS};
+use bsp_rs::trace;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(map) = GoldSrcBSP::from_bytes(data) {
+        for hull in 0..MAX_MAP_HULLS {
+            if let Some(hull) = map.hull(hull) {
+                trace::is_visible(&hull, [0f32; 3], [64f32, 64f32, 64f32]);
+            }
+        }
+    }
+});
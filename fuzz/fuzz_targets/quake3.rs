@@ -0,0 +1,11 @@
+#![no_main]
+
+use bsp_rs::quake3::Quake3BSP;
+use bsp_rs::trace;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(map) = Quake3BSP::from_bytes(data) {
+        trace::is_visible(&map, [0f32; 3], [64f32, 64f32, 64f32]);
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use bsp_rs::bsp::{self, BSPView, LoadOptions, BSP};
+use bsp_rs::trace;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BSP::from_bytes(data);
+    let _ = BSPView::new(data);
+    let _ = bsp::crc_map_file(std::io::Cursor::new(data));
+    let _ = bsp::parse_entities(data);
+
+    let options = LoadOptions::new().lenient(true);
+    if let Ok(map) = BSP::from_bytes_with(data, &options) {
+        trace::is_visible(&map, [0f32; 3], [64f32, 64f32, 64f32]);
+        if let Ok(zones) = map.zones() {
+            for zone in zones.iter() {
+                map.point_in_zone(zone, [0f32; 3]);
+            }
+        }
+    }
+});
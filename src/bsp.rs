@@ -1,34 +1,98 @@
+pub mod cache;
+pub mod crc;
+pub mod endian;
+pub mod entity;
+pub mod io_graph;
+pub mod lmp;
+pub mod lump;
+pub mod lzma;
 pub mod math;
 pub mod native;
+pub mod options;
 pub mod polygon;
+pub mod validate;
+pub mod view;
+pub mod writer;
+pub mod zones;
 
+pub use crc::crc_map_file;
+pub use endian::SwapBytes;
+pub use entity::*;
+pub use io_graph::*;
+pub use lmp::*;
+pub use lump::GameLump;
 pub use native::*;
+pub use options::*;
 pub use polygon::*;
+pub use validate::*;
+pub use view::*;
+pub use writer::*;
+pub use zones::*;
+
+#[cfg(test)]
+pub(crate) mod fixture;
 
 use crate::error::*;
 
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom};
+use lump::*;
+
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 use std::mem::size_of;
 use std::path::Path;
 
-use dataview::{Pod, PodMethods};
+use dataview::PodMethods;
+
+/// Returns true if the leaf lump is stored in the version 0 layout (`dleaf_v0_t`).
+///
+/// The lump version decides the layout, newer bsp versions only ship version 1 leafs.
+/// Bsp v17 (Bloodlines) and v18 predate version 1 leafs, they store lump version 0 and are
+/// read like v19 and v20 maps. If the lump size does not fit the chosen layout but fits the
+/// other one the size wins, a lump that fits neither fails with a size mismatch.
+fn is_leaf_v0(header: &dheader_t, lump_len: usize) -> bool {
+    let lump = &header.lumps[LumpIndex::Leafs as usize];
+    let v0 = lump.version == 0 && header.version <= 20;
+
+    let (size, other_size) = if v0 {
+        (size_of::<dleaf_v0_t>(), size_of::<dleaf_t>())
+    } else {
+        (size_of::<dleaf_t>(), size_of::<dleaf_v0_t>())
+    };
+    if !lump_len.is_multiple_of(size) && lump_len.is_multiple_of(other_size) {
+        !v0
+    } else {
+        v0
+    }
+}
 
-fn parse_lump_data<T: Pod + Clone>(
-    file: &mut File,
+/// Parses the leaf lump into the version 1 leaf layout.
+///
+/// Version 0 leafs embed their ambient lighting which is returned separately.
+fn parse_leaves(
     header: &dheader_t,
-    lump: LumpIndex,
-) -> Result<Vec<T>> {
-    let lump = &header.lumps[lump as usize];
-    let lump_size = lump.filelen / size_of::<T>() as i32;
-    if lump_size == 0 {
-        return Err(Error::new("invalid lump data"));
-    }
-
-    let mut out: Vec<T> = vec![unsafe { core::mem::zeroed() }; lump_size as usize];
-    file.seek(SeekFrom::Start(lump.fileofs as u64))?;
-    file.read_exact(out.as_bytes_mut())?;
-    Ok(out)
+    raw_lumps: &RawLumps,
+) -> Result<(Vec<dleaf_t>, Vec<CompressedLightCube>)> {
+    if is_leaf_v0(header, raw_lumps.get(LumpIndex::Leafs).len()) {
+        let leaves_v0: Vec<dleaf_v0_t> = raw_lumps.cast(LumpIndex::Leafs)?;
+        let ambient_lighting = leaves_v0.iter().map(|l| l.ambient_lighting).collect();
+        let leaves = leaves_v0.into_iter().map(dleaf_t::from).collect();
+        Ok((leaves, ambient_lighting))
+    } else {
+        Ok((raw_lumps.cast(LumpIndex::Leafs)?, Vec::new()))
+    }
+}
+
+/// Parses the face lump into the standard face layout.
+///
+/// Version 17 faces start with their average light colors, they are dropped.
+fn parse_faces(header: &dheader_t, raw_lumps: &RawLumps) -> Result<Vec<dface_t>> {
+    if header.version == 17 {
+        let faces_v17: Vec<dface_v17_t> = raw_lumps.cast(LumpIndex::Faces)?;
+        Ok(faces_v17.into_iter().map(dface_t::from).collect())
+    } else {
+        raw_lumps.cast(LumpIndex::Faces)
+    }
 }
 
 #[allow(dead_code)]
@@ -39,6 +103,8 @@ pub struct BSP {
     pub edges: Vec<dedge_t>,
     pub surf_edges: Vec<i32>,
     pub leaves: Vec<dleaf_t>,
+    /// Per-leaf ambient lighting, only present for version 0 leaf lumps.
+    pub leaf_ambient_lighting: Vec<CompressedLightCube>,
     //pub dnodes: Vec<dnode_t>,
     pub nodes: Vec<snode_t>,
     pub faces: Vec<dface_t>,
@@ -47,61 +113,186 @@ pub struct BSP {
     pub brush_sides: Vec<dbrushside_t>,
     pub leaf_faces: Vec<u16>,
     pub leaf_brushes: Vec<u16>,
+    pub models: Vec<dmodel_t>,
     pub polys: Vec<Polygon>,
+    /// Index into `polys` for every face, `None` for faces without a polygon.
+    pub face_polys: Vec<Option<u32>>,
+    pub game_lumps: Vec<GameLump>,
+    /// Lumps that were replaced by external lump files.
+    pub lump_overrides: Vec<LumpOverride>,
+    /// Invalid cross-lump references, only non-empty for maps loaded in lenient mode.
+    pub validation: ValidationReport,
+    /// The bsp header in native byte order and standard lump layout.
+    ///
+    /// Lumps that were replaced by lump files carry the version and length of their lump file.
+    pub header: dheader_t,
+    map_crc: Option<u32>,
+    raw_lumps: Vec<Vec<u8>>,
 }
 
 impl BSP {
+    /// Opens and parses the bsp file at the given path.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut file = OpenOptions::new().read(true).write(false).open(path)?;
+        Self::open_with(path, &LoadOptions::default())
+    }
+
+    /// Opens and parses the bsp file at the given path with custom load options.
+    ///
+    /// If enabled in the options, lump files next to the map are applied as well.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<Self> {
+        let lump_files = open_lump_files(path.as_ref(), options)?;
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        Self::from_reader_with_overrides(BufReader::new(file), options, lump_files)
+    }
+
+    /// Opens the bsp file at the given path through a cache of the processed map.
+    ///
+    /// If the cache at `cache_path` is missing or stale the map is parsed and the cache is rewritten.
+    pub fn open_cached<P: AsRef<Path>, C: AsRef<Path>>(path: P, cache_path: C) -> Result<Self> {
+        Self::open_cached_with(path, cache_path, &LoadOptions::default())
+    }
 
-        let mut header = dheader_t::default();
-        file.read_exact(header.as_bytes_mut())?;
+    /// Opens the bsp file at the given path through a cache with custom load options.
+    ///
+    /// Caches are keyed by the contents of the map, its lump files and the load options.
+    pub fn open_cached_with<P: AsRef<Path>, C: AsRef<Path>>(
+        path: P,
+        cache_path: C,
+        options: &LoadOptions,
+    ) -> Result<Self> {
+        let bytes = fs::read(path.as_ref())?;
+        let lump_files = open_lump_files(path.as_ref(), options)?;
+        let key = cache::cache_key(&bytes, &lump_files, options);
 
-        if header.ident != HEADER_MAGIC {
-            return Err(Error::new(format!(
-                "invalid bsp magic: 0x{:x}",
-                header.ident
-            )));
+        if let Ok(cached) = fs::read(cache_path.as_ref()) {
+            if let Some(map) = cache::read(&cached, key) {
+                return Ok(map);
+            }
         }
 
-        if header.version < 19 {
-            return Err(Error::new(format!(
-                "invalid bsp file version: {}",
-                header.version
-            )));
+        let map = Self::from_reader_with_overrides(Cursor::new(&bytes), options, lump_files)?;
+        // the cache only speeds up loading, failing to write it does not fail opening the map
+        cache::write_file(&map, key, cache_path.as_ref()).ok();
+        Ok(map)
+    }
+
+    /// Parses a bsp that is fully held in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes_with(bytes, &LoadOptions::default())
+    }
+
+    /// Parses a bsp that is fully held in memory with custom load options.
+    pub fn from_bytes_with(bytes: &[u8], options: &LoadOptions) -> Result<Self> {
+        Self::from_reader_with(Cursor::new(bytes), options)
+    }
+
+    /// Memory-maps the bsp file at the given path without copying or decoding any lumps up front.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<MappedBSP> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        BSPView::new(mmap)
+    }
+
+    /// Parses a bsp from any seekable source (files, archive entries, in-memory buffers).
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        Self::from_reader_with(reader, &LoadOptions::default())
+    }
+
+    /// Parses a bsp from any seekable source with custom load options.
+    pub fn from_reader_with<R: Read + Seek>(reader: R, options: &LoadOptions) -> Result<Self> {
+        Self::from_reader_with_overrides(reader, options, Vec::new())
+    }
+
+    /// Parses a bsp from any seekable source and replaces lumps with the given lump files.
+    ///
+    /// Lump files are applied in order, later files win over earlier ones.
+    pub fn from_reader_with_overrides<R: Read + Seek>(
+        mut reader: R,
+        options: &LoadOptions,
+        lump_files: Vec<LumpFile>,
+    ) -> Result<Self> {
+        let (header, big_endian) = read_header(&mut reader, options.lump_layout)?;
+
+        // the engine computes the crc over the map file alone, lump files are not included
+        let map_crc = if options.map_crc {
+            Some(crc::crc_lumps(&mut reader, &header)?)
+        } else {
+            None
+        };
+
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let unpack_budget = options.unpack_budget(file_len);
+        let mut reader = LumpReader::new(reader, header, big_endian, unpack_budget);
+        let mut lump_overrides: Vec<LumpOverride> = Vec::new();
+        for file in lump_files.into_iter() {
+            if let Some(lump_override) = reader.apply(file)? {
+                lump_overrides.retain(|o| o.lump_id != lump_override.lump_id);
+                lump_overrides.push(lump_override);
+            }
         }
 
-        let vertexes: Vec<mvertex_t> = parse_lump_data(&mut file, &header, LumpIndex::Vertexes)?;
-        let dplanes: Vec<dplane_t> = parse_lump_data(&mut file, &header, LumpIndex::Planes)?;
+        let raw_lumps = reader.read_all(options.profile)?;
+        let vertexes: Vec<mvertex_t> = raw_lumps.cast(LumpIndex::Vertexes)?;
+        let dplanes: Vec<dplane_t> = raw_lumps.cast(LumpIndex::Planes)?;
         let planes = parse_planes(&dplanes)?;
 
-        let edges: Vec<dedge_t> = parse_lump_data(&mut file, &header, LumpIndex::Edges)?;
-        let surf_edges: Vec<i32> = parse_lump_data(&mut file, &header, LumpIndex::SurfEdges)?;
-        let leaves: Vec<dleaf_t> = parse_lump_data(&mut file, &header, LumpIndex::Leafs)?;
-        let dnodes: Vec<dnode_t> = parse_lump_data(&mut file, &header, LumpIndex::Nodes)?;
+        let edges: Vec<dedge_t> = raw_lumps.cast(LumpIndex::Edges)?;
+        let surf_edges: Vec<i32> = raw_lumps.cast(LumpIndex::SurfEdges)?;
+        let (leaves, leaf_ambient_lighting) = parse_leaves(reader.header(), &raw_lumps)?;
+        let dnodes: Vec<dnode_t> = raw_lumps.cast(LumpIndex::Nodes)?;
         let nodes = parse_nodes(&dnodes)?;
 
-        let faces: Vec<dface_t> = parse_lump_data(&mut file, &header, LumpIndex::Faces)?;
-        let tex_info: Vec<texinfo_t> = parse_lump_data(&mut file, &header, LumpIndex::TexInfo)?;
-        let brushes: Vec<dbrush_t> = parse_lump_data(&mut file, &header, LumpIndex::Brushes)?;
-        let brush_sides: Vec<dbrushside_t> =
-            parse_lump_data(&mut file, &header, LumpIndex::BrushSides)?;
+        let faces = parse_faces(reader.header(), &raw_lumps)?;
+        let tex_info: Vec<texinfo_t> = raw_lumps.cast(LumpIndex::TexInfo)?;
+        let brushes: Vec<dbrush_t> = raw_lumps.cast(LumpIndex::Brushes)?;
+        let brush_sides: Vec<dbrushside_t> = raw_lumps.cast(LumpIndex::BrushSides)?;
 
-        let leaf_faces: Vec<u16> = parse_lump_data(&mut file, &header, LumpIndex::LeafFaces)?;
+        let leaf_faces: Vec<u16> = raw_lumps.cast(LumpIndex::LeafFaces)?;
         if leaf_faces.len() > MAX_MAP_LEAFBRUSHES {
-            return Err(Error::new("map has to many leaf_faces"));
-        } else if leaf_faces.is_empty() {
-            return Err(Error::new("map has no many leaf_faces"));
+            return Err(Error::InvalidLump {
+                lump: LumpIndex::LeafFaces,
+                reason: "map has too many leaf faces",
+            });
         }
 
-        let leaf_brushes: Vec<u16> = parse_lump_data(&mut file, &header, LumpIndex::LeafBrushes)?;
+        let leaf_brushes: Vec<u16> = raw_lumps.cast(LumpIndex::LeafBrushes)?;
         if leaf_brushes.len() > MAX_MAP_LEAFBRUSHES {
-            return Err(Error::new("map has to many leaf_brushes"));
-        } else if leaf_faces.is_empty() {
-            return Err(Error::new("map has no many leaf_brushes"));
+            return Err(Error::InvalidLump {
+                lump: LumpIndex::LeafBrushes,
+                reason: "map has too many leaf brushes",
+            });
+        }
+
+        let models: Vec<dmodel_t> = raw_lumps.cast(LumpIndex::Models)?;
+
+        let validation = validate(&Lumps {
+            profile: options.profile,
+            vertexes: vertexes.len(),
+            planes: planes.len(),
+            edges: &edges,
+            surf_edges: &surf_edges,
+            nodes: &dnodes,
+            leaves: &leaves,
+            faces: &faces,
+            brushes: &brushes,
+            brush_sides: &brush_sides,
+            leaf_faces: &leaf_faces,
+            leaf_brushes: &leaf_brushes,
+            models: &models,
+        });
+        if !options.lenient {
+            validation.check()?;
         }
 
-        let polys = parse_polygons(&faces, &surf_edges, &edges, &vertexes, &planes)?;
+        let (polys, face_polys) = parse_polygons(&faces, &surf_edges, &edges, &vertexes, &planes)?;
+
+        let game_lumps = if options.profile.includes(LumpIndex::GameLump) {
+            reader.game_lumps()?
+        } else {
+            Vec::new()
+        };
+        let header = reader.header().clone();
 
         Ok(Self {
             vertexes,
@@ -110,6 +301,7 @@ impl BSP {
             edges,
             surf_edges,
             leaves,
+            leaf_ambient_lighting,
             //dnodes,
             nodes,
             faces,
@@ -118,12 +310,139 @@ impl BSP {
             brush_sides,
             leaf_faces,
             leaf_brushes,
+            models,
             polys,
+            face_polys,
+            game_lumps,
+            lump_overrides,
+            validation,
+            header,
+            map_crc,
+            raw_lumps: raw_lumps.into_inner(),
         })
     }
+
+    /// Iterates over the descriptors of all lumps in header order.
+    pub fn lumps(&self) -> impl Iterator<Item = (LumpIndex, &lump_t)> {
+        self.header.iter_lumps()
+    }
+
+    /// The decompressed contents of a lump in file byte order.
+    ///
+    /// This also gives access to lumps that are not parsed by this crate,
+    /// lumps that are not part of the load profile are empty.
+    pub fn raw_lump(&self, index: LumpIndex) -> &[u8] {
+        &self.raw_lumps[index as usize]
+    }
+
+    /// Parses the entity lump.
+    ///
+    /// Returns no entities if the entity lump is not part of the load profile.
+    pub fn entities(&self) -> Result<Vec<Entity>> {
+        parse_entities(self.raw_lump(LumpIndex::Entities))
+    }
+
+    /// Parses the entity lump into a list that can be queried by classname and targetname.
+    pub fn entity_index(&self) -> Result<Entities> {
+        Entities::parse(self.raw_lump(LumpIndex::Entities))
+    }
+
+    /// The crc the engine uses to verify that clients run the same map (`CRC_MapFile`).
+    ///
+    /// Only computed if enabled in the load options, see `crc_map_file` to compute it without parsing the map.
+    pub fn map_crc(&self) -> Option<u32> {
+        self.map_crc
+    }
+}
+
+fn open_lump_files(path: &Path, options: &LoadOptions) -> Result<Vec<LumpFile>> {
+    if !options.lump_overrides {
+        return Ok(Vec::new());
+    }
+
+    find_lump_files(path)
+        .iter()
+        .map(LumpFile::open)
+        .collect::<Result<Vec<_>>>()
+}
+
+/// Reads the header in native byte order and standard lump layout.
+///
+/// Returns the header and whether the file is stored in big-endian.
+pub(crate) fn read_header<R: Read + Seek>(
+    reader: &mut R,
+    layout: LumpLayout,
+) -> Result<(dheader_t, bool)> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+
+    let mut header = dheader_t::default();
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(header.as_bytes_mut())?;
+
+    // console maps are stored in big-endian and are swapped while parsing
+    let big_endian = header.ident == HEADER_MAGIC_BE;
+    if big_endian {
+        header.swap_bytes();
+    }
+    check_header(&header)?;
+    normalize_lump_layout(&mut header, file_len, layout);
+    check_lump_placement(&header, file_len)?;
+    Ok((header, big_endian))
+}
+
+/// Rejects lumps that lie outside of the file, overlap the header or overlap each other.
+///
+/// Empty lumps are skipped, compilers do not care about their offset.
+pub(crate) fn check_lump_placement(header: &dheader_t, file_len: u64) -> Result<()> {
+    let mut placed = Vec::new();
+    for (index, lump) in header.iter_lumps() {
+        if lump.filelen == 0 {
+            continue;
+        }
+
+        let start = lump.fileofs as i64;
+        let end = start + lump.filelen as i64;
+        if lump.fileofs < 0 || lump.filelen < 0 || end as u64 > file_len {
+            return Err(Error::LumpOutOfBounds {
+                lump: index,
+                offset: start,
+                len: lump.filelen as i64,
+            });
+        }
+        if start < size_of::<dheader_t>() as i64 {
+            return Err(Error::InvalidLump {
+                lump: index,
+                reason: "lump overlaps the header",
+            });
+        }
+        placed.push((start, end, index));
+    }
+
+    placed.sort_by_key(|&(start, end, _)| (start, end));
+    for pair in placed.windows(2) {
+        if pair[1].0 < pair[0].1 {
+            return Err(Error::InvalidLump {
+                lump: pair[1].2,
+                reason: "lump overlaps another lump",
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_header(header: &dheader_t) -> Result<()> {
+    if header.ident != HEADER_MAGIC {
+        return Err(Error::BadMagic(header.ident));
+    }
+
+    if header.version < MIN_BSP_VERSION {
+        return Err(Error::UnsupportedVersion(header.version));
+    }
+
+    Ok(())
 }
 
-fn parse_planes(dplanes: &Vec<dplane_t>) -> Result<Vec<cplane_t>> {
+fn parse_planes(dplanes: &[dplane_t]) -> Result<Vec<cplane_t>> {
     let mut cplanes: Vec<cplane_t> = Vec::new();
 
     dplanes.iter().for_each(|p| {
@@ -145,7 +464,7 @@ fn parse_planes(dplanes: &Vec<dplane_t>) -> Result<Vec<cplane_t>> {
     Ok(cplanes)
 }
 
-fn parse_nodes(dnodes: &Vec<dnode_t>) -> Result<Vec<snode_t>> {
+fn parse_nodes(dnodes: &[dnode_t]) -> Result<Vec<snode_t>> {
     let mut snodes: Vec<snode_t> = Vec::new();
 
     dnodes.iter().for_each(|n| {
@@ -179,35 +498,547 @@ fn parse_nodes(dnodes: &Vec<dnode_t>) -> Result<Vec<snode_t>> {
     Ok(snodes)
 }
 
+fn get<T>(data: &[T], index: i64) -> Option<&T> {
+    usize::try_from(index).ok().and_then(|i| data.get(i))
+}
+
+/// Builds the polygons of all textured faces and the polygon index of every face.
+///
+/// Faces with invalid references are skipped, they are reported by the validation pass instead.
 fn parse_polygons(
-    faces: &Vec<dface_t>,
-    surf_edges: &Vec<i32>,
-    edges: &Vec<dedge_t>,
-    vertexes: &Vec<mvertex_t>,
-    planes: &Vec<cplane_t>,
-) -> Result<Vec<Polygon>> {
+    faces: &[dface_t],
+    surf_edges: &[i32],
+    edges: &[dedge_t],
+    vertexes: &[mvertex_t],
+    planes: &[cplane_t],
+) -> Result<(Vec<Polygon>, Vec<Option<u32>>)> {
     let mut polys: Vec<Polygon> = Vec::new();
+    let mut face_polys = Vec::with_capacity(faces.len());
 
     for f in faces.iter() {
-        if f.num_edges < 3 || f.num_edges > MAX_SURFINFO_VERTS as i16 {
-            continue;
-        }
+        let poly = if f.num_edges < 3 || f.num_edges > MAX_SURFINFO_VERTS as i16 || f.tex_info <= 0
+        {
+            None
+        } else {
+            parse_polygon(f, surf_edges, edges, vertexes, planes)
+        };
 
-        if f.tex_info <= 0 {
-            continue;
-        }
+        face_polys.push(poly.map(|poly| {
+            polys.push(poly);
+            (polys.len() - 1) as u32
+        }));
+    }
 
-        let mut verts = [[0f32; 3]; MAX_SURFINFO_VERTS];
-        for i in 0..(f.num_edges as i32) {
-            let edge_idx = surf_edges[(f.first_edge + i) as usize];
-            if edge_idx >= 0 {
-                verts[i as usize] = vertexes[edges[edge_idx as usize].v[0] as usize].position;
-            } else {
-                verts[i as usize] = vertexes[edges[-edge_idx as usize].v[1] as usize].position;
+    Ok((polys, face_polys))
+}
+
+fn parse_polygon(
+    f: &dface_t,
+    surf_edges: &[i32],
+    edges: &[dedge_t],
+    vertexes: &[mvertex_t],
+    planes: &[cplane_t],
+) -> Option<Polygon> {
+    let mut verts = [[0f32; 3]; MAX_SURFINFO_VERTS];
+    for i in 0..(f.num_edges as i32) {
+        let edge_idx = *get(surf_edges, f.first_edge as i64 + i as i64)?;
+        let edge = get(edges, (edge_idx as i64).abs())?;
+        let vertex = if edge_idx >= 0 { edge.v[0] } else { edge.v[1] };
+        verts[i as usize] = get(vertexes, vertex as i64)?.position;
+    }
+    let plane = get(planes, f.plane_num as i64)?;
+    Some(Polygon::with(verts, f, plane))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::MapBuilder;
+    use super::*;
+    use crate::trace;
+
+    #[test]
+    fn from_bytes() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert_eq!(map.planes.len(), 7);
+        assert_eq!(map.nodes.len(), 1);
+        assert_eq!(map.leaves.len(), 2);
+        assert_eq!(map.brushes.len(), 1);
+        assert_eq!(map.brush_sides.len(), 6);
+        assert_eq!(map.models.len(), 1);
+        assert_eq!(map.polys.len(), 1);
+        assert_eq!(map.planes[2].sign_bits, 1);
+    }
+
+    #[test]
+    fn from_reader() {
+        let mut reader = Cursor::new(MapBuilder::box_room().build());
+        reader.set_position(64);
+
+        // lump offsets are relative to the start of the source, not the current position
+        let map = BSP::from_reader(&mut reader).unwrap();
+        assert_eq!(map.vertexes.len(), 4);
+        assert_eq!(map.edges.len(), 4);
+        assert_eq!(map.surf_edges.len(), 4);
+    }
+
+    #[test]
+    fn compressed_lumps() {
+        let builder = MapBuilder::box_room()
+            .compressed(LumpIndex::Planes)
+            .compressed(LumpIndex::Leafs)
+            .compressed(LumpIndex::BrushSides);
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+        assert_eq!(map.planes.len(), 7);
+        assert_eq!(map.leaves.len(), 2);
+        assert_eq!(map.brush_sides.len(), 6);
+        assert_eq!(map.brush_sides[5].plane_num, 6);
+
+        // the lumps unpack to 7 planes, 2 leafs and 6 brush sides
+        let unpacked = 7 * 20 + 2 * 32 + 6 * 8;
+        let data = builder.build();
+        let options = LoadOptions::new().max_unpacked_size(unpacked);
+        assert!(BSP::from_bytes_with(&data, &options).is_ok());
+        let options = LoadOptions::new().max_unpacked_size(unpacked - 1);
+        assert!(matches!(
+            BSP::from_bytes_with(&data, &options),
+            Err(Error::InvalidLump { .. })
+        ));
+
+        // the size in the lzma header has to match the uncompressed size in the bsp header
+        let mut builder = builder;
+        builder.lumps[LumpIndex::Leafs as usize].four_cc = 63u32.to_le_bytes();
+        assert!(matches!(
+            BSP::from_bytes(&builder.build()),
+            Err(Error::Decompress {
+                lump: LumpIndex::Leafs,
+                source: None
+            })
+        ));
+
+        // only the uncompressed size in the header marks a lump as compressed
+        let data = b"LZMA uncompressed overlay data".to_vec();
+        let builder = MapBuilder::box_room().lump(LumpIndex::Overlays, &data[..]);
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+        assert_eq!(map.raw_lump(LumpIndex::Overlays), &data[..]);
+    }
+
+    #[test]
+    fn game_lumps() {
+        let builder = MapBuilder::box_room()
+            .game_lump(0x73707270, 10, true, &[1u8; 300]) // 'sprp'
+            .game_lump(0x64707270, 4, false, &[2u8; 12]); // 'dprp'
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+        assert_eq!(map.game_lumps.len(), 2);
+        assert_eq!(map.game_lumps[0].id, 0x73707270);
+        assert_eq!(map.game_lumps[0].version, 10);
+        assert_eq!(map.game_lumps[0].data, vec![1u8; 300]);
+        assert_eq!(map.game_lumps[1].data, vec![2u8; 12]);
+
+        // compressed game lumps count towards the unpacked size limit
+        let mut data = builder.build();
+        let options = LoadOptions::new().max_unpacked_size(299);
+        assert!(BSP::from_bytes_with(&data, &options).is_err());
+
+        // the length in the directory has to match the size in the lzma header
+        let dir =
+            map.header.lump(LumpIndex::GameLump).fileofs as usize + size_of::<dgamelumpheader_t>();
+        let filelen = dir + 12;
+        data[filelen..filelen + 4].copy_from_slice(&301i32.to_le_bytes());
+        assert!(matches!(
+            BSP::from_bytes(&data),
+            Err(Error::Decompress {
+                lump: LumpIndex::GameLump,
+                source: None
+            })
+        ));
+    }
+
+    #[test]
+    fn lump_placement() {
+        let data = MapBuilder::box_room().build();
+        let lump_ofs = |index: LumpIndex| 8 + index as usize * size_of::<lump_t>();
+
+        // let the planes start in the middle of the vertexes
+        let mut overlapping = data.clone();
+        let vertexes = lump_ofs(LumpIndex::Vertexes);
+        let fileofs = i32::from_le_bytes([
+            data[vertexes],
+            data[vertexes + 1],
+            data[vertexes + 2],
+            data[vertexes + 3],
+        ]);
+        let planes = lump_ofs(LumpIndex::Planes);
+        overlapping[planes..planes + 4].copy_from_slice(&(fileofs + 4).to_le_bytes());
+        assert!(matches!(
+            BSP::from_bytes(&overlapping),
+            Err(Error::InvalidLump {
+                reason: "lump overlaps another lump",
+                ..
+            })
+        ));
+        assert!(BSPView::new(&overlapping[..]).is_err());
+
+        let mut header = data.clone();
+        header[planes..planes + 4].copy_from_slice(&0i32.to_le_bytes());
+        assert!(matches!(
+            BSP::from_bytes(&header),
+            Err(Error::InvalidLump {
+                lump: LumpIndex::Planes,
+                reason: "lump overlaps the header",
+            })
+        ));
+
+        // a huge length must not be allocated
+        let mut huge = data;
+        huge[planes + 4..planes + 8].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(
+            BSP::from_bytes(&huge),
+            Err(Error::LumpOutOfBounds {
+                lump: LumpIndex::Planes,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+
+        let json = serde_json::to_string(&map.header).unwrap();
+        let header: dheader_t = serde_json::from_str(&json).unwrap();
+        assert_eq!(header.as_bytes(), map.header.as_bytes());
+
+        let json = serde_json::to_string(&map.faces).unwrap();
+        let faces: Vec<dface_t> = serde_json::from_str(&json).unwrap();
+        assert_eq!(faces.as_bytes(), map.faces.as_bytes());
+
+        let json = serde_json::to_string(&map.polys[0]).unwrap();
+        let poly: Polygon = serde_json::from_str(&json).unwrap();
+        assert_eq!(poly.vert_num, map.polys[0].vert_num);
+        assert_eq!(poly.verts, map.polys[0].verts);
+
+        let mut trace = trace::Trace::new();
+        trace::ray_cast(&map, [24f32, 0f32, 0f32], [-24f32, 0f32, 0f32], &mut trace);
+        let json = serde_json::to_string(&trace).unwrap();
+        let copy: trace::Trace = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy.fraction, trace.fraction);
+        assert_eq!(copy.end_pos, trace.end_pos);
+        assert!(copy.fraction < 1f32);
+        assert_eq!(copy.brush.is_some(), trace.brush.is_some());
+
+        // the lump array has a fixed length
+        let mut value = serde_json::to_value(&map.header).unwrap();
+        value["lumps"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<dheader_t>(value).is_err());
+    }
+
+    #[test]
+    fn entities() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        let entities = map.entities().unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].get("classname"), Some("worldspawn"));
+        assert_eq!(entities[1].get("origin"), Some("-64 0 0"));
+
+        let index = map.entity_index().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.worldspawn(), Some(&entities[0]));
+        assert_eq!(
+            index
+                .entities_by_class("INFO_PLAYER_START")
+                .collect::<Vec<_>>(),
+            vec![&entities[1]]
+        );
+
+        let builder = MapBuilder::box_room().lump(LumpIndex::Entities, b"{ \"classname\"".as_ref());
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+        assert!(map.entities().is_err());
+        assert!(map.entity_index().is_err());
+    }
+
+    /// A single version 17 face in the box room.
+    ///
+    /// Bloodlines faces carry the average light colors of 8 styles in front, the bytes are
+    /// written by hand so the layout is not checked against itself.
+    fn bloodlines_face() -> [u8; 0x68] {
+        let mut face = [0u8; 0x68];
+        face[0x00] = 0xff; // avg_light_color[0].r
+        face[0x20..0x22].copy_from_slice(&1u16.to_le_bytes()); // plane_num
+        face[0x28..0x2A].copy_from_slice(&4i16.to_le_bytes()); // num_edges
+        face[0x2A..0x2C].copy_from_slice(&1i16.to_le_bytes()); // tex_info
+        face[0x2C..0x2E].copy_from_slice(&(-1i16).to_le_bytes()); // disp_info
+        face[0x30..0x38].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]); // styles
+        face[0x38] = 8; // day_styles
+        face[0x40] = 9; // night_styles
+        face[0x48..0x4C].copy_from_slice(&(-1i32).to_le_bytes()); // light_ofs
+        face[0x64..0x68].copy_from_slice(&3u32.to_le_bytes()); // smoothing_groups
+        face
+    }
+
+    #[test]
+    fn old_versions() {
+        let mut builder = MapBuilder::box_room();
+        builder.version = 18;
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+        assert_eq!(map.polys.len(), 1);
+
+        let face = bloodlines_face();
+        let mut builder = MapBuilder::box_room().lump(LumpIndex::Faces, &face[..]);
+        builder.version = 17;
+        let data = builder.build();
+
+        let map = BSP::from_bytes(&data).unwrap();
+        assert_eq!(map.faces.len(), 1);
+        assert_eq!({ map.faces[0].num_edges }, 4);
+        assert_eq!({ map.faces[0].disp_info }, -1);
+        assert_eq!({ map.faces[0].styles }, [0, 1, 2, 3]);
+        assert_eq!({ map.faces[0].light_ofs }, -1);
+        assert_eq!({ map.faces[0].smoothing_groups }, 3);
+        assert_eq!(map.polys.len(), 1);
+        assert!(!trace::is_visible(
+            &map,
+            [24f32, 0f32, 0f32],
+            [-24f32, 0f32, 0f32]
+        ));
+
+        assert!(matches!(
+            BSPView::new(&data[..]),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn leaf_v0() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert!(map.leaf_ambient_lighting.is_empty());
+
+        let mut leaf = dleaf_v0_t::zeroed();
+        leaf.contents = 1;
+        leaf.mins = [-8, -8, -8];
+        leaf.maxs = [8, 8, 8];
+        leaf.num_leaf_brushes = 1;
+        leaf.ambient_lighting.color[2].g = 42;
+        let leaves = [leaf, leaf, leaf];
+
+        for (version, lump_version) in [(17, 0), (18, 0), (19, 0), (20, 0), (19, 1)] {
+            let mut builder = MapBuilder::box_room()
+                .lump(LumpIndex::Leafs, &leaves[..])
+                .lump_version(LumpIndex::Leafs, lump_version);
+            if version == 17 {
+                builder = builder.lump(LumpIndex::Faces, &bloodlines_face()[..]);
             }
+            builder.version = version;
+
+            let map = BSP::from_bytes(&builder.build()).unwrap();
+            assert_eq!(map.leaves.len(), 3);
+            assert_eq!({ map.leaves[2].contents }, 1);
+            assert_eq!({ map.leaves[2].maxs }, [8, 8, 8]);
+            assert_eq!({ map.leaves[2].num_leaf_brushes }, 1);
+            assert_eq!(map.leaf_ambient_lighting.len(), 3);
+            assert_eq!(map.leaf_ambient_lighting[1].color[2].g, 42);
+        }
+    }
+
+    #[test]
+    fn lump_layout_l4d2() {
+        let mut builder = MapBuilder::box_room();
+        builder.lump_layout = LumpLayout::Left4Dead2;
+        let data = builder.build();
+
+        let map = BSP::from_bytes(&data).unwrap();
+        assert_eq!(map.planes.len(), 7);
+        assert_eq!(map.brush_sides.len(), 6);
+
+        let options = LoadOptions::new().lump_layout(LumpLayout::Left4Dead2);
+        assert!(BSP::from_bytes_with(&data, &options).is_ok());
+
+        // forcing the wrong layout reads the lumps from bogus offsets
+        let options = LoadOptions::new().lump_layout(LumpLayout::Standard);
+        assert!(matches!(
+            BSP::from_bytes_with(&data, &options),
+            Err(Error::InvalidLump {
+                lump: LumpIndex::Entities,
+                reason: "lump overlaps the header",
+            })
+        ));
+    }
+
+    #[test]
+    fn big_endian() {
+        let data = MapBuilder::box_room_big_endian().build();
+        assert_eq!(&data[..4], b"PSBV");
+
+        let map = BSP::from_bytes(&data).unwrap();
+        let expected = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert_eq!(map.planes.len(), expected.planes.len());
+        for (a, b) in map.planes.iter().zip(expected.planes.iter()) {
+            assert_eq!(a.as_bytes(), b.as_bytes());
         }
-        polys.push(Polygon::with(verts, f, &planes[f.plane_num as usize]));
+        assert_eq!(map.nodes[0].children, expected.nodes[0].children);
+        assert_eq!(map.faces[0].as_bytes(), expected.faces[0].as_bytes());
+        assert_eq!(map.surf_edges, expected.surf_edges);
+        assert_eq!(map.brush_sides[3].plane_num, 4);
+        assert_eq!(map.polys.len(), 1);
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut builder = MapBuilder::box_room();
+        builder.ident = 0x12345678;
+        assert!(matches!(
+            BSP::from_bytes(&builder.build()),
+            Err(Error::BadMagic(0x12345678))
+        ));
     }
 
-    Ok(polys)
+    #[test]
+    fn unsupported_version() {
+        let mut builder = MapBuilder::box_room();
+        builder.version = 16;
+        assert!(matches!(
+            BSP::from_bytes(&builder.build()),
+            Err(Error::UnsupportedVersion(16))
+        ));
+    }
+
+    #[test]
+    fn lump_out_of_bounds() {
+        let mut data = MapBuilder::box_room().build();
+        data.truncate(data.len() - 4);
+        assert!(matches!(
+            BSP::from_bytes(&data),
+            Err(Error::LumpOutOfBounds {
+                lump: LumpIndex::BrushSides,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn lump_size_mismatch() {
+        let builder = MapBuilder::box_room().lump(LumpIndex::Vertexes, &[0u8; 13][..]);
+        assert!(matches!(
+            BSP::from_bytes(&builder.build()),
+            Err(Error::LumpSizeMismatch {
+                lump: LumpIndex::Vertexes,
+                len: 13,
+                element_size: 12,
+            })
+        ));
+    }
+
+    #[test]
+    fn raw_lumps() {
+        let mut builder = MapBuilder::box_room()
+            .lump(LumpIndex::Overlays, &[1u8, 2, 3, 4][..])
+            .lump_version(LumpIndex::Overlays, 3)
+            .compressed(LumpIndex::Planes);
+        builder.map_revision = 42;
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+
+        assert_eq!(map.header.map_revision, 42);
+        assert_eq!(map.raw_lump(LumpIndex::Overlays), &[1u8, 2, 3, 4][..]);
+        assert_eq!(
+            map.raw_lump(LumpIndex::Planes).len(),
+            7 * size_of::<dplane_t>()
+        );
+        assert!(map.raw_lump(LumpIndex::DispMultiBlend).is_empty());
+
+        let (index, lump) = map
+            .lumps()
+            .find(|(index, _)| *index == LumpIndex::Overlays)
+            .unwrap();
+        assert_eq!(index as usize, 45);
+        assert_eq!(lump.version, 3);
+        assert_eq!(lump.filelen, 4);
+        assert_eq!(map.lumps().count(), HEADER_LUMP_COUNT);
+    }
+
+    #[test]
+    fn collision_profile() {
+        let builder = MapBuilder::box_room().lump(LumpIndex::LeafFaces, &[0u8; 0][..]);
+        let options = LoadOptions::new().profile(LoadProfile::Collision);
+        let map = BSP::from_bytes_with(&builder.build(), &options).unwrap();
+        assert_eq!(map.brush_sides.len(), 6);
+        assert!(map.faces.is_empty());
+        assert!(map.polys.is_empty());
+        assert!(map.raw_lump(LumpIndex::Entities).is_empty());
+
+        assert!(!trace::is_visible(
+            &map,
+            [24f32, 0f32, 0f32],
+            [-24f32, 0f32, 0f32]
+        ));
+        assert!(trace::is_visible(
+            &map,
+            [24f32, 0f32, 0f32],
+            [24f32, 8f32, 0f32]
+        ));
+    }
+
+    #[test]
+    fn render_profile() {
+        let options = LoadOptions::new().profile(LoadProfile::Render);
+        let map = BSP::from_bytes_with(&MapBuilder::box_room().build(), &options).unwrap();
+        assert!(map.brushes.is_empty());
+        assert_eq!(map.polys.len(), 1);
+
+        // without brushes only the polygons of the world block the trace
+        assert!(!trace::is_visible(
+            &map,
+            [24f32, 0f32, 0f32],
+            [8f32, 0f32, 0f32]
+        ));
+        assert!(trace::is_visible(
+            &map,
+            [24f32, 8f32, 0f32],
+            [24f32, 0f32, 0f32]
+        ));
+    }
+
+    #[test]
+    fn lenient() {
+        let mut node = dnode_t::zeroed();
+        node.children = [-1, -3];
+        let builder = MapBuilder::box_room().lump(LumpIndex::Nodes, &[node][..]);
+        let data = builder.build();
+        assert!(matches!(
+            BSP::from_bytes(&data),
+            Err(Error::InvalidIndex {
+                lump: LumpIndex::Leafs,
+                index: 2,
+            })
+        ));
+
+        let map = BSP::from_bytes_with(&data, &LoadOptions::new().lenient(true)).unwrap();
+        assert_eq!(map.validation.invalid_references.len(), 1);
+        assert!(!trace::is_visible(
+            &map,
+            [24f32, 0f32, 0f32],
+            [8f32, 0f32, 0f32]
+        ));
+        assert!(trace::is_visible(
+            &map,
+            [-24f32, 0f32, 0f32],
+            [-8f32, 0f32, 0f32]
+        ));
+    }
+
+    #[test]
+    fn invalid_index() {
+        let mut face = dface_t::zeroed();
+        face.plane_num = 1;
+        face.first_edge = 2;
+        face.num_edges = 4;
+        face.tex_info = 1;
+        let builder = MapBuilder::box_room().lump(LumpIndex::Faces, &[face][..]);
+        assert!(matches!(
+            BSP::from_bytes(&builder.build()),
+            Err(Error::InvalidIndex {
+                lump: LumpIndex::SurfEdges,
+                index: 4,
+            })
+        ));
+    }
 }
@@ -0,0 +1,463 @@
+//! Binary cache of fully processed maps.
+//!
+//! The cache stores every field of `BSP` in native byte order and is keyed by a hash
+//! over the source file, the applied lump files and the load options.
+//! Caches written by a different format version, for a different key or on a machine
+//! with a different byte order are treated as stale.
+
+use super::*;
+
+use std::fs;
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dataview::{Pod, PodMethods};
+
+const CACHE_MAGIC: u32 = 0x43505342; // 'BSPC'
+
+/// Version of the cache format, has to be bumped whenever `BSP` or the format changes.
+pub const CACHE_VERSION: u32 = 4;
+
+/// 64-bit FNV-1a, used to key the cache with a hash that is stable across builds.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Computes the key of a map loaded from `bytes` with the given lump files and options.
+pub(crate) fn cache_key(bytes: &[u8], lump_files: &[LumpFile], options: &LoadOptions) -> u64 {
+    let mut hash = Fnv64::new();
+    hash.write(&(bytes.len() as u64).to_le_bytes());
+    hash.write(bytes);
+    for file in lump_files.iter() {
+        hash.write(&(file.bytes().len() as u64).to_le_bytes());
+        hash.write(file.bytes());
+    }
+    hash.write(&[
+        options.lump_layout as u8,
+        options.lump_overrides as u8,
+        options.lenient as u8,
+        options.profile as u8,
+        options.map_crc as u8,
+        options.max_unpacked_size.is_some() as u8,
+    ]);
+    hash.write(&options.max_unpacked_size.unwrap_or(0).to_le_bytes());
+    hash.0
+}
+
+/// Writes the cache next to its final location first so readers never observe a partial file.
+/// The temporary name is unique per process and call, concurrent writers never share a file.
+pub(crate) fn write_file(map: &BSP, key: u64, path: &Path) -> Result<()> {
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+
+    let result = fs::write(&tmp, write(map, key)).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    result?;
+    Ok(())
+}
+
+pub(crate) fn write(map: &BSP, key: u64) -> Vec<u8> {
+    let mut w = CacheWriter(Vec::new());
+    w.u32(CACHE_MAGIC);
+    w.u32(CACHE_VERSION);
+    w.u64(key);
+
+    w.pod(&map.header);
+    w.u32(map.map_crc.is_some() as u32);
+    w.u32(map.map_crc.unwrap_or(0));
+    w.pods(&map.vertexes);
+    w.pods(&map.planes);
+    w.pods(&map.edges);
+    w.pods(&map.surf_edges);
+    w.pods(&map.leaves);
+    w.pods(&map.leaf_ambient_lighting);
+    w.pods(&map.nodes);
+    w.pods(&map.faces);
+    w.pods(&map.tex_info);
+    w.pods(&map.brushes);
+    w.pods(&map.brush_sides);
+    w.pods(&map.leaf_faces);
+    w.pods(&map.leaf_brushes);
+    w.pods(&map.models);
+
+    w.u64(map.polys.len() as u64);
+    for poly in map.polys.iter() {
+        w.pod(&poly.verts);
+        w.u64(poly.vert_num as u64);
+        w.plane(&poly.plane);
+        for plane in poly.edge_planes.iter() {
+            w.plane(plane);
+        }
+        w.pod(&poly.vec_2d);
+        w.pod(&poly.skip);
+    }
+
+    w.u64(map.face_polys.len() as u64);
+    for poly in map.face_polys.iter() {
+        w.u32(poly.unwrap_or(u32::MAX));
+    }
+
+    w.u64(map.game_lumps.len() as u64);
+    for game_lump in map.game_lumps.iter() {
+        w.pod(&game_lump.id);
+        w.pod(&game_lump.flags);
+        w.pod(&game_lump.version);
+        w.bytes(&game_lump.data);
+    }
+
+    w.u64(map.lump_overrides.len() as u64);
+    for lump_override in map.lump_overrides.iter() {
+        w.u64(lump_override.lump_id as u64);
+        w.pod(&lump_override.version);
+        w.pod(&lump_override.map_revision);
+        match &lump_override.path {
+            Some(path) => {
+                w.u32(1);
+                w.bytes(path.to_string_lossy().as_bytes());
+            }
+            None => w.u32(0),
+        }
+    }
+
+    w.u64(map.validation.invalid_references.len() as u64);
+    for reference in map.validation.invalid_references.iter() {
+        w.u32(reference.lump as u32);
+        w.u64(reference.element as u64);
+        w.u32(reference.target as u32);
+        w.pod(&reference.index);
+    }
+
+    for raw_lump in map.raw_lumps.iter() {
+        w.bytes(raw_lump);
+    }
+
+    w.0
+}
+
+/// Reads a cached map, returns `None` if the cache is stale or corrupt.
+pub(crate) fn read(data: &[u8], key: u64) -> Option<BSP> {
+    let mut r = CacheReader(data);
+    if r.u32()? != CACHE_MAGIC || r.u32()? != CACHE_VERSION || r.u64()? != key {
+        return None;
+    }
+
+    let header: dheader_t = r.pod()?;
+    let map_crc = match (r.u32()?, r.u32()?) {
+        (0, _) => None,
+        (1, crc) => Some(crc),
+        _ => return None,
+    };
+    let vertexes = r.pods()?;
+    let planes = r.pods()?;
+    let edges = r.pods()?;
+    let surf_edges = r.pods()?;
+    let leaves = r.pods()?;
+    let leaf_ambient_lighting = r.pods()?;
+    let nodes = r.pods()?;
+    let faces = r.pods()?;
+    let tex_info = r.pods()?;
+    let brushes = r.pods()?;
+    let brush_sides = r.pods()?;
+    let leaf_faces = r.pods()?;
+    let leaf_brushes = r.pods()?;
+    let models = r.pods()?;
+
+    let mut polys = Vec::new();
+    for _ in 0..r.len(1)? {
+        let verts = r.pod()?;
+        let vert_num = r.u64()? as usize;
+        let plane = r.plane()?;
+        let mut edge_planes = [Plane::new(); MAX_SURFINFO_VERTS];
+        for edge_plane in edge_planes.iter_mut() {
+            *edge_plane = r.plane()?;
+        }
+        if vert_num > MAX_SURFINFO_VERTS {
+            return None;
+        }
+        polys.push(Polygon {
+            verts,
+            vert_num,
+            plane,
+            edge_planes,
+            vec_2d: r.pod()?,
+            skip: r.pod()?,
+        });
+    }
+
+    let mut face_polys = Vec::new();
+    for _ in 0..r.len(4)? {
+        face_polys.push(match r.u32()? {
+            u32::MAX => None,
+            poly => Some(poly),
+        });
+    }
+
+    let mut game_lumps = Vec::new();
+    for _ in 0..r.len(1)? {
+        game_lumps.push(GameLump {
+            id: r.pod()?,
+            flags: r.pod()?,
+            version: r.pod()?,
+            data: r.bytes()?.to_vec(),
+        });
+    }
+
+    let mut lump_overrides = Vec::new();
+    for _ in 0..r.len(1)? {
+        lump_overrides.push(LumpOverride {
+            lump_id: r.u64()? as usize,
+            version: r.pod()?,
+            map_revision: r.pod()?,
+            path: match r.u32()? {
+                0 => None,
+                _ => Some(PathBuf::from(String::from_utf8_lossy(r.bytes()?).as_ref())),
+            },
+        });
+    }
+
+    let mut validation = ValidationReport::default();
+    for _ in 0..r.len(1)? {
+        validation.invalid_references.push(InvalidReference {
+            lump: LumpIndex::from_index(r.u32()? as usize)?,
+            element: r.u64()? as usize,
+            target: LumpIndex::from_index(r.u32()? as usize)?,
+            index: r.pod()?,
+        });
+    }
+
+    let raw_lumps = LumpIndex::ALL
+        .iter()
+        .map(|_| r.bytes().map(<[u8]>::to_vec))
+        .collect::<Option<Vec<_>>>()?;
+
+    if !r.0.is_empty() {
+        return None;
+    }
+
+    Some(BSP {
+        vertexes,
+        planes,
+        edges,
+        surf_edges,
+        leaves,
+        leaf_ambient_lighting,
+        nodes,
+        faces,
+        tex_info,
+        brushes,
+        brush_sides,
+        leaf_faces,
+        leaf_brushes,
+        models,
+        polys,
+        face_polys,
+        game_lumps,
+        lump_overrides,
+        validation,
+        header,
+        map_crc,
+        raw_lumps,
+    })
+}
+
+struct CacheWriter(Vec<u8>);
+
+impl CacheWriter {
+    fn u32(&mut self, value: u32) {
+        self.pod(&value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.pod(&value);
+    }
+
+    fn pod<T: Pod + ?Sized>(&mut self, value: &T) {
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn pods<T: Pod>(&mut self, values: &[T]) {
+        self.u64(values.len() as u64);
+        self.pod(values);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.pods(bytes);
+    }
+
+    fn plane(&mut self, plane: &Plane) {
+        self.pod(&plane.origin);
+        self.pod(&plane.distance);
+    }
+}
+
+struct CacheReader<'a>(&'a [u8]);
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.pod()
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.pod()
+    }
+
+    /// Reads an element count and checks it against the remaining data to avoid huge allocations.
+    fn len(&mut self, min_element_size: usize) -> Option<usize> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        if len.checked_mul(min_element_size)? > self.0.len() {
+            return None;
+        }
+        Some(len)
+    }
+
+    fn pod<T: Pod>(&mut self) -> Option<T> {
+        let mut value = T::zeroed();
+        value
+            .as_bytes_mut()
+            .copy_from_slice(self.take(size_of::<T>())?);
+        Some(value)
+    }
+
+    fn pods<T: Pod + Clone>(&mut self) -> Option<Vec<T>> {
+        let len = self.len(size_of::<T>())?;
+        let mut values = vec![T::zeroed(); len];
+        values
+            .as_bytes_mut()
+            .copy_from_slice(self.take(len * size_of::<T>())?);
+        Some(values)
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.len(1)?;
+        self.take(len)
+    }
+
+    fn plane(&mut self) -> Option<Plane> {
+        Some(Plane {
+            origin: self.pod()?,
+            distance: self.pod()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        let cache = write(&map, 7);
+        let cached = read(&cache, 7).unwrap();
+
+        assert_eq!(cached.planes.as_bytes(), map.planes.as_bytes());
+        assert_eq!(cached.nodes.as_bytes(), map.nodes.as_bytes());
+        assert_eq!(cached.brush_sides.as_bytes(), map.brush_sides.as_bytes());
+        assert_eq!(cached.models.as_bytes(), map.models.as_bytes());
+        assert_eq!(cached.polys.len(), 1);
+        assert_eq!(cached.polys[0].vert_num, map.polys[0].vert_num);
+        assert_eq!(
+            cached.polys[0].edge_planes[3].origin,
+            map.polys[0].edge_planes[3].origin
+        );
+        assert_eq!(cached.header.as_bytes(), map.header.as_bytes());
+        assert_eq!(cached.map_crc(), None);
+        assert_eq!(
+            cached.raw_lump(LumpIndex::Entities),
+            map.raw_lump(LumpIndex::Entities)
+        );
+        assert_eq!(write(&cached, 7), cache);
+    }
+
+    #[test]
+    fn stale() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        let cache = write(&map, 7);
+        assert!(read(&cache, 8).is_none());
+        assert!(read(&cache[..cache.len() - 1], 7).is_none());
+
+        let mut outdated = cache.clone();
+        outdated[4..8].copy_from_slice(&(CACHE_VERSION + 1).to_ne_bytes());
+        assert!(read(&outdated, 7).is_none());
+    }
+
+    #[test]
+    fn cache_key_options() {
+        let data = MapBuilder::box_room().build();
+        let key = cache_key(&data, &[], &LoadOptions::default());
+        assert_eq!(key, cache_key(&data, &[], &LoadOptions::default()));
+        assert_ne!(
+            key,
+            cache_key(&data, &[], &LoadOptions::new().lenient(true))
+        );
+        assert_ne!(
+            key,
+            cache_key(&data, &[], &LoadOptions::new().map_crc(true))
+        );
+        assert_ne!(
+            key,
+            cache_key(&data, &[], &LoadOptions::new().max_unpacked_size(0))
+        );
+        assert_ne!(key, cache_key(&data[1..], &[], &LoadOptions::default()));
+    }
+
+    #[test]
+    fn open_cached() {
+        let dir = std::env::temp_dir().join(format!("bsp_rs_{}_open_cached", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let map_path = dir.join("box_room.bsp");
+        let cache_path = dir.join("box_room.bspc");
+        std::fs::write(&map_path, MapBuilder::box_room().build()).unwrap();
+        std::fs::remove_file(&cache_path).ok();
+
+        let map = BSP::open_cached(&map_path, &cache_path).unwrap();
+        assert_eq!(map.polys.len(), 1);
+        assert!(cache_path.exists());
+
+        // a stale cache is replaced
+        let mut builder = MapBuilder::box_room();
+        builder.map_revision = 2;
+        std::fs::write(&map_path, builder.build()).unwrap();
+        let map = BSP::open_cached(&map_path, &cache_path).unwrap();
+        assert_eq!(map.header.map_revision, 2);
+
+        let cache = std::fs::read(&cache_path).unwrap();
+        let key = cache_key(&builder.build(), &[], &LoadOptions::default());
+        assert!(read(&cache, key).is_some());
+        // no temporary files are left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
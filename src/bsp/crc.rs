@@ -0,0 +1,149 @@
+//! The map crc as computed by the engine (`CRC_MapFile`).
+//!
+//! The crc is a regular CRC32 over the contents of all lumps in header order as they are stored
+//! in the file, the entity lump is skipped so servers can change entities without changing the crc.
+
+use super::*;
+
+use std::io::ErrorKind;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// Computes the map crc over the lumps described by the header.
+pub(crate) fn crc_lumps<R: Read + Seek>(reader: &mut R, header: &dheader_t) -> Result<u32> {
+    let mut crc = Crc32::new();
+    let mut buf = vec![0u8; 0x10000];
+    for (index, lump) in header.iter_lumps() {
+        if index == LumpIndex::Entities || lump.filelen <= 0 {
+            continue;
+        }
+
+        let out_of_bounds = || Error::LumpOutOfBounds {
+            lump: index,
+            offset: lump.fileofs as i64,
+            len: lump.filelen as i64,
+        };
+        if lump.fileofs < 0 {
+            return Err(out_of_bounds());
+        }
+
+        reader.seek(SeekFrom::Start(lump.fileofs as u64))?;
+        let mut remaining = lump.filelen as usize;
+        while remaining > 0 {
+            let chunk = &mut buf[..remaining.min(0x10000)];
+            reader.read_exact(chunk).map_err(|err| {
+                if err.kind() == ErrorKind::UnexpectedEof {
+                    out_of_bounds()
+                } else {
+                    Error::Io(err)
+                }
+            })?;
+            crc.update(chunk);
+            remaining -= chunk.len();
+        }
+    }
+    Ok(crc.finish())
+}
+
+/// Computes the map crc of a bsp file without parsing its lumps.
+pub fn crc_map_file<R: Read + Seek>(mut reader: R) -> Result<u32> {
+    let (header, _) = read_header(&mut reader, LumpLayout::Detect)?;
+    crc_lumps(&mut reader, &header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::*;
+
+    #[test]
+    fn crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn map_crc() {
+        let data = MapBuilder::box_room().build();
+        let options = LoadOptions::new().map_crc(true);
+        let map = BSP::from_bytes_with(&data, &options).unwrap();
+
+        let mut expected = Crc32::new();
+        for (index, _) in map.lumps() {
+            if index != LumpIndex::Entities {
+                expected.update(map.raw_lump(index));
+            }
+        }
+        let expected = expected.finish();
+        assert_eq!(map.map_crc(), Some(expected));
+        assert_eq!(crc_map_file(Cursor::new(&data)).unwrap(), expected);
+        assert_eq!(BSP::from_bytes(&data).unwrap().map_crc(), None);
+
+        // entities are not part of the crc
+        let builder = MapBuilder::box_room().lump(LumpIndex::Entities, b"{\n}\n\0".as_ref());
+        assert_eq!(
+            crc_map_file(Cursor::new(builder.build())).unwrap(),
+            expected
+        );
+
+        // compressed lumps are hashed as they are stored
+        let builder = MapBuilder::box_room().compressed(LumpIndex::Planes);
+        assert_ne!(
+            crc_map_file(Cursor::new(builder.build())).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn map_crc_truncated() {
+        let mut data = MapBuilder::box_room().build();
+        data.truncate(data.len() - 4);
+        assert!(matches!(
+            crc_map_file(Cursor::new(data)),
+            Err(Error::LumpOutOfBounds {
+                lump: LumpIndex::BrushSides,
+                ..
+            })
+        ));
+    }
+}
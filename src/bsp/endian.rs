@@ -0,0 +1,240 @@
+//! Byte swapping of big-endian (console) bsp files into native endianness.
+
+use super::native::*;
+
+/// Swaps the byte order of every field of a value in place.
+pub trait SwapBytes {
+    fn swap_bytes(&mut self);
+}
+
+macro_rules! impl_swap_bytes_int {
+    ($($ty:ty),*) => {
+        $(impl SwapBytes for $ty {
+            fn swap_bytes(&mut self) {
+                *self = <$ty>::swap_bytes(*self);
+            }
+        })*
+    };
+}
+
+impl_swap_bytes_int!(i8, u8, i16, u16, i32, u32);
+
+impl SwapBytes for f32 {
+    fn swap_bytes(&mut self) {
+        *self = f32::from_bits(self.to_bits().swap_bytes());
+    }
+}
+
+impl<T: SwapBytes, const N: usize> SwapBytes for [T; N] {
+    fn swap_bytes(&mut self) {
+        self.iter_mut().for_each(SwapBytes::swap_bytes);
+    }
+}
+
+/// Implements `SwapBytes` by swapping the listed fields, fields are accessed unaligned
+/// so the macro can be used on packed structs as well.
+macro_rules! impl_swap_bytes {
+    ($ty:ty { $($field:ident),* }) => {
+        impl SwapBytes for $ty {
+            fn swap_bytes(&mut self) {
+                $(unsafe {
+                    let field = core::ptr::addr_of_mut!(self.$field);
+                    let mut value = field.read_unaligned();
+                    SwapBytes::swap_bytes(&mut value);
+                    field.write_unaligned(value);
+                })*
+            }
+        }
+    };
+}
+
+impl_swap_bytes!(dheader_t {
+    ident,
+    version,
+    lumps,
+    map_revision
+});
+impl_swap_bytes!(lump_t {
+    fileofs,
+    filelen,
+    version,
+    four_cc
+});
+impl_swap_bytes!(mvertex_t { position });
+impl_swap_bytes!(dplane_t {
+    normal,
+    distance,
+    typ
+});
+impl_swap_bytes!(cplane_t {
+    normal,
+    distance,
+    typ,
+    sign_bits
+});
+impl_swap_bytes!(dedge_t { v });
+impl_swap_bytes!(dleaf_t {
+    contents,
+    cluster,
+    area_flags,
+    mins,
+    maxs,
+    first_leaf_face,
+    num_leaf_faces,
+    first_leaf_brush,
+    num_leaf_brushes,
+    feaf_water_data_id
+});
+impl_swap_bytes!(dleaf_v0_t {
+    contents,
+    cluster,
+    area_flags,
+    mins,
+    maxs,
+    first_leaf_face,
+    num_leaf_faces,
+    first_leaf_brush,
+    num_leaf_brushes,
+    feaf_water_data_id,
+    ambient_lighting
+});
+impl_swap_bytes!(ColorRGBExp32 { r, g, b, exponent });
+impl_swap_bytes!(CompressedLightCube { color });
+impl_swap_bytes!(dnode_t {
+    plane_num,
+    children,
+    mins,
+    maxs,
+    first_face,
+    num_faces,
+    area
+});
+impl_swap_bytes!(snode_t {
+    plane_num,
+    plane_idx,
+    children,
+    leaf_children_idx,
+    node_children_idx,
+    mins,
+    maxs,
+    first_face,
+    num_faces,
+    area
+});
+impl_swap_bytes!(dface_t {
+    plane_num,
+    side,
+    on_node,
+    first_edge,
+    num_edges,
+    tex_info,
+    disp_info,
+    surface_fog_volume_id,
+    styles,
+    light_ofs,
+    area,
+    lightmap_texture_mins_in_luxels,
+    lightmap_texture_size_in_luxels,
+    orig_face,
+    num_prims,
+    first_prim_id,
+    smoothing_groups
+});
+impl_swap_bytes!(dface_v17_t {
+    avg_light_color,
+    plane_num,
+    side,
+    on_node,
+    first_edge,
+    num_edges,
+    tex_info,
+    disp_info,
+    surface_fog_volume_id,
+    styles,
+    day_styles,
+    night_styles,
+    light_ofs,
+    area,
+    lightmap_texture_mins_in_luxels,
+    lightmap_texture_size_in_luxels,
+    orig_face,
+    smoothing_groups
+});
+impl_swap_bytes!(texinfo_t {
+    texture_vecs,
+    lightmap_vecs,
+    flags,
+    tex_data
+});
+impl_swap_bytes!(dbrush_t {
+    first_side,
+    num_sides,
+    contents
+});
+impl_swap_bytes!(dbrushside_t {
+    plane_num,
+    tex_info,
+    disp_info,
+    bevel,
+    thin
+});
+impl_swap_bytes!(dmodel_t {
+    mins,
+    maxs,
+    origin,
+    head_node,
+    first_face,
+    num_faces
+});
+impl_swap_bytes!(lumpfileheader_t {
+    lump_offset,
+    lump_id,
+    lump_version,
+    lump_length,
+    map_revision
+});
+impl_swap_bytes!(lzma_header_t {
+    id,
+    actual_size,
+    lzma_size,
+    properties
+});
+impl_swap_bytes!(dgamelumpheader_t { lump_count });
+impl_swap_bytes!(dgamelump_t {
+    id,
+    flags,
+    version,
+    fileofs,
+    filelen
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_packed() {
+        let mut face: dface_t = dataview::PodMethods::zeroed();
+        face.first_edge = 0x01020304;
+        face.area = 1f32;
+        face.styles = [1, 2, 3, 4];
+        face.swap_bytes();
+        assert_eq!({ face.first_edge }, 0x04030201);
+        assert_eq!({ face.area }.to_bits(), 1f32.to_bits().swap_bytes());
+        assert_eq!(face.styles, [1, 2, 3, 4]);
+        face.swap_bytes();
+        assert_eq!({ face.area }, 1f32);
+    }
+
+    #[test]
+    fn swap_header() {
+        let mut header = dheader_t {
+            ident: HEADER_MAGIC,
+            ..Default::default()
+        };
+        header.lumps[3].filelen = 0x10;
+        header.swap_bytes();
+        assert_eq!(header.ident, HEADER_MAGIC_BE);
+        assert_eq!(header.lumps[3].filelen, 0x10000000);
+    }
+}
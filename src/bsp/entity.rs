@@ -0,0 +1,610 @@
+//! Parser for the entity lump.
+//!
+//! The lump is a list of `{ "key" "value" ... }` blocks terminated by a NUL byte.
+//! Keys can repeat (every entity output is its own key), so the properties are kept in lump order.
+//!
+//! `Entities` indexes a parsed lump by classname and targetname for gameplay queries.
+
+use super::math;
+use super::native::LumpIndex;
+use crate::error::*;
+
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A single entity of the entity lump.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entity {
+    /// Key/value pairs in lump order including duplicate keys.
+    pub properties: Vec<(String, String)>,
+}
+
+impl Entity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the first value of the key, keys are case insensitive like in the engine.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all values of the key in lump order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.properties
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets the value of the key, other values of the same key are removed.
+    ///
+    /// The first occurrence keeps its position, new keys are appended.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut found = false;
+        self.properties.retain_mut(|(k, v)| {
+            if !k.eq_ignore_ascii_case(key) {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            *v = value.to_string();
+            true
+        });
+        if !found {
+            self.add(key, value);
+        }
+    }
+
+    /// Appends a key/value pair, existing values of the key are kept (entity outputs).
+    pub fn add(&mut self, key: &str, value: &str) {
+        self.properties.push((key.to_string(), value.to_string()));
+    }
+
+    /// Removes all values of the key, returns false if there was none.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.properties.len();
+        self.properties
+            .retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.properties.len() != len
+    }
+
+    /// Iterates over all key/value pairs in lump order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.properties
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Parses the value of the key as a number.
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key)?.trim().parse().ok()
+    }
+
+    /// Parses the value of the key as a vector of three space separated numbers (`"x y z"`).
+    pub fn get_vector(&self, key: &str) -> Option<[f32; 3]> {
+        let mut parts = self.get(key)?.split_whitespace().map(str::parse::<f32>);
+        let mut out = [0f32; 3];
+        for value in out.iter_mut() {
+            *value = parts.next()?.ok()?;
+        }
+        Some(out)
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn targetname(&self) -> Option<&str> {
+        self.get("targetname")
+    }
+
+    pub fn origin(&self) -> Option<[f32; 3]> {
+        self.get_vector("origin")
+    }
+
+    /// Returns pitch, yaw and roll in degrees.
+    ///
+    /// Falls back to the yaw only `angle` key, which uses -1 and -2 for straight up and down.
+    pub fn angles(&self) -> Option<[f32; 3]> {
+        if let Some(angles) = self.get_vector("angles") {
+            return Some(angles);
+        }
+        let angle = self.get_f32("angle")?;
+        Some(if angle == -1f32 {
+            [-90f32, 0f32, 0f32]
+        } else if angle == -2f32 {
+            [90f32, 0f32, 0f32]
+        } else {
+            [0f32, angle, 0f32]
+        })
+    }
+
+    pub fn model(&self) -> Option<EntityModel> {
+        let model = self.get("model")?;
+        Some(match model.strip_prefix('*') {
+            Some(index) => EntityModel::Brush(index.trim().parse().ok()?),
+            None => EntityModel::File(model.to_string()),
+        })
+    }
+}
+
+/// The `model` of an entity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntityModel {
+    /// `*N`, the index of a brush model in the models lump (0 is the world).
+    Brush(usize),
+    /// The path of a studio model or sprite.
+    File(String),
+}
+
+/// The entities of a map indexed by classname and targetname.
+///
+/// Names are matched case insensitively like in the engine.
+#[derive(Clone, Debug, Default)]
+pub struct Entities {
+    entities: Vec<Entity>,
+    by_class: HashMap<String, Vec<usize>>,
+    by_targetname: HashMap<String, Vec<usize>>,
+}
+
+impl Entities {
+    pub fn new(entities: Vec<Entity>) -> Self {
+        let mut by_class: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_targetname: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entity) in entities.iter().enumerate() {
+            if let Some(class) = entity.classname() {
+                by_class
+                    .entry(class.to_ascii_lowercase())
+                    .or_default()
+                    .push(i);
+            }
+            if let Some(name) = entity.targetname() {
+                by_targetname
+                    .entry(name.to_ascii_lowercase())
+                    .or_default()
+                    .push(i);
+            }
+        }
+
+        Self {
+            entities,
+            by_class,
+            by_targetname,
+        }
+    }
+
+    /// Parses the contents of an entity lump.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        Ok(Self::new(parse_entities(data)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Entity> {
+        self.entities.get(index)
+    }
+
+    /// Iterates over all entities in lump order.
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.iter()
+    }
+
+    /// The entity at index 0, every valid map starts with it.
+    pub fn worldspawn(&self) -> Option<&Entity> {
+        self.entities
+            .first()
+            .filter(|entity| entity.classname() == Some("worldspawn"))
+    }
+
+    pub fn entities_by_class<'a>(&'a self, class: &str) -> impl Iterator<Item = &'a Entity> + 'a {
+        self.lookup(&self.by_class, class)
+    }
+
+    /// Returns all entities with the targetname, several entities can share one.
+    pub fn entities_by_targetname<'a>(
+        &'a self,
+        name: &str,
+    ) -> impl Iterator<Item = &'a Entity> + 'a {
+        self.lookup(&self.by_targetname, name)
+    }
+
+    /// Returns the first entity with the targetname.
+    pub fn entity_by_targetname(&self, name: &str) -> Option<&Entity> {
+        self.entities_by_targetname(name).next()
+    }
+
+    /// Returns all entities whose origin lies within `radius` units of the point.
+    pub fn entities_within(
+        &self,
+        point: [f32; 3],
+        radius: f32,
+    ) -> impl Iterator<Item = &Entity> + '_ {
+        self.entities.iter().filter(move |entity| {
+            entity.origin().is_some_and(|origin| {
+                let delta = [
+                    origin[0] - point[0],
+                    origin[1] - point[1],
+                    origin[2] - point[2],
+                ];
+                math::dot_product(delta, delta) <= radius * radius
+            })
+        })
+    }
+
+    /// Returns the indices of all entities with the classname.
+    pub fn class_indices(&self, class: &str) -> &[usize] {
+        Self::indices(&self.by_class, class)
+    }
+
+    /// Returns the indices of all entities with the targetname.
+    pub fn targetname_indices(&self, name: &str) -> &[usize] {
+        Self::indices(&self.by_targetname, name)
+    }
+
+    fn indices<'a>(index: &'a HashMap<String, Vec<usize>>, name: &str) -> &'a [usize] {
+        index
+            .get(&name.to_ascii_lowercase())
+            .map_or(&[][..], Vec::as_slice)
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        index: &'a HashMap<String, Vec<usize>>,
+        name: &str,
+    ) -> impl Iterator<Item = &'a Entity> + 'a {
+        Self::indices(index, name)
+            .iter()
+            .map(move |&i| &self.entities[i])
+    }
+
+    pub fn as_slice(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn into_inner(self) -> Vec<Entity> {
+        self.entities
+    }
+}
+
+impl From<Vec<Entity>> for Entities {
+    fn from(entities: Vec<Entity>) -> Self {
+        Self::new(entities)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    String(String),
+}
+
+struct Tokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        // everything after the terminating NUL is padding
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Self {
+            data: &data[..end],
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(b'/') if self.data.get(self.pos + 1) == Some(&b'/') => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Token>> {
+        self.skip_whitespace();
+        let token = match self.peek() {
+            None => return Ok(None),
+            Some(b'{') => {
+                self.pos += 1;
+                Token::Open
+            }
+            Some(b'}') => {
+                self.pos += 1;
+                Token::Close
+            }
+            Some(b'"') => {
+                self.pos += 1;
+                Token::String(self.quoted()?)
+            }
+            Some(_) => Token::String(self.bare()),
+        };
+        Ok(Some(token))
+    }
+
+    /// Reads a quoted string up to the next quote, the engine knows no escape sequences.
+    fn quoted(&mut self) -> Result<String> {
+        let start = self.pos;
+        let len = self.data[start..]
+            .iter()
+            .position(|&b| b == b'"')
+            .ok_or_else(|| invalid("unterminated string"))?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&self.data[start..start + len]).into_owned())
+    }
+
+    /// Reads an unquoted token, the engine accepts them for keys and values without spaces.
+    fn bare(&mut self) -> String {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if b.is_ascii_whitespace() || b == b'{' || b == b'}' || b == b'"' {
+                break;
+            }
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.data[start..self.pos]).into_owned()
+    }
+}
+
+fn invalid(reason: &'static str) -> Error {
+    Error::InvalidLump {
+        lump: LumpIndex::Entities,
+        reason,
+    }
+}
+
+/// Parses the contents of an entity lump.
+pub fn parse_entities(data: &[u8]) -> Result<Vec<Entity>> {
+    let mut tokens = Tokenizer::new(data);
+    let mut entities = Vec::new();
+
+    while let Some(token) = tokens.next()? {
+        if token != Token::Open {
+            return Err(invalid("expected the start of an entity"));
+        }
+
+        let mut entity = Entity::new();
+        loop {
+            let key = match tokens.next()? {
+                Some(Token::Close) => break,
+                Some(Token::String(key)) => key,
+                Some(Token::Open) => return Err(invalid("nested entity")),
+                None => return Err(invalid("unterminated entity")),
+            };
+            let value = match tokens.next()? {
+                Some(Token::String(value)) => value,
+                _ => return Err(invalid("key without a value")),
+            };
+            entity.properties.push((key, value));
+        }
+        entities.push(entity);
+    }
+
+    Ok(entities)
+}
+
+/// Serializes entities into the contents of an entity lump including the terminating NUL.
+///
+/// Keys and values are written as they are, the format can not represent quotes or NULs in them.
+pub fn write_entities(entities: &[Entity]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for entity in entities.iter() {
+        out.extend_from_slice(b"{\n");
+        for (key, value) in entity.iter() {
+            write_string(&mut out, key)?;
+            out.push(b' ');
+            write_string(&mut out, value)?;
+            out.push(b'\n');
+        }
+        out.extend_from_slice(b"}\n");
+    }
+    out.push(0);
+    Ok(out)
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) -> Result<()> {
+    if value.contains(['"', '\0']) {
+        return Err(invalid("keys and values can not contain quotes or NULs"));
+    }
+    out.push(b'"');
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'"');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let data = b"{\n\"classname\" \"worldspawn\"\n\"skyname\" \"sky_dust\"\n}\n\
+            {\n\"classname\" \"logic_relay\"\n\"OnTrigger\" \"door,Open,,0,-1\"\n\
+            \"OnTrigger\" \"light,TurnOn,,1,-1\"\n}\n\0\0\0";
+        let entities = parse_entities(data).unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].get("classname"), Some("worldspawn"));
+        assert_eq!(entities[0].get("SkyName"), Some("sky_dust"));
+        assert_eq!(entities[1].properties.len(), 3);
+        assert_eq!(
+            entities[1].get_all("ontrigger").collect::<Vec<_>>(),
+            vec!["door,Open,,0,-1", "light,TurnOn,,1,-1"]
+        );
+        assert_eq!(
+            entities[1].iter().next(),
+            Some(("classname", "logic_relay"))
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let data = b"{ \"classname\" \"func_door\" \"model\" \"*3\" \"angle\" \"-1\" }\
+            { \"classname\" \"prop_static\" \"model\" \"models/box.mdl\" \"origin\" \"1 -2.5 3e1\"\
+            \"angles\" \"0 90 0\" \"angle\" \"45\" }\
+            { \"origin\" \"1 2\" \"model\" \"*x\" \"angle\" \"yaw\" }";
+        let entities = parse_entities(data).unwrap();
+
+        assert_eq!(entities[0].model(), Some(EntityModel::Brush(3)));
+        assert_eq!(entities[0].angles(), Some([-90f32, 0f32, 0f32]));
+        assert_eq!(entities[0].origin(), None);
+
+        assert_eq!(
+            entities[1].model(),
+            Some(EntityModel::File("models/box.mdl".to_string()))
+        );
+        assert_eq!(entities[1].origin(), Some([1f32, -2.5f32, 30f32]));
+        assert_eq!(entities[1].angles(), Some([0f32, 90f32, 0f32]));
+        assert_eq!(entities[1].get_f32("angle"), Some(45f32));
+
+        assert_eq!(entities[2].classname(), None);
+        assert_eq!(entities[2].origin(), None);
+        assert_eq!(entities[2].model(), None);
+        assert_eq!(entities[2].angles(), None);
+    }
+
+    #[test]
+    fn queries() {
+        let data = b"{ \"classname\" \"worldspawn\" }\
+            { \"classname\" \"info_player_terrorist\" \"origin\" \"0 0 0\" }\
+            { \"classname\" \"Info_Player_Terrorist\" \"origin\" \"100 0 0\" }\
+            { \"classname\" \"info_player_counterterrorist\" \"origin\" \"0 300 0\" }\
+            { \"classname\" \"func_door\" \"targetname\" \"door\" }\
+            { \"classname\" \"func_door\" \"targetname\" \"Door\" }";
+        let entities = Entities::parse(data).unwrap();
+        assert_eq!(entities.len(), 6);
+        assert!(entities.worldspawn().is_some());
+
+        assert_eq!(
+            entities.entities_by_class("info_player_terrorist").count(),
+            2
+        );
+        assert_eq!(entities.entities_by_class("FUNC_DOOR").count(), 2);
+        assert_eq!(entities.entities_by_class("light").count(), 0);
+
+        assert_eq!(entities.entities_by_targetname("door").count(), 2);
+        let door = entities.entity_by_targetname("DOOR").unwrap();
+        assert_eq!(door.targetname(), Some("door"));
+        assert!(entities.entity_by_targetname("window").is_none());
+
+        let near = entities
+            .entities_within([0f32, 0f32, 0f32], 100f32)
+            .map(|entity| entity.origin().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(near, vec![[0f32; 3], [100f32, 0f32, 0f32]]);
+
+        let empty = Entities::default();
+        assert!(empty.is_empty());
+        assert!(empty.worldspawn().is_none());
+    }
+
+    #[test]
+    fn strings() {
+        // backslashes are no escapes, a value can end with one
+        let data = b"// comment\n{ \"path\" \"c:\\maps\\\" \"model\" \"models\\props\\box.mdl\"\n\
+            \"empty\" \"\" angle 90 \"message\" \"a\\\\b\" }";
+        let entities = parse_entities(data).unwrap();
+        let entity = &entities[0];
+        assert_eq!(entity.get("path"), Some("c:\\maps\\"));
+        assert_eq!(entity.get("model"), Some("models\\props\\box.mdl"));
+        assert_eq!(entity.get("empty"), Some(""));
+        assert_eq!(entity.get("angle"), Some("90"));
+        assert_eq!(entity.get("message"), Some("a\\\\b"));
+
+        // nothing after the terminating NUL is parsed
+        assert!(parse_entities(b"\0{ garbage").unwrap().is_empty());
+        assert!(parse_entities(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn edit() {
+        let mut entity = Entity::new();
+        entity.add("classname", "logic_relay");
+        entity.add("OnTrigger", "a,Open,,0,-1");
+        entity.add("OnTrigger", "b,Open,,0,-1");
+        entity.add("spawnflags", "0");
+
+        entity.set("ontrigger", "c,Close,,0,-1");
+        assert_eq!(
+            entity.properties[1],
+            ("OnTrigger".to_string(), "c,Close,,0,-1".to_string())
+        );
+        assert_eq!(entity.properties.len(), 3);
+
+        entity.set("targetname", "relay");
+        assert_eq!(entity.properties[3].0, "targetname");
+
+        assert!(entity.remove("SpawnFlags"));
+        assert!(!entity.remove("spawnflags"));
+        assert_eq!(entity.properties.len(), 3);
+    }
+
+    #[test]
+    fn write() {
+        let mut entity = Entity::new();
+        entity.add("classname", "info_target");
+        entity.add("model", "models\\props\\box.mdl");
+        entity.add("path", "c:\\maps\\\\x\\");
+        entity.add("OnUser1", "a\x1bOpen\x1b\x1b0\x1b-1");
+        let mut entities = vec![Entity::new(), entity];
+
+        let data = write_entities(&entities).unwrap();
+        assert!(data.starts_with(b"{\n}\n{\n\"classname\" \"info_target\"\n"));
+        assert!(data.ends_with(b"}\n\0"));
+        assert!(data.windows(22).any(|w| w == b"\"models\\props\\box.mdl\""));
+        assert!(data.windows(13).any(|w| w == b"\"c:\\maps\\\\x\\\""));
+        assert_eq!(parse_entities(&data).unwrap(), entities);
+
+        assert_eq!(write_entities(&[]).unwrap(), b"\0");
+
+        entities[1].add("message", "say \"hi\"");
+        assert!(write_entities(&entities).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        for data in [
+            &b"\"classname\" \"worldspawn\""[..],
+            b"{ \"classname\" \"worldspawn\"",
+            b"{ \"classname\" }",
+            b"{ \"classname\" \"worldspawn }",
+            b"{ { } }",
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                parse_entities(data),
+                Err(Error::InvalidLump {
+                    lump: LumpIndex::Entities,
+                    ..
+                })
+            ));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Synthetic maps for unit tests.
+//!
+//! The box room consists of a single solid 32x32x32 brush centered around the origin
+//! which sits in a tree with one node splitting the world at x=0.
+
+use super::endian::SwapBytes;
+use super::lmp::LumpFile;
+use super::lzma;
+use super::native::*;
+use super::options::LumpLayout;
+
+use std::mem::size_of;
+
+use dataview::{Pod, PodMethods};
+
+pub const BOX_ENTITIES: &str =
+    "{\n\"classname\" \"worldspawn\"\n}\n{\n\"classname\" \"info_player_start\"\n\"origin\" \"-64 0 0\"\n}\n\0";
+
+#[derive(Clone, Default)]
+pub struct FixtureLump {
+    pub version: i32,
+    pub four_cc: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct FixtureGameLump {
+    pub id: i32,
+    pub version: u16,
+    pub compressed: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct MapBuilder {
+    pub ident: i32,
+    pub version: i32,
+    pub map_revision: i32,
+    pub lump_layout: LumpLayout,
+    pub big_endian: bool,
+    pub lumps: Vec<FixtureLump>,
+    pub game_lumps: Vec<FixtureGameLump>,
+}
+
+impl MapBuilder {
+    pub fn new() -> Self {
+        Self {
+            ident: HEADER_MAGIC,
+            version: 20,
+            map_revision: 1,
+            lump_layout: LumpLayout::Standard,
+            big_endian: false,
+            lumps: vec![FixtureLump::default(); HEADER_LUMP_COUNT],
+            game_lumps: Vec::new(),
+        }
+    }
+
+    pub fn lump<T: Pod + Clone + SwapBytes>(mut self, index: LumpIndex, data: &[T]) -> Self {
+        let mut data = data.to_vec();
+        if self.big_endian {
+            data.iter_mut().for_each(SwapBytes::swap_bytes);
+        }
+        self.lumps[index as usize].data = data.as_bytes().to_vec();
+        self
+    }
+
+    pub fn lump_version(mut self, index: LumpIndex, version: i32) -> Self {
+        self.lumps[index as usize].version = version;
+        self
+    }
+
+    /// Compresses the lump with valve's lzma header, the uncompressed size is stored in the four_cc.
+    pub fn compressed(mut self, index: LumpIndex) -> Self {
+        let lump = &mut self.lumps[index as usize];
+        lump.four_cc = (lump.data.len() as u32).to_le_bytes();
+        lump.data = lzma::compress(&lump.data);
+        self
+    }
+
+    pub fn game_lump(mut self, id: i32, version: u16, compressed: bool, data: &[u8]) -> Self {
+        self.game_lumps.push(FixtureGameLump {
+            id,
+            version,
+            compressed,
+            data: data.to_vec(),
+        });
+        self
+    }
+
+    pub fn box_room() -> Self {
+        Self::new().with_box_room()
+    }
+
+    /// The box room in big-endian byte order, as found on consoles.
+    pub fn box_room_big_endian() -> Self {
+        let mut builder = Self::new();
+        builder.big_endian = true;
+        builder.with_box_room()
+    }
+
+    fn with_box_room(self) -> Self {
+        let plane = |normal: [f32; 3], distance: f32, typ: i32| dplane_t {
+            normal,
+            distance,
+            typ,
+        };
+        let planes = [
+            plane([1f32, 0f32, 0f32], 0f32, 0),
+            plane([1f32, 0f32, 0f32], 16f32, 0),
+            plane([-1f32, 0f32, 0f32], 16f32, 3),
+            plane([0f32, 1f32, 0f32], 16f32, 1),
+            plane([0f32, -1f32, 0f32], 16f32, 4),
+            plane([0f32, 0f32, 1f32], 16f32, 2),
+            plane([0f32, 0f32, -1f32], 16f32, 5),
+        ];
+
+        let nodes = [dnode_t {
+            plane_num: 0,
+            children: [-1, -2],
+            mins: [-16, -16, -16],
+            maxs: [16, 16, 16],
+            first_face: 0,
+            num_faces: 1,
+            area: 0,
+            pad0: [0; 2],
+        }];
+
+        let leaf = dleaf_t {
+            contents: 0,
+            cluster: 0,
+            area_flags: 0,
+            mins: [-16, -16, -16],
+            maxs: [16, 16, 16],
+            first_leaf_face: 0,
+            num_leaf_faces: 1,
+            first_leaf_brush: 0,
+            num_leaf_brushes: 1,
+            feaf_water_data_id: -1,
+            pad0: [0; 2],
+        };
+        let leaves = [leaf, leaf];
+
+        let brushes = [dbrush_t {
+            first_side: 0,
+            num_sides: 6,
+            contents: 0x1, // CONTENTS_SOLID
+        }];
+        let brush_sides: Vec<dbrushside_t> = (1..7)
+            .map(|plane_num| dbrushside_t {
+                plane_num,
+                tex_info: 1,
+                disp_info: -1,
+                bevel: 0,
+                thin: 0,
+            })
+            .collect();
+
+        let vertexes = [
+            mvertex_t {
+                position: [16f32, -16f32, -16f32],
+            },
+            mvertex_t {
+                position: [16f32, 16f32, -16f32],
+            },
+            mvertex_t {
+                position: [16f32, 16f32, 16f32],
+            },
+            mvertex_t {
+                position: [16f32, -16f32, 16f32],
+            },
+        ];
+        let edges = [
+            dedge_t { v: [0, 1] },
+            dedge_t { v: [1, 2] },
+            dedge_t { v: [2, 3] },
+            dedge_t { v: [3, 0] },
+        ];
+        let surf_edges = [0i32, 1, 2, 3];
+
+        let mut face: dface_t = dface_t::zeroed();
+        face.plane_num = 1;
+        face.first_edge = 0;
+        face.num_edges = 4;
+        face.tex_info = 1;
+        face.disp_info = -1;
+        let faces = [face];
+
+        let tex_info = [texinfo_t::zeroed(), texinfo_t::zeroed()];
+
+        let world = dmodel_t {
+            mins: [-16f32; 3],
+            maxs: [16f32; 3],
+            origin: [0f32; 3],
+            head_node: 0,
+            first_face: 0,
+            num_faces: 1,
+        };
+
+        self.lump(LumpIndex::Entities, BOX_ENTITIES.as_bytes())
+            .lump(LumpIndex::Planes, &planes[..])
+            .lump(LumpIndex::Vertexes, &vertexes[..])
+            .lump(LumpIndex::Nodes, &nodes[..])
+            .lump(LumpIndex::TexInfo, &tex_info[..])
+            .lump(LumpIndex::Faces, &faces[..])
+            .lump(LumpIndex::Leafs, &leaves[..])
+            .lump(LumpIndex::Edges, &edges[..])
+            .lump(LumpIndex::SurfEdges, &surf_edges[..])
+            .lump(LumpIndex::LeafFaces, &[0u16][..])
+            .lump(LumpIndex::LeafBrushes, &[0u16][..])
+            .lump(LumpIndex::Brushes, &brushes[..])
+            .lump(LumpIndex::BrushSides, &brush_sides[..])
+            .lump(LumpIndex::Models, &[world][..])
+            .lump_version(LumpIndex::Leafs, 1)
+    }
+
+    /// Serializes the map, lumps are laid out in index order and aligned to 4 bytes.
+    pub fn build(&self) -> Vec<u8> {
+        let mut header = dheader_t {
+            ident: self.ident,
+            version: self.version,
+            map_revision: self.map_revision,
+            ..Default::default()
+        };
+
+        let mut out = vec![0u8; size_of::<dheader_t>()];
+        for (i, lump) in self.lumps.iter().enumerate() {
+            out.resize((out.len() + 3) & !3, 0);
+
+            let data = if i == LumpIndex::GameLump as usize && !self.game_lumps.is_empty() {
+                self.build_game_lump(out.len())
+            } else {
+                lump.data.clone()
+            };
+
+            header.lumps[i].fileofs = if data.is_empty() { 0 } else { out.len() as i32 };
+            header.lumps[i].filelen = data.len() as i32;
+            header.lumps[i].version = lump.version;
+            header.lumps[i].four_cc = lump.four_cc;
+            out.extend_from_slice(&data);
+        }
+
+        if self.lump_layout == LumpLayout::Left4Dead2 {
+            for lump in header.lumps.iter_mut() {
+                *lump = lump_t {
+                    fileofs: lump.version,
+                    filelen: lump.fileofs,
+                    version: lump.filelen,
+                    four_cc: lump.four_cc,
+                };
+            }
+        }
+
+        if self.big_endian {
+            header.swap_bytes();
+        }
+
+        out[..size_of::<dheader_t>()].copy_from_slice(header.as_bytes());
+        out
+    }
+
+    /// Serializes the game lump directory with file-absolute offsets.
+    fn build_game_lump(&self, fileofs: usize) -> Vec<u8> {
+        let compressed = self.game_lumps.iter().any(|lump| lump.compressed);
+        let count = self.game_lumps.len() + compressed as usize;
+
+        let payloads: Vec<Vec<u8>> = self
+            .game_lumps
+            .iter()
+            .map(|lump| {
+                if lump.compressed {
+                    lzma::compress(&lump.data)
+                } else {
+                    lump.data.clone()
+                }
+            })
+            .collect();
+
+        let mut ofs = fileofs + size_of::<dgamelumpheader_t>() + count * size_of::<dgamelump_t>();
+        let mut dir = Vec::new();
+        for (lump, payload) in self.game_lumps.iter().zip(payloads.iter()) {
+            dir.push(dgamelump_t {
+                id: lump.id,
+                flags: if lump.compressed {
+                    GAMELUMP_FLAG_COMPRESSED
+                } else {
+                    0
+                },
+                version: lump.version,
+                fileofs: ofs as i32,
+                filelen: lump.data.len() as i32,
+            });
+            ofs += payload.len();
+        }
+        if compressed {
+            dir.push(dgamelump_t {
+                id: 0,
+                flags: 0,
+                version: 0,
+                fileofs: ofs as i32,
+                filelen: 0,
+            });
+        }
+
+        let mut out = (count as i32).to_le_bytes().to_vec();
+        out.extend_from_slice(dir.as_bytes());
+        for payload in payloads.iter() {
+            out.extend_from_slice(payload);
+        }
+        out
+    }
+}
+
+/// Serializes a `.lmp` lump file that replaces the given lump.
+pub fn lump_file(index: LumpIndex, version: i32, map_revision: i32, data: &[u8]) -> Vec<u8> {
+    LumpFile::new(index, version, map_revision, data)
+        .bytes()
+        .to_vec()
+}
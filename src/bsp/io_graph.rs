@@ -0,0 +1,354 @@
+//! The input/output graph of the map logic.
+//!
+//! Every output key of an entity (`"OnTrigger" "door,Open,,0,-1"`) is a connection to the
+//! entities matching the target name. Newer games separate the fields with ESC instead of commas.
+
+use super::entity::{Entities, Entity};
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const FIELD_SEPARATOR_ESC: char = '\x1b';
+
+/// A single output of an entity.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Connection {
+    /// Index of the entity that fires the output.
+    pub source: usize,
+    pub output: String,
+    /// The targetname or classname of the targets, a trailing `*` matches any suffix.
+    pub target: String,
+    pub input: String,
+    pub parameter: String,
+    /// Delay in seconds.
+    pub delay: f32,
+    /// Number of times the output fires, -1 fires forever.
+    pub max_fires: i32,
+    /// Indices of the entities the target resolves to.
+    ///
+    /// Names that only exist at runtime (`!activator`, `!caller`, `!player`, ...) resolve to nothing.
+    pub targets: Vec<usize>,
+}
+
+impl Connection {
+    /// Parses the value of an output key, returns `None` if the value is not an output.
+    pub fn parse(source: usize, output: &str, value: &str) -> Option<Self> {
+        let separator = if value.contains(FIELD_SEPARATOR_ESC) {
+            FIELD_SEPARATOR_ESC
+        } else {
+            ','
+        };
+        let fields = value.split(separator).collect::<Vec<_>>();
+        if fields.len() != 5 || fields[0].is_empty() || fields[1].is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            source,
+            output: output.to_string(),
+            target: fields[0].to_string(),
+            input: fields[1].to_string(),
+            parameter: fields[2].to_string(),
+            delay: fields[3].trim().parse().ok()?,
+            max_fires: fields[4].trim().parse().ok()?,
+            targets: Vec::new(),
+        })
+    }
+}
+
+/// The connections between all entities of a map.
+#[derive(Clone, Debug, Default)]
+pub struct IoGraph {
+    connections: Vec<Connection>,
+    /// Connection indices by source entity.
+    outputs: Vec<Vec<usize>>,
+    /// Connection indices by target entity.
+    inputs: Vec<Vec<usize>>,
+    labels: Vec<String>,
+}
+
+impl IoGraph {
+    pub fn new(entities: &Entities) -> Self {
+        let mut graph = Self {
+            outputs: vec![Vec::new(); entities.len()],
+            inputs: vec![Vec::new(); entities.len()],
+            ..Default::default()
+        };
+
+        for (source, entity) in entities.iter().enumerate() {
+            graph
+                .labels
+                .push(match (entity.targetname(), entity.classname()) {
+                    (Some(name), Some(class)) => format!("{}\n{}", name, class),
+                    (None, Some(class)) => format!("#{}\n{}", source, class),
+                    (Some(name), None) => name.to_string(),
+                    (None, None) => format!("#{}", source),
+                });
+
+            for (key, value) in entity.iter() {
+                if let Some(mut connection) = Connection::parse(source, key, value) {
+                    connection.targets = resolve_target(entities, source, &connection.target);
+
+                    let index = graph.connections.len();
+                    graph.outputs[source].push(index);
+                    for &target in connection.targets.iter() {
+                        graph.inputs[target].push(index);
+                    }
+                    graph.connections.push(connection);
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// All connections in entity and key order.
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    /// The outputs fired by the entity.
+    pub fn outputs_of(&self, entity: usize) -> impl Iterator<Item = &Connection> {
+        self.connections_at(&self.outputs, entity)
+    }
+
+    /// The connections that target the entity.
+    pub fn inputs_of(&self, entity: usize) -> impl Iterator<Item = &Connection> {
+        self.connections_at(&self.inputs, entity)
+    }
+
+    fn connections_at<'a>(
+        &'a self,
+        index: &'a [Vec<usize>],
+        entity: usize,
+    ) -> impl Iterator<Item = &'a Connection> {
+        index
+            .get(entity)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(move |&i| &self.connections[i])
+    }
+
+    /// Returns all entities that can be triggered by the entity, directly or through other entities.
+    ///
+    /// The entities are returned in breadth first order, the entity itself is only included
+    /// if it is part of a cycle.
+    pub fn reachable_from(&self, entity: usize) -> Vec<usize> {
+        let mut visited = vec![false; self.outputs.len()];
+        let mut reachable = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(entity);
+
+        while let Some(current) = queue.pop_front() {
+            for connection in self.outputs_of(current) {
+                for &target in connection.targets.iter() {
+                    if !visited[target] {
+                        visited[target] = true;
+                        reachable.push(target);
+                        queue.push_back(target);
+                    }
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// Exports the graph in graphviz DOT format.
+    ///
+    /// Only entities with connections are included, unresolved targets are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph entities {\n");
+
+        for (entity, label) in self.labels.iter().enumerate() {
+            if !self.outputs[entity].is_empty() || !self.inputs[entity].is_empty() {
+                writeln!(dot, "    e{} [label=\"{}\"];", entity, escape(label)).unwrap();
+            }
+        }
+
+        let mut unresolved = HashMap::new();
+        for connection in self.connections.iter() {
+            let mut label = format!("{} > {}", connection.output, connection.input);
+            if !connection.parameter.is_empty() {
+                write!(label, "({})", connection.parameter).unwrap();
+            }
+            if connection.delay > 0f32 {
+                write!(label, " +{}s", connection.delay).unwrap();
+            }
+            let label = escape(&label);
+
+            if connection.targets.is_empty() {
+                let count = unresolved.len();
+                let node = *unresolved
+                    .entry(connection.target.to_ascii_lowercase())
+                    .or_insert_with(|| {
+                        writeln!(
+                            dot,
+                            "    u{} [label=\"{}\", style=dashed];",
+                            count,
+                            escape(&connection.target)
+                        )
+                        .unwrap();
+                        count
+                    });
+                writeln!(
+                    dot,
+                    "    e{} -> u{} [label=\"{}\", style=dashed];",
+                    connection.source, node, label
+                )
+                .unwrap();
+            }
+            for target in connection.targets.iter() {
+                writeln!(
+                    dot,
+                    "    e{} -> e{} [label=\"{}\"];",
+                    connection.source, target, label
+                )
+                .unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Resolves a target like the engine: targetnames first, then classnames.
+fn resolve_target(entities: &Entities, source: usize, target: &str) -> Vec<usize> {
+    // `!caller` is whichever entity fired the input at runtime, only `!self` is known up front
+    if target.eq_ignore_ascii_case("!self") {
+        return vec![source];
+    }
+    if target.starts_with('!') {
+        return Vec::new();
+    }
+
+    if let Some(prefix) = target.strip_suffix('*') {
+        let matches = |name: Option<&str>| {
+            name.and_then(|name| name.get(..prefix.len()))
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        };
+        let find = |name: fn(&Entity) -> Option<&str>| {
+            entities
+                .iter()
+                .enumerate()
+                .filter(|(_, entity)| matches(name(entity)))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        let by_name = find(Entity::targetname);
+        return if by_name.is_empty() {
+            find(Entity::classname)
+        } else {
+            by_name
+        };
+    }
+
+    let by_name = entities.targetname_indices(target);
+    if !by_name.is_empty() {
+        by_name.to_vec()
+    } else {
+        entities.class_indices(target).to_vec()
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGIC: &[u8] = b"{ \"classname\" \"worldspawn\" }\n\
+        { \"classname\" \"trigger_once\" \"OnTrigger\" \"relay,Trigger,,0,1\" }\n\
+        { \"classname\" \"logic_relay\" \"targetname\" \"relay\"\n\
+          \"OnTrigger\" \"door_*,Open,,0.5,-1\" \"OnTrigger\" \"!activator\x1bSetHealth\x1b100\x1b0\x1b-1\" }\n\
+        { \"classname\" \"func_door\" \"targetname\" \"door_a\" \"OnFullyOpen\" \"relay,Trigger,,1,-1\" }\n\
+        { \"classname\" \"func_door\" \"targetname\" \"Door_B\" \"rendercolor\" \"255 255 255\" }\n\
+        { \"classname\" \"ambient_generic\" \"targetname\" \"sound\" }\n";
+
+    #[test]
+    fn parse_connection() {
+        let connection = Connection::parse(3, "OnTrigger", "door,Open,,0.5,-1").unwrap();
+        assert_eq!(connection.target, "door");
+        assert_eq!(connection.input, "Open");
+        assert_eq!(connection.parameter, "");
+        assert_eq!(connection.delay, 0.5f32);
+        assert_eq!(connection.max_fires, -1);
+
+        let connection =
+            Connection::parse(0, "OnUser1", "msg\x1bShowMessage\x1ba,b\x1b0\x1b1").unwrap();
+        assert_eq!(connection.parameter, "a,b");
+        assert_eq!(connection.max_fires, 1);
+
+        assert!(Connection::parse(0, "rendercolor", "255 255 255").is_none());
+        assert!(Connection::parse(0, "ResponseContext", "a,b,c,d,e").is_none());
+        assert!(Connection::parse(0, "OnTrigger", ",Open,,0,-1").is_none());
+    }
+
+    #[test]
+    fn graph() {
+        let entities = Entities::parse(LOGIC).unwrap();
+        let graph = IoGraph::new(&entities);
+        assert_eq!(graph.connections().len(), 4);
+
+        let relay = &graph.connections()[1];
+        assert_eq!(relay.source, 2);
+        assert_eq!(relay.targets, vec![3, 4]);
+        assert!(graph.connections()[2].targets.is_empty());
+
+        assert_eq!(graph.outputs_of(2).count(), 2);
+        assert_eq!(graph.inputs_of(2).count(), 2);
+        assert_eq!(graph.inputs_of(4).count(), 1);
+        assert_eq!(graph.outputs_of(100).count(), 0);
+
+        // the door triggers the relay again
+        assert_eq!(graph.reachable_from(1), vec![2, 3, 4]);
+        assert_eq!(graph.reachable_from(2), vec![3, 4, 2]);
+        assert!(graph.reachable_from(5).is_empty());
+
+        // only `!self` is known without running the map
+        let entities = Entities::parse(
+            b"{ \"classname\" \"logic_relay\" \"OnTrigger\" \"!self,Disable,,0,-1\" \
+                \"OnSpawn\" \"!caller,Kill,,0,-1\" }",
+        )
+        .unwrap();
+        let graph = IoGraph::new(&entities);
+        assert_eq!(graph.connections()[0].targets, vec![0]);
+        assert!(graph.connections()[1].targets.is_empty());
+
+        // classnames are used if no targetname matches
+        let entities = Entities::parse(
+            b"{ \"classname\" \"logic_auto\" \"OnMapSpawn\" \"func_door,Lock,,0,-1\" }\
+              { \"classname\" \"func_door\" }",
+        )
+        .unwrap();
+        assert_eq!(IoGraph::new(&entities).connections()[0].targets, vec![1]);
+    }
+
+    #[test]
+    fn dot() {
+        let entities = Entities::parse(LOGIC).unwrap();
+        let dot = IoGraph::new(&entities).to_dot();
+        assert!(dot.starts_with("digraph entities {\n"));
+        assert!(dot.contains("    e2 [label=\"relay\\nlogic_relay\"];\n"));
+        assert!(dot.contains("    e1 [label=\"#1\\ntrigger_once\"];\n"));
+        assert!(dot.contains("    e2 -> e3 [label=\"OnTrigger > Open +0.5s\"];\n"));
+        assert!(dot.contains("    u0 [label=\"!activator\", style=dashed];\n"));
+        assert!(
+            dot.contains("    e2 -> u0 [label=\"OnTrigger > SetHealth(100)\", style=dashed];\n")
+        );
+        // entities without connections are left out
+        assert!(!dot.contains("e5 "));
+        assert!(!dot.contains("e0 "));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,199 @@
+use super::native::*;
+use crate::error::*;
+
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use dataview::PodMethods;
+
+/// An external lump file (`<mapname>_l_<n>.lmp`) that replaces a single lump of a map.
+#[derive(Clone)]
+pub struct LumpFile {
+    pub header: lumpfileheader_t,
+    pub path: Option<PathBuf>,
+    bytes: Vec<u8>,
+}
+
+impl LumpFile {
+    /// Builds a lump file that replaces the lump of a map with the given data.
+    ///
+    /// The engine ignores lump files with a `map_revision` older than the one of the map.
+    pub fn new(index: LumpIndex, version: i32, map_revision: i32, data: &[u8]) -> Self {
+        let header = lumpfileheader_t {
+            lump_offset: size_of::<lumpfileheader_t>() as i32,
+            lump_id: index as i32,
+            lump_version: version,
+            lump_length: data.len() as i32,
+            map_revision,
+        };
+
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(data);
+        Self {
+            header,
+            path: None,
+            bytes,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = Self::from_bytes(fs::read(path.as_ref())?)?;
+        file.path = Some(path.as_ref().to_path_buf());
+        Ok(file)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < size_of::<lumpfileheader_t>() {
+            return Err(Error::InvalidLumpFile("lump file is too small"));
+        }
+
+        let mut header = lumpfileheader_t::zeroed();
+        header
+            .as_bytes_mut()
+            .copy_from_slice(&bytes[..size_of::<lumpfileheader_t>()]);
+
+        let end = header.lump_offset as i64 + header.lump_length as i64;
+        if header.lump_offset < 0 || header.lump_length < 0 || end > bytes.len() as i64 {
+            return Err(Error::InvalidLumpFile("lump data is out of bounds"));
+        }
+
+        Ok(Self {
+            header,
+            path: None,
+            bytes,
+        })
+    }
+
+    /// The contents of the replaced lump.
+    pub fn data(&self) -> &[u8] {
+        let start = self.header.lump_offset as usize;
+        &self.bytes[start..start + self.header.lump_length as usize]
+    }
+
+    /// The whole lump file including its header.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Writes the lump file, the path is usually `<mapname>_l_<n>.lmp` next to the map.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        fs::write(path.as_ref(), &self.bytes)?;
+        self.path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+}
+
+/// Describes a lump that was replaced by a lump file while loading.
+#[derive(Clone, Debug)]
+pub struct LumpOverride {
+    pub lump_id: usize,
+    pub version: i32,
+    pub map_revision: i32,
+    pub path: Option<PathBuf>,
+}
+
+/// Returns all lump files of the given map in the order the engine applies them.
+///
+/// Lump files are numbered sequentially starting at `<mapname>_l_0.lmp`,
+/// the search stops at the first missing file.
+pub fn find_lump_files<P: AsRef<Path>>(map_path: P) -> Vec<PathBuf> {
+    let map_path = map_path.as_ref();
+    let stem = match map_path.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => return Vec::new(),
+    };
+    let dir = map_path.parent().unwrap_or_else(|| Path::new(""));
+
+    (0..)
+        .map(|i| dir.join(format!("{}_l_{}.lmp", stem, i)))
+        .take_while(|path| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::{self, MapBuilder};
+    use super::super::*;
+
+    #[test]
+    fn lump_file_bounds() {
+        let bytes = fixture::lump_file(LumpIndex::Entities, 0, 1, b"{}\0");
+        let file = LumpFile::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(file.header.lump_id, 0);
+        assert_eq!(file.data(), b"{}\0");
+
+        assert!(LumpFile::from_bytes(bytes[..bytes.len() - 1].to_vec()).is_err());
+        assert!(LumpFile::from_bytes(bytes[..8].to_vec()).is_err());
+    }
+
+    #[test]
+    fn apply_lump_files() {
+        let data = MapBuilder::box_room().build();
+
+        let leaves = [dleaf_v0_t::zeroed(); 5];
+        let lump_files = vec![
+            // out of date, ignored
+            LumpFile::from_bytes(fixture::lump_file(LumpIndex::Leafs, 1, 0, &[0u8; 32])).unwrap(),
+            LumpFile::from_bytes(fixture::lump_file(
+                LumpIndex::Leafs,
+                0,
+                1,
+                leaves.as_bytes(),
+            ))
+            .unwrap(),
+        ];
+
+        let map = BSP::from_reader_with_overrides(
+            std::io::Cursor::new(data),
+            &LoadOptions::default(),
+            lump_files,
+        )
+        .unwrap();
+        assert_eq!(map.leaves.len(), 5);
+        assert_eq!(map.leaf_ambient_lighting.len(), 5);
+        assert_eq!(map.lump_overrides.len(), 1);
+        assert_eq!(map.lump_overrides[0].lump_id, LumpIndex::Leafs as usize);
+        assert_eq!(map.lump_overrides[0].version, 0);
+    }
+
+    #[test]
+    fn open_with_lump_files() {
+        let dir = std::env::temp_dir().join(format!(
+            "bsp_rs_{}_open_with_lump_files",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let map_path = dir.join("box_room.bsp");
+        std::fs::write(&map_path, MapBuilder::box_room().build()).unwrap();
+
+        let brush_sides = vec![dbrushside_t::zeroed(); 8];
+        std::fs::write(
+            dir.join("box_room_l_0.lmp"),
+            fixture::lump_file(LumpIndex::BrushSides, 0, 1, brush_sides[..].as_bytes()),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("box_room_l_2.lmp"),
+            fixture::lump_file(LumpIndex::Planes, 0, 1, &[0u8; 20]),
+        )
+        .unwrap();
+
+        // only consecutive lump files are picked up
+        assert_eq!(find_lump_files(&map_path).len(), 1);
+
+        let map = BSP::open(&map_path).unwrap();
+        assert_eq!(map.brush_sides.len(), 6);
+        assert!(map.lump_overrides.is_empty());
+
+        let map = BSP::open_with(&map_path, &LoadOptions::new().lump_overrides(true)).unwrap();
+        assert_eq!(map.brush_sides.len(), 8);
+        assert_eq!(map.planes.len(), 7);
+        assert_eq!(
+            map.lump_overrides[0].path.as_deref(),
+            Some(dir.join("box_room_l_0.lmp").as_path())
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
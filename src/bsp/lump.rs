@@ -0,0 +1,300 @@
+use super::endian::SwapBytes;
+use super::lmp::*;
+use super::lzma;
+use super::native::*;
+use super::options::LoadProfile;
+use crate::error::*;
+
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::mem::size_of;
+
+use dataview::{Pod, PodMethods};
+
+/// Reads `len` bytes at `offset` that belong to the given lump.
+pub(crate) fn read_bytes<R: Read + Seek>(
+    reader: &mut R,
+    lump: LumpIndex,
+    offset: i32,
+    len: i32,
+) -> Result<Vec<u8>> {
+    let out_of_bounds = || Error::LumpOutOfBounds {
+        lump,
+        offset: offset as i64,
+        len: len as i64,
+    };
+    if offset < 0 || len < 0 {
+        return Err(out_of_bounds());
+    }
+
+    // check the size before allocating, the lengths in the file can not be trusted
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    if offset as u64 + len as u64 > file_len {
+        return Err(out_of_bounds());
+    }
+
+    let mut out = vec![0u8; len as usize];
+    reader.seek(SeekFrom::Start(offset as u64))?;
+    reader.read_exact(&mut out).map_err(|err| {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            out_of_bounds()
+        } else {
+            Error::Io(err)
+        }
+    })?;
+    Ok(out)
+}
+
+/// Reads the contents of a lump and transparently decompresses it.
+fn read_lump<R: Read + Seek>(
+    reader: &mut R,
+    index: LumpIndex,
+    lump: &lump_t,
+    big_endian: bool,
+    budget: &mut u64,
+) -> Result<Vec<u8>> {
+    let data = read_bytes(reader, index, lump.fileofs, lump.filelen)?;
+    if lump.is_compressed() {
+        lzma::decompress(index, &data, lump.uncompressed_size(big_endian), budget)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Copies the lump data into a vector of `T`, the lump size has to be a multiple of `T`.
+pub(crate) fn cast_lump_data<T: Pod + Clone>(lump: LumpIndex, data: &[u8]) -> Result<Vec<T>> {
+    let lump_size = data.len() / size_of::<T>();
+    if !data.len().is_multiple_of(size_of::<T>()) {
+        return Err(Error::LumpSizeMismatch {
+            lump,
+            len: data.len(),
+            element_size: size_of::<T>(),
+        });
+    }
+
+    let mut out: Vec<T> = vec![unsafe { core::mem::zeroed() }; lump_size];
+    out.as_bytes_mut()
+        .copy_from_slice(&data[..lump_size * size_of::<T>()]);
+    Ok(out)
+}
+
+/// A single entry of the game lump (static props, detail props, etc).
+#[derive(Clone, Debug)]
+pub struct GameLump {
+    pub id: i32,
+    pub flags: u16,
+    pub version: u16,
+    /// The decompressed contents of this game lump.
+    pub data: Vec<u8>,
+}
+
+/// Parses the game lump directory, the contents of each game lump are kept in file byte order.
+fn parse_game_lumps<R: Read + Seek>(
+    reader: &mut R,
+    lump: &lump_t,
+    big_endian: bool,
+    budget: &mut u64,
+) -> Result<Vec<GameLump>> {
+    if lump.filelen < size_of::<dgamelumpheader_t>() as i32 {
+        return Ok(Vec::new());
+    }
+
+    let mut game_header = dgamelumpheader_t::zeroed();
+    reader.seek(SeekFrom::Start(lump.fileofs as u64))?;
+    reader.read_exact(game_header.as_bytes_mut())?;
+    if big_endian {
+        game_header.swap_bytes();
+    }
+
+    let dir_len = (game_header.lump_count as i64) * size_of::<dgamelump_t>() as i64;
+    if game_header.lump_count < 0
+        || dir_len > lump.filelen as i64 - size_of::<dgamelumpheader_t>() as i64
+    {
+        return Err(Error::InvalidLump {
+            lump: LumpIndex::GameLump,
+            reason: "game lump directory exceeds the lump",
+        });
+    }
+
+    let mut dir = vec![dgamelump_t::zeroed(); game_header.lump_count as usize];
+    reader.read_exact(dir.as_bytes_mut())?;
+    if big_endian {
+        dir.iter_mut().for_each(SwapBytes::swap_bytes);
+    }
+
+    // game lump offsets are relative to the start of the file they are stored in
+    let game_lump_end = lump.fileofs as i64 + lump.filelen as i64;
+    let mut game_lumps = Vec::with_capacity(dir.len());
+    for (i, entry) in dir.iter().enumerate() {
+        // compressed maps terminate the directory with an empty dummy entry
+        if entry.id == 0 && entry.filelen == 0 {
+            continue;
+        }
+
+        let data = if entry.flags & GAMELUMP_FLAG_COMPRESSED != 0 {
+            // the compressed size is implied by the offset of the following entry
+            let next_ofs = dir
+                .get(i + 1)
+                .map(|next| next.fileofs as i64)
+                .unwrap_or(game_lump_end);
+            let len = next_ofs - entry.fileofs as i64;
+            if len < 0 || len > i32::MAX as i64 {
+                return Err(Error::InvalidLump {
+                    lump: LumpIndex::GameLump,
+                    reason: "invalid compressed game lump size",
+                });
+            }
+            let data = read_bytes(reader, LumpIndex::GameLump, entry.fileofs, len as i32)?;
+            lzma::decompress(LumpIndex::GameLump, &data, entry.filelen as u32, budget)?
+        } else {
+            read_bytes(reader, LumpIndex::GameLump, entry.fileofs, entry.filelen)?
+        };
+
+        game_lumps.push(GameLump {
+            id: entry.id,
+            flags: entry.flags,
+            version: entry.version,
+            data,
+        });
+    }
+
+    Ok(game_lumps)
+}
+
+/// The decompressed contents of all lumps in file byte order.
+pub(crate) struct RawLumps {
+    lumps: Vec<Vec<u8>>,
+    big_endian: bool,
+}
+
+impl RawLumps {
+    pub fn get(&self, index: LumpIndex) -> &[u8] {
+        &self.lumps[index as usize]
+    }
+
+    /// Reinterprets the lump in native endianness.
+    pub fn cast<T: Pod + Clone + SwapBytes>(&self, index: LumpIndex) -> Result<Vec<T>> {
+        let mut out: Vec<T> = cast_lump_data(index, self.get(index))?;
+        if self.big_endian {
+            out.iter_mut().for_each(SwapBytes::swap_bytes);
+        }
+        Ok(out)
+    }
+
+    pub fn into_inner(self) -> Vec<Vec<u8>> {
+        self.lumps
+    }
+}
+
+/// Reads the lumps of a bsp while taking applied lump files into account.
+pub(crate) struct LumpReader<R> {
+    reader: R,
+    header: dheader_t,
+    big_endian: bool,
+    lump_files: Vec<Option<LumpFile>>,
+    /// Bytes compressed lumps may still unpack to.
+    unpack_budget: u64,
+}
+
+impl<R: Read + Seek> LumpReader<R> {
+    pub fn new(reader: R, header: dheader_t, big_endian: bool, unpack_budget: u64) -> Self {
+        Self {
+            reader,
+            header,
+            big_endian,
+            lump_files: vec![None; HEADER_LUMP_COUNT],
+            unpack_budget,
+        }
+    }
+
+    /// The bsp header, overridden lumps carry the version of their lump file.
+    pub fn header(&self) -> &dheader_t {
+        &self.header
+    }
+
+    /// Replaces a lump with the contents of the lump file.
+    ///
+    /// Lump files built for an older revision of the map are ignored and `None` is returned.
+    pub fn apply(&mut self, file: LumpFile) -> Result<Option<LumpOverride>> {
+        let lump_id = file.header.lump_id;
+        if lump_id < 0 || lump_id as usize >= HEADER_LUMP_COUNT {
+            return Err(Error::InvalidLumpFile("lump id is out of range"));
+        }
+
+        if file.header.map_revision < self.header.map_revision {
+            return Ok(None);
+        }
+
+        let lump = &mut self.header.lumps[lump_id as usize];
+        lump.version = file.header.lump_version;
+        lump.filelen = file.header.lump_length;
+        // lump files have no field for the uncompressed size, their contents are stored as is
+        lump.four_cc = [0; 4];
+
+        let lump_override = LumpOverride {
+            lump_id: lump_id as usize,
+            version: file.header.lump_version,
+            map_revision: file.header.map_revision,
+            path: file.path.clone(),
+        };
+        self.lump_files[lump_id as usize] = Some(file);
+        Ok(Some(lump_override))
+    }
+
+    /// Reads the decompressed contents of a lump.
+    pub fn read(&mut self, index: LumpIndex) -> Result<Vec<u8>> {
+        match &self.lump_files[index as usize] {
+            Some(file) => Ok(file.data().to_vec()),
+            None => read_lump(
+                &mut self.reader,
+                index,
+                &self.header.lumps[index as usize],
+                self.big_endian,
+                &mut self.unpack_budget,
+            ),
+        }
+    }
+
+    /// Reads the decompressed contents of all lumps in the profile, other lumps are left empty.
+    pub fn read_all(&mut self, profile: LoadProfile) -> Result<RawLumps> {
+        let lumps = LumpIndex::ALL
+            .iter()
+            .map(|&index| {
+                if profile.includes(index) {
+                    self.read(index)
+                } else {
+                    Ok(Vec::new())
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RawLumps {
+            lumps,
+            big_endian: self.big_endian,
+        })
+    }
+
+    pub fn game_lumps(&mut self) -> Result<Vec<GameLump>> {
+        let index = LumpIndex::GameLump as usize;
+        match &self.lump_files[index] {
+            Some(file) => {
+                let lump = lump_t {
+                    fileofs: file.header.lump_offset,
+                    filelen: file.header.lump_length,
+                    version: file.header.lump_version,
+                    four_cc: [0; 4],
+                };
+                parse_game_lumps(
+                    &mut Cursor::new(file.bytes()),
+                    &lump,
+                    self.big_endian,
+                    &mut self.unpack_budget,
+                )
+            }
+            None => parse_game_lumps(
+                &mut self.reader,
+                &self.header.lumps[index],
+                self.big_endian,
+                &mut self.unpack_budget,
+            ),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use super::native::*;
+use crate::error::*;
+
+use std::io::Cursor;
+use std::mem::size_of;
+
+use dataview::PodMethods;
+use lzma_rs::decompress::{Options, UnpackedSize};
+
+/// Lumps are addressed with `i32` lengths, larger unpacked sizes can not be valid.
+const MAX_UNPACKED_SIZE: usize = i32::MAX as usize;
+
+/// Returns true if the data starts with valve's lzma header.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() >= size_of::<lzma_header_t>() && data[..4] == LZMA_ID.to_le_bytes()
+}
+
+/// Decompresses a lump (or game lump) that was compressed with valve's lzma header.
+///
+/// `unpacked_size` is the size recorded outside of the lzma header (the `four_cc` of the lump
+/// or the length of the game lump), both sizes have to agree.
+/// `budget` is the number of bytes that may still be unpacked while loading the map, the
+/// unpacked size of the lump is checked against it before decoding and then deducted.
+pub fn decompress(
+    lump: LumpIndex,
+    data: &[u8],
+    unpacked_size: u32,
+    budget: &mut u64,
+) -> Result<Vec<u8>> {
+    let error = |source| Error::Decompress { lump, source };
+    if !is_compressed(data) {
+        return Err(error(None));
+    }
+
+    let mut header = lzma_header_t::zeroed();
+    header
+        .as_bytes_mut()
+        .copy_from_slice(&data[..size_of::<lzma_header_t>()]);
+
+    let body = &data[size_of::<lzma_header_t>()..];
+    let lzma_size = header.lzma_size as usize;
+    if lzma_size > body.len() {
+        return Err(error(None));
+    }
+
+    // valve omits the unpacked size from the regular lzma header, it is stored in `actual_size` instead
+    let mut stream = Vec::with_capacity(header.properties.len() + lzma_size);
+    stream.extend_from_slice(&header.properties);
+    stream.extend_from_slice(&body[..lzma_size]);
+
+    if header.actual_size != unpacked_size {
+        return Err(error(None));
+    }
+    let actual_size = header.actual_size as usize;
+    if actual_size > MAX_UNPACKED_SIZE {
+        return Err(error(None));
+    }
+    if actual_size as u64 > *budget {
+        return Err(Error::InvalidLump {
+            lump,
+            reason: "compressed lumps exceed the unpacked size limit",
+        });
+    }
+    // the unpacked size bounds the output, the buffer grows with the data that is actually decoded
+    let mut out = Vec::new();
+    let options = Options {
+        unpacked_size: UnpackedSize::UseProvided(Some(actual_size as u64)),
+        ..Default::default()
+    };
+    lzma_rs::lzma_decompress_with_options(&mut Cursor::new(stream), &mut out, &options)
+        .map_err(|err| error(Some(err.into())))?;
+
+    if out.len() != actual_size {
+        return Err(error(None));
+    }
+    *budget -= actual_size as u64;
+    Ok(out)
+}
+
+/// Compresses data into valve's lzma format.
+#[cfg(test)]
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    use lzma_rs::compress::{Options, UnpackedSize};
+
+    let mut stream = Vec::new();
+    let options = Options {
+        unpacked_size: UnpackedSize::SkipWritingToHeader,
+    };
+    lzma_rs::lzma_compress_with_options(&mut Cursor::new(data), &mut stream, &options).unwrap();
+
+    let mut header = lzma_header_t {
+        id: LZMA_ID,
+        actual_size: data.len() as u32,
+        lzma_size: (stream.len() - 5) as u32,
+        properties: [0u8; 5],
+    };
+    header.properties.copy_from_slice(&stream[..5]);
+
+    let mut out = header.as_bytes().to_vec();
+    out.extend_from_slice(&stream[5..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let compressed = compress(&data);
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < data.len());
+        let mut budget = data.len() as u64 + 1;
+        assert_eq!(
+            decompress(LumpIndex::Planes, &compressed, 4096, &mut budget).unwrap(),
+            data
+        );
+        assert_eq!(budget, 1);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = vec![7u8; 1024];
+        let compressed = compress(&data);
+        let truncated = &compressed[..compressed.len() - 4];
+        assert!(decompress(LumpIndex::Planes, truncated, 1024, &mut u64::MAX).is_err());
+        assert!(!is_compressed(&data));
+    }
+
+    /// 1 MiB of zeros compressed by xz, the ratio is far above what `compress` produces.
+    const ZEROS_PROPERTIES: [u8; 5] = [0x5d, 0x00, 0x00, 0x01, 0x00];
+    const ZEROS: &[u8] = &[
+        0x00, 0x00, 0x6f, 0xfd, 0xff, 0xff, 0xa3, 0xb7, 0xff, 0x47, 0x3e, 0x48, 0x15, 0x72, 0x39,
+        0x61, 0x51, 0xb8, 0x92, 0x28, 0xe6, 0xa3, 0x86, 0x07, 0xf9, 0xee, 0xe4, 0x1e, 0x82, 0xd3,
+        0x2f, 0xc5, 0x3a, 0x3c, 0x01, 0x4b, 0xb1, 0x7e, 0xc9, 0x8a, 0x8a, 0x4d, 0x2f, 0xa3, 0x0d,
+        0xd9, 0x7f, 0xa6, 0xe3, 0x8c, 0x23, 0x11, 0x53, 0xe0, 0x59, 0x18, 0xc5, 0x75, 0x8a, 0xe2,
+        0x77, 0xf8, 0xb6, 0x94, 0x7f, 0x0c, 0x6a, 0xc0, 0xde, 0x74, 0x49, 0x64, 0xe2, 0xe9, 0x5c,
+        0x53, 0xb2, 0x04, 0xd8, 0xf7, 0x44, 0x0c, 0xab, 0x5f, 0x0d, 0x6d, 0x46, 0xe9, 0xe5, 0xc3,
+        0x76, 0x88, 0xb7, 0x96, 0x57, 0xac, 0xb6, 0x4d, 0xe1, 0x69, 0x1d, 0x6f, 0xfb, 0x4b, 0x88,
+        0x10, 0x6c, 0x42, 0xcb, 0x88, 0x3f, 0x5c, 0x00, 0x8f, 0xd0, 0x4e, 0xaf, 0x26, 0x28, 0x94,
+        0x71, 0x1f, 0x3d, 0x8f, 0x24, 0xe1, 0x70, 0x9e, 0xa7, 0x23, 0x5f, 0xec, 0x28, 0xcb, 0x85,
+        0xd1, 0x95, 0x98, 0x8a, 0x7e, 0x2a, 0x91, 0xf2, 0x27, 0x75, 0xf7, 0x19, 0xc0, 0x06, 0x98,
+        0x4d, 0x98, 0xfd, 0xd8, 0xaf, 0xd5, 0x90, 0x0f, 0xc4, 0x25, 0x53, 0xf8, 0xf5, 0x91, 0x36,
+        0x31, 0x05, 0xa5, 0xb0, 0xee, 0x6f, 0xc1, 0x70, 0x4d, 0x47, 0x0c, 0xd1, 0x91, 0x11, 0xaa,
+        0xad, 0x60, 0x1d, 0xba, 0xce, 0xb1, 0x27, 0x18, 0x5c, 0x59, 0x86, 0xe9, 0x66, 0x52, 0x58,
+        0xbe, 0xe9, 0x76, 0xac, 0x59, 0xe4, 0xe5, 0x5b, 0x05, 0x08, 0xf9, 0xc7, 0xda, 0xad, 0xfc,
+        0xfb, 0x52, 0x2a, 0xf7, 0xe5, 0x1c, 0xdc, 0xcf, 0xff, 0xff, 0x82, 0x16, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn highly_compressed() {
+        let header = lzma_header_t {
+            id: LZMA_ID,
+            actual_size: 1 << 20,
+            lzma_size: ZEROS.len() as u32,
+            properties: ZEROS_PROPERTIES,
+        };
+        let mut compressed = header.as_bytes().to_vec();
+        compressed.extend_from_slice(ZEROS);
+        assert!(compressed.len() * 1024 < 1 << 20);
+
+        let data = decompress(LumpIndex::Lighting, &compressed, 1 << 20, &mut u64::MAX).unwrap();
+        assert_eq!(data.len(), 1 << 20);
+        assert!(data.iter().all(|&b| b == 0));
+
+        // the budget is checked before anything is decoded
+        let mut budget = (1 << 20) - 1;
+        assert!(matches!(
+            decompress(LumpIndex::Lighting, &compressed, 1 << 20, &mut budget),
+            Err(Error::InvalidLump {
+                lump: LumpIndex::Lighting,
+                ..
+            })
+        ));
+        assert_eq!(budget, (1 << 20) - 1);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let compressed = compress(&[7u8; 1024]);
+        assert!(matches!(
+            decompress(LumpIndex::Planes, &compressed, 1023, &mut u64::MAX),
+            Err(Error::Decompress { source: None, .. })
+        ));
+    }
+
+    #[test]
+    fn oversized() {
+        let mut compressed = compress(&[7u8; 1024]);
+        compressed[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            decompress(LumpIndex::Planes, &compressed, u32::MAX, &mut u64::MAX),
+            Err(Error::Decompress { source: None, .. })
+        ));
+    }
+}
@@ -2,7 +2,57 @@ pub fn dot_product(a: [f32; 3], b: [f32; 3]) -> f32 {
     a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
 }
 
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// Returns the point at `fraction` of the way from `from` to `to`.
+pub fn lerp(from: [f32; 3], to: [f32; 3], fraction: f32) -> [f32; 3] {
+    [
+        from[0] + fraction * (to[0] - from[0]),
+        from[1] + fraction * (to[1] - from[1]),
+        from[2] + fraction * (to[2] - from[2]),
+    ]
+}
+
+/// Scales the vector to unit length, the zero vector is returned as is.
 pub fn normalize(a: [f32; 3]) -> [f32; 3] {
-    let len = dot_product(a, a);
-    [a[0] / len, a[1] / len, a[2] / len]
+    let len = dot_product(a, a).sqrt();
+    if len > 0f32 {
+        [a[0] / len, a[1] / len, a[2] / len]
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors() {
+        assert_eq!(dot_product([1f32, 2f32, 3f32], [4f32, 5f32, 6f32]), 32f32);
+        assert_eq!(
+            cross([1f32, 0f32, 0f32], [0f32, 1f32, 0f32]),
+            [0f32, 0f32, 1f32]
+        );
+        assert_eq!(
+            sub([1f32, 2f32, 3f32], [3f32, 2f32, 1f32]),
+            [-2f32, 0f32, 2f32]
+        );
+        assert_eq!(
+            lerp([0f32, 10f32, -4f32], [8f32, 20f32, 4f32], 0.25f32),
+            [2f32, 12.5f32, -2f32]
+        );
+        assert_eq!(normalize([0f32, 3f32, -4f32]), [0f32, 0.6f32, -0.8f32]);
+        assert_eq!(normalize([0f32; 3]), [0f32; 3]);
+    }
 }
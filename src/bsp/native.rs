@@ -1,12 +1,24 @@
 #![allow(dead_code)]
 
 use dataview::Pod;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub const HEADER_MAGIC: i32 = 0x50534256; // 'VBSP'
+pub const HEADER_MAGIC_BE: i32 = 0x56425350; // 'PSBV', byte-swapped console maps
 pub const HEADER_LUMP_COUNT: usize = 64;
 
+/// The oldest supported bsp version (Vampire: The Masquerade - Bloodlines).
+pub const MIN_BSP_VERSION: i32 = 17;
+
 pub const MAX_MAP_LEAFBRUSHES: usize = 65536;
 
+pub const LZMA_ID: u32 = 0x414d5a4c; // 'LZMA'
+
+pub const GAMELUMP_FLAG_COMPRESSED: u16 = 0x1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum LumpIndex {
     Entities = 0,
     Planes = 1,
@@ -19,6 +31,7 @@ pub enum LumpIndex {
     Lighting = 8,
     Occlusion = 9,
     Leafs = 10,
+    FaceIds = 11,
     Edges = 12,
     SurfEdges = 13,
     Models = 14,
@@ -35,6 +48,7 @@ pub enum LumpIndex {
     ClusterPortals = 25,
     DispInfo = 26,
     OriginalFaces = 27,
+    PhysDisp = 28,
     PhysCollide = 29,
     VertNormals = 30,
     VertNormalIndices = 31,
@@ -55,15 +69,107 @@ pub enum LumpIndex {
     LeafMinDistToWater = 46,
     FaceMacroTextureInfo = 47,
     DispTris = 48,
+    PhysCollideSurface = 49,
+    WaterOverlays = 50,
+    LeafAmbientIndexHdr = 51,
+    LeafAmbientIndex = 52,
+    LightingHdr = 53,
+    WorldLightsHdr = 54,
+    LeafAmbientLightingHdr = 55,
+    LeafAmbientLighting = 56,
+    XZipPakFile = 57,
+    FacesHdr = 58,
+    MapFlags = 59,
+    OverlayFades = 60,
+    OverlaySystemLevels = 61,
+    PhysLevel = 62,
+    DispMultiBlend = 63,
+}
+
+impl LumpIndex {
+    /// All lumps in header order.
+    pub const ALL: [LumpIndex; HEADER_LUMP_COUNT] = [
+        LumpIndex::Entities,
+        LumpIndex::Planes,
+        LumpIndex::TexData,
+        LumpIndex::Vertexes,
+        LumpIndex::Visibility,
+        LumpIndex::Nodes,
+        LumpIndex::TexInfo,
+        LumpIndex::Faces,
+        LumpIndex::Lighting,
+        LumpIndex::Occlusion,
+        LumpIndex::Leafs,
+        LumpIndex::FaceIds,
+        LumpIndex::Edges,
+        LumpIndex::SurfEdges,
+        LumpIndex::Models,
+        LumpIndex::WorldLights,
+        LumpIndex::LeafFaces,
+        LumpIndex::LeafBrushes,
+        LumpIndex::Brushes,
+        LumpIndex::BrushSides,
+        LumpIndex::Areas,
+        LumpIndex::AreaPortals,
+        LumpIndex::Portals,
+        LumpIndex::Clusters,
+        LumpIndex::PortalVerts,
+        LumpIndex::ClusterPortals,
+        LumpIndex::DispInfo,
+        LumpIndex::OriginalFaces,
+        LumpIndex::PhysDisp,
+        LumpIndex::PhysCollide,
+        LumpIndex::VertNormals,
+        LumpIndex::VertNormalIndices,
+        LumpIndex::DispLightmapAlphas,
+        LumpIndex::DispVerts,
+        LumpIndex::DispLightmapSamplePositions,
+        LumpIndex::GameLump,
+        LumpIndex::LeafWaterData,
+        LumpIndex::Primitives,
+        LumpIndex::PrimVerts,
+        LumpIndex::PrimIndices,
+        LumpIndex::PakFile,
+        LumpIndex::ClipPortalVerts,
+        LumpIndex::CubeMaps,
+        LumpIndex::TexDataStringData,
+        LumpIndex::TexDataStringTable,
+        LumpIndex::Overlays,
+        LumpIndex::LeafMinDistToWater,
+        LumpIndex::FaceMacroTextureInfo,
+        LumpIndex::DispTris,
+        LumpIndex::PhysCollideSurface,
+        LumpIndex::WaterOverlays,
+        LumpIndex::LeafAmbientIndexHdr,
+        LumpIndex::LeafAmbientIndex,
+        LumpIndex::LightingHdr,
+        LumpIndex::WorldLightsHdr,
+        LumpIndex::LeafAmbientLightingHdr,
+        LumpIndex::LeafAmbientLighting,
+        LumpIndex::XZipPakFile,
+        LumpIndex::FacesHdr,
+        LumpIndex::MapFlags,
+        LumpIndex::OverlayFades,
+        LumpIndex::OverlaySystemLevels,
+        LumpIndex::PhysLevel,
+        LumpIndex::DispMultiBlend,
+    ];
+
+    /// Returns the lump stored at the given slot of the header.
+    pub fn from_index(index: usize) -> Option<LumpIndex> {
+        Self::ALL.get(index).copied()
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dheader_t {
-    pub ident: i32,                         // 0x000
-    pub version: i32,                       // 0x004
+    pub ident: i32,   // 0x000
+    pub version: i32, // 0x004
+    #[cfg_attr(feature = "serde", serde(with = "lump_array"))]
     pub lumps: [lump_t; HEADER_LUMP_COUNT], // 0x008
-    pub map_revision: i32,                  // 0x408
+    pub map_revision: i32, // 0x408
 } //Size=0x40C
 
 impl Default for dheader_t {
@@ -72,8 +178,46 @@ impl Default for dheader_t {
     }
 }
 
+impl dheader_t {
+    pub fn lump(&self, index: LumpIndex) -> &lump_t {
+        &self.lumps[index as usize]
+    }
+
+    /// Iterates over the descriptors of all lumps in header order.
+    pub fn iter_lumps(&self) -> impl Iterator<Item = (LumpIndex, &lump_t)> {
+        LumpIndex::ALL.iter().copied().zip(self.lumps.iter())
+    }
+}
+
+/// serde only implements its traits for arrays of up to 32 elements.
+#[cfg(feature = "serde")]
+mod lump_array {
+    use super::{lump_t, HEADER_LUMP_COUNT};
+
+    use std::convert::TryFrom;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        lumps: &[lump_t; HEADER_LUMP_COUNT],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(lumps.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[lump_t; HEADER_LUMP_COUNT], D::Error> {
+        let lumps = Vec::<lump_t>::deserialize(deserializer)?;
+        <[lump_t; HEADER_LUMP_COUNT]>::try_from(lumps)
+            .map_err(|lumps| D::Error::invalid_length(lumps.len(), &"64 lumps"))
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct lump_t {
     pub fileofs: i32,     // 0x0
     pub filelen: i32,     // 0x4
@@ -81,14 +225,32 @@ pub struct lump_t {
     pub four_cc: [u8; 4], // 0xC
 } //Size=0x10
 
+impl lump_t {
+    /// Compressed lumps store their uncompressed size in `four_cc`, it is zero otherwise.
+    pub fn is_compressed(&self) -> bool {
+        self.four_cc != [0; 4]
+    }
+
+    /// The uncompressed size of a compressed lump, `four_cc` is stored in file byte order.
+    pub fn uncompressed_size(&self, big_endian: bool) -> u32 {
+        if big_endian {
+            u32::from_be_bytes(self.four_cc)
+        } else {
+            u32::from_le_bytes(self.four_cc)
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct mvertex_t {
     pub position: [f32; 3], // 0x0
 } //Size=0xC
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dplane_t {
     pub normal: [f32; 3], // 0x00
     pub distance: f32,    // 0x0C
@@ -97,6 +259,7 @@ pub struct dplane_t {
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct cplane_t {
     pub normal: [f32; 3], // 0x00
     pub distance: f32,    // 0x0C
@@ -107,12 +270,14 @@ pub struct cplane_t {
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dedge_t {
     pub v: [u16; 2], // 0x0
 } //Size=0x4
 
 #[repr(C, packed)]
 #[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dleaf_t {
     pub contents: i32, // 0x00
     pub cluster: i16,  // 0x04
@@ -129,8 +294,63 @@ pub struct dleaf_t {
     pub pad0: [u8; 2],           //
 } //Size=0x20
 
+/// Leaf layout of version 0 leaf lumps (bsp v19 and older) with embedded ambient lighting.
+#[repr(C, packed)]
+#[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct dleaf_v0_t {
+    pub contents: i32,                         // 0x00
+    pub cluster: i16,                          // 0x04
+    pub area_flags: u16,                       // 0x06
+    pub mins: [i16; 3],                        // 0x08
+    pub maxs: [i16; 3],                        // 0x0e
+    pub first_leaf_face: u16,                  // 0x14
+    pub num_leaf_faces: u16,                   // 0x16
+    pub first_leaf_brush: u16,                 // 0x18
+    pub num_leaf_brushes: u16,                 // 0x1a
+    pub feaf_water_data_id: i16,               // 0x1c
+    pub ambient_lighting: CompressedLightCube, // 0x1e
+    pub pad0: [u8; 2],                         //
+} //Size=0x38
+
+impl From<dleaf_v0_t> for dleaf_t {
+    fn from(leaf: dleaf_v0_t) -> Self {
+        Self {
+            contents: leaf.contents,
+            cluster: leaf.cluster,
+            area_flags: leaf.area_flags,
+            mins: leaf.mins,
+            maxs: leaf.maxs,
+            first_leaf_face: leaf.first_leaf_face,
+            num_leaf_faces: leaf.num_leaf_faces,
+            first_leaf_brush: leaf.first_leaf_brush,
+            num_leaf_brushes: leaf.num_leaf_brushes,
+            feaf_water_data_id: leaf.feaf_water_data_id,
+            pad0: [0u8; 2],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ColorRGBExp32 {
+    pub r: u8,        // 0x0
+    pub g: u8,        // 0x1
+    pub b: u8,        // 0x2
+    pub exponent: i8, // 0x3
+} //Size=0x4
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompressedLightCube {
+    pub color: [ColorRGBExp32; 6], // 0x00
+} //Size=0x18
+
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dnode_t {
     pub plane_num: i32,     // 0x00
     pub children: [i32; 2], // 0x04
@@ -144,6 +364,7 @@ pub struct dnode_t {
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct snode_t {
     pub plane_num: i32,         // 0x00
     pub plane_idx: u32,         // 0x04 - cplane_t*
@@ -160,6 +381,7 @@ pub struct snode_t {
 
 #[repr(C, packed)]
 #[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dface_t {
     pub plane_num: u16,                            // 0x00
     pub side: u8,                                  // 0x02
@@ -181,8 +403,68 @@ pub struct dface_t {
     pub pad0: [u8; 2],                             //
 } //Size=0x38
 
+/// Face layout of bsp v17 (Vampire: The Masquerade - Bloodlines).
+///
+/// Bloodlines stores the average light color of all 8 light styles in front of the face and
+/// carries separate day and night styles, the primitive fields are gone and the smoothing
+/// groups are widened to 32 bits. Follows `DFaceVTMB` of bspsrc.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct dface_v17_t {
+    pub avg_light_color: [ColorRGBExp32; 8],       // 0x00
+    pub plane_num: u16,                            // 0x20
+    pub side: u8,                                  // 0x22
+    pub on_node: u8,                               // 0x23
+    pub first_edge: i32,                           // 0x24
+    pub num_edges: i16,                            // 0x28
+    pub tex_info: i16,                             // 0x2A
+    pub disp_info: i16,                            // 0x2C
+    pub surface_fog_volume_id: i16,                // 0x2E
+    pub styles: [u8; 8],                           // 0x30
+    pub day_styles: [u8; 8],                       // 0x38
+    pub night_styles: [u8; 8],                     // 0x40
+    pub light_ofs: i32,                            // 0x48
+    pub area: f32,                                 // 0x4C
+    pub lightmap_texture_mins_in_luxels: [i32; 2], // 0x50
+    pub lightmap_texture_size_in_luxels: [i32; 2], // 0x58
+    pub orig_face: i32,                            // 0x60
+    pub smoothing_groups: u32,                     // 0x64
+} //Size=0x68
+
+impl From<dface_v17_t> for dface_t {
+    fn from(face: dface_v17_t) -> Self {
+        Self {
+            plane_num: face.plane_num,
+            side: face.side,
+            on_node: face.on_node,
+            first_edge: face.first_edge,
+            num_edges: face.num_edges,
+            tex_info: face.tex_info,
+            disp_info: face.disp_info,
+            surface_fog_volume_id: face.surface_fog_volume_id,
+            styles: [
+                face.styles[0],
+                face.styles[1],
+                face.styles[2],
+                face.styles[3],
+            ],
+            light_ofs: face.light_ofs,
+            area: face.area,
+            lightmap_texture_mins_in_luxels: face.lightmap_texture_mins_in_luxels,
+            lightmap_texture_size_in_luxels: face.lightmap_texture_size_in_luxels,
+            orig_face: face.orig_face,
+            num_prims: 0,
+            first_prim_id: 0,
+            smoothing_groups: face.smoothing_groups as u16,
+            pad0: [0u8; 2],
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct texinfo_t {
     pub texture_vecs: [[f32; 4]; 2],  // 0x00
     pub lightmap_vecs: [[f32; 4]; 2], // 0x20
@@ -192,6 +474,7 @@ pub struct texinfo_t {
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dbrush_t {
     pub first_side: i32, // 0x0
     pub num_sides: i32,  // 0x4
@@ -200,6 +483,7 @@ pub struct dbrush_t {
 
 #[repr(C)]
 #[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct dbrushside_t {
     pub plane_num: u16, // 0x0
     pub tex_info: i16,  // 0x2
@@ -208,11 +492,72 @@ pub struct dbrushside_t {
     pub thin: u8,       // 0x7
 } //Size=0x8
 
+/// A brush model, model 0 is the world and `*N` models belong to brush entities.
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct dmodel_t {
+    pub mins: [f32; 3],   // 0x00
+    pub maxs: [f32; 3],   // 0x0C
+    pub origin: [f32; 3], // 0x18 - for sounds or lights
+    pub head_node: i32,   // 0x24
+    pub first_face: i32,  // 0x28
+    pub num_faces: i32,   // 0x2C
+} //Size=0x30
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct lumpfileheader_t {
+    pub lump_offset: i32,  // 0x00
+    pub lump_id: i32,      // 0x04
+    pub lump_version: i32, // 0x08
+    pub lump_length: i32,  // 0x0C
+    pub map_revision: i32, // 0x10
+} //Size=0x14
+
+#[repr(C, packed)]
+#[derive(Clone, Debug, Copy, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct lzma_header_t {
+    pub id: u32,             // 0x00
+    pub actual_size: u32,    // 0x04
+    pub lzma_size: u32,      // 0x08
+    pub properties: [u8; 5], // 0x0C
+} //Size=0x11
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct dgamelumpheader_t {
+    pub lump_count: i32, // 0x0
+} //Size=0x4
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct dgamelump_t {
+    pub id: i32,      // 0x0
+    pub flags: u16,   // 0x4
+    pub version: u16, // 0x6
+    pub fileofs: i32, // 0x8
+    pub filelen: i32, // 0xC
+} //Size=0x10
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::mem::size_of;
 
+    #[test]
+    fn test_lump_index() {
+        for (i, index) in LumpIndex::ALL.iter().enumerate() {
+            assert_eq!(*index as usize, i);
+        }
+        assert_eq!(LumpIndex::from_index(35), Some(LumpIndex::GameLump));
+        assert_eq!(LumpIndex::from_index(HEADER_LUMP_COUNT), None);
+    }
+
     #[test]
     fn test_struct_sizes() {
         assert_eq!(size_of::<dheader_t>(), 0x40C);
@@ -225,12 +570,21 @@ mod tests {
         assert_eq!(size_of::<dedge_t>(), 0x4);
 
         assert_eq!(size_of::<dleaf_t>(), 0x20);
+        assert_eq!(size_of::<dleaf_v0_t>(), 0x38);
+        assert_eq!(size_of::<CompressedLightCube>(), 0x18);
         assert_eq!(size_of::<dnode_t>(), 0x20);
         //assert_eq!(size_of::<snode_t>(), 0x2C); // only used internally
         assert_eq!(size_of::<dface_t>(), 0x38);
+        assert_eq!(size_of::<dface_v17_t>(), 0x68);
 
         assert_eq!(size_of::<texinfo_t>(), 0x48);
         assert_eq!(size_of::<dbrush_t>(), 0xC);
         assert_eq!(size_of::<dbrushside_t>(), 0x8);
+        assert_eq!(size_of::<dmodel_t>(), 0x30);
+
+        assert_eq!(size_of::<lumpfileheader_t>(), 0x14);
+        assert_eq!(size_of::<lzma_header_t>(), 0x11);
+        assert_eq!(size_of::<dgamelumpheader_t>(), 0x4);
+        assert_eq!(size_of::<dgamelump_t>(), 0x10);
     }
 }
@@ -0,0 +1,268 @@
+use super::native::*;
+
+use std::mem::size_of;
+
+/// Order of the fields inside of each `lump_t` in the bsp header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LumpLayout {
+    /// Detect the layout by checking which interpretation yields plausible lump offsets.
+    Detect,
+    /// `fileofs, filelen, version, four_cc`
+    Standard,
+    /// `version, fileofs, filelen, four_cc` as used by left 4 dead 2 and derived branches.
+    Left4Dead2,
+}
+
+/// Selects which lumps (and the data derived from them) are loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadProfile {
+    /// Only the bsp tree and brushes needed for traces against the world.
+    Collision,
+    /// The bsp tree together with the face geometry, polygons and game lumps.
+    Render,
+    /// All lumps, including lumps this crate does not parse (see `BSP::raw_lump`).
+    Everything,
+}
+
+impl LoadProfile {
+    const COLLISION_LUMPS: &'static [LumpIndex] = &[
+        LumpIndex::Planes,
+        LumpIndex::Nodes,
+        LumpIndex::Leafs,
+        LumpIndex::LeafBrushes,
+        LumpIndex::Brushes,
+        LumpIndex::BrushSides,
+        LumpIndex::Models,
+    ];
+
+    const RENDER_LUMPS: &'static [LumpIndex] = &[
+        LumpIndex::Planes,
+        LumpIndex::Nodes,
+        LumpIndex::Leafs,
+        LumpIndex::LeafFaces,
+        LumpIndex::Vertexes,
+        LumpIndex::Edges,
+        LumpIndex::SurfEdges,
+        LumpIndex::Faces,
+        LumpIndex::TexInfo,
+        LumpIndex::GameLump,
+        LumpIndex::Models,
+    ];
+
+    /// Returns true if the lump is loaded with this profile.
+    pub fn includes(self, index: LumpIndex) -> bool {
+        match self {
+            LoadProfile::Collision => Self::COLLISION_LUMPS.contains(&index),
+            LoadProfile::Render => Self::RENDER_LUMPS.contains(&index),
+            LoadProfile::Everything => true,
+        }
+    }
+}
+
+/// The default limit of `LoadOptions::max_unpacked_size` as a multiple of the map file size.
+pub const DEFAULT_UNPACK_FACTOR: u64 = 32;
+
+/// Options that control how a bsp file is loaded.
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    pub lump_layout: LumpLayout,
+    /// Apply `<mapname>_l_<n>.lmp` lump files found next to the map.
+    pub lump_overrides: bool,
+    /// Load maps with invalid cross-lump references instead of failing,
+    /// the invalid references are skipped and reported in `BSP::validation`.
+    pub lenient: bool,
+    /// Lumps that are not part of the profile are left empty.
+    pub profile: LoadProfile,
+    /// Compute the engine map crc (`BSP::map_crc`) while loading.
+    pub map_crc: bool,
+    /// Total number of bytes all compressed lumps and game lumps may unpack to.
+    ///
+    /// `None` allows `DEFAULT_UNPACK_FACTOR` times the size of the map file.
+    pub max_unpacked_size: Option<u64>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            lump_layout: LumpLayout::Detect,
+            lump_overrides: false,
+            lenient: false,
+            profile: LoadProfile::Everything,
+            map_crc: false,
+            max_unpacked_size: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lump_layout(mut self, lump_layout: LumpLayout) -> Self {
+        self.lump_layout = lump_layout;
+        self
+    }
+
+    pub fn lump_overrides(mut self, lump_overrides: bool) -> Self {
+        self.lump_overrides = lump_overrides;
+        self
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    pub fn profile(mut self, profile: LoadProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn map_crc(mut self, map_crc: bool) -> Self {
+        self.map_crc = map_crc;
+        self
+    }
+
+    pub fn max_unpacked_size(mut self, max_unpacked_size: u64) -> Self {
+        self.max_unpacked_size = Some(max_unpacked_size);
+        self
+    }
+
+    /// The number of bytes compressed lumps may unpack to for a map file of the given size.
+    pub(crate) fn unpack_budget(&self, file_len: u64) -> u64 {
+        self.max_unpacked_size
+            .unwrap_or_else(|| file_len.saturating_mul(DEFAULT_UNPACK_FACTOR))
+    }
+}
+
+/// Rewrites all lumps of the header into the standard field order.
+///
+/// Returns the layout the header was stored in.
+pub(crate) fn normalize_lump_layout(
+    header: &mut dheader_t,
+    file_len: u64,
+    layout: LumpLayout,
+) -> LumpLayout {
+    let layout = match layout {
+        LumpLayout::Detect => detect_lump_layout(header, file_len),
+        layout => layout,
+    };
+
+    if layout == LumpLayout::Left4Dead2 {
+        for lump in header.lumps.iter_mut() {
+            *lump = swizzle_l4d2(lump);
+        }
+    }
+
+    layout
+}
+
+/// Rewrites all lumps of a normalized header back into the given layout.
+pub(crate) fn denormalize_lump_layout(header: &mut dheader_t, layout: LumpLayout) {
+    if layout == LumpLayout::Left4Dead2 {
+        for lump in header.lumps.iter_mut() {
+            *lump = lump_t {
+                fileofs: lump.version,
+                filelen: lump.fileofs,
+                version: lump.filelen,
+                four_cc: lump.four_cc,
+            };
+        }
+    }
+}
+
+fn swizzle_l4d2(lump: &lump_t) -> lump_t {
+    lump_t {
+        fileofs: lump.filelen,
+        filelen: lump.version,
+        version: lump.fileofs,
+        four_cc: lump.four_cc,
+    }
+}
+
+/// Prefers the standard layout, the left 4 dead 2 reading is only used if the standard one
+/// places a lump outside of the file.
+///
+/// Lump order can not be used to decide, compilers do not write the lumps in index order.
+fn detect_lump_layout(header: &dheader_t, file_len: u64) -> LumpLayout {
+    if lumps_in_bounds(header.lumps.iter().cloned(), file_len) {
+        LumpLayout::Standard
+    } else if lumps_in_bounds(header.lumps.iter().map(swizzle_l4d2), file_len) {
+        LumpLayout::Left4Dead2
+    } else {
+        LumpLayout::Standard
+    }
+}
+
+/// Returns true if all non-empty lumps lie within the file behind the header.
+fn lumps_in_bounds<I: Iterator<Item = lump_t>>(lumps: I, file_len: u64) -> bool {
+    lumps.filter(|l| l.filelen != 0).all(|lump| {
+        let ofs = lump.fileofs as i64;
+        let len = lump.filelen as i64;
+        ofs >= size_of::<dheader_t>() as i64 && len >= 0 && (ofs + len) as u64 <= file_len
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(lumps: &[(i32, i32, i32)]) -> dheader_t {
+        let mut header = dheader_t::default();
+        for (i, &(fileofs, filelen, version)) in lumps.iter().enumerate() {
+            header.lumps[i] = lump_t {
+                fileofs,
+                filelen,
+                version,
+                four_cc: [0; 4],
+            };
+        }
+        header
+    }
+
+    #[test]
+    fn detect_standard() {
+        let mut header = header(&[(0x40C, 16, 0), (0x41C, 20, 1)]);
+        let layout = normalize_lump_layout(&mut header, 0x430, LumpLayout::Detect);
+        assert_eq!(layout, LumpLayout::Standard);
+        assert_eq!(header.lumps[1].fileofs, 0x41C);
+    }
+
+    #[test]
+    fn detect_unordered_standard() {
+        // the left 4 dead 2 reading is in bounds too as it skips both lumps (version 0)
+        let mut header = header(&[(0x41C, 16, 0), (0x40C, 16, 0)]);
+        let layout = normalize_lump_layout(&mut header, 0x42C, LumpLayout::Detect);
+        assert_eq!(layout, LumpLayout::Standard);
+        assert_eq!(header.lumps[0].fileofs, 0x41C);
+        assert_eq!(header.lumps[1].fileofs, 0x40C);
+    }
+
+    #[test]
+    fn detect_l4d2() {
+        let mut header = header(&[(0, 0x40C, 16), (1, 0x41C, 20)]);
+        let layout = normalize_lump_layout(&mut header, 0x430, LumpLayout::Detect);
+        assert_eq!(layout, LumpLayout::Left4Dead2);
+        assert_eq!(header.lumps[1].fileofs, 0x41C);
+        assert_eq!(header.lumps[1].filelen, 20);
+        assert_eq!(header.lumps[1].version, 1);
+    }
+
+    #[test]
+    fn profiles() {
+        assert!(LoadProfile::Collision.includes(LumpIndex::BrushSides));
+        assert!(!LoadProfile::Collision.includes(LumpIndex::Faces));
+        assert!(LoadProfile::Render.includes(LumpIndex::Faces));
+        assert!(!LoadProfile::Render.includes(LumpIndex::PakFile));
+        assert!(LoadProfile::Everything.includes(LumpIndex::PakFile));
+    }
+
+    #[test]
+    fn explicit_layout() {
+        let mut header = header(&[(0x40C, 16, 0)]);
+        let layout = normalize_lump_layout(&mut header, 0x41C, LumpLayout::Left4Dead2);
+        assert_eq!(layout, LumpLayout::Left4Dead2);
+        assert_eq!(header.lumps[0].fileofs, 16);
+    }
+}
@@ -1,9 +1,13 @@
 use super::math::*;
 use super::native::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 pub const MAX_SURFINFO_VERTS: usize = 32;
 
 #[derive(Clone, Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Polygon {
     pub verts: [[f32; 3]; MAX_SURFINFO_VERTS],
     pub vert_num: usize,
@@ -49,6 +53,7 @@ impl Polygon {
 }
 
 #[derive(Clone, Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Plane {
     pub origin: [f32; 3],
     pub distance: f32,
@@ -0,0 +1,341 @@
+use super::native::*;
+use super::options::LoadProfile;
+use crate::error::*;
+
+/// A reference from an element of one lump into another lump that does not exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidReference {
+    /// The lump that contains the reference.
+    pub lump: LumpIndex,
+    /// Index of the element inside of `lump` that contains the reference.
+    pub element: usize,
+    /// The lump that is referenced.
+    pub target: LumpIndex,
+    /// The first index into `target` that does not exist (or can not be referenced).
+    pub index: i64,
+}
+
+/// Returns true if all child nodes of node `num` are stored after it.
+///
+/// Compilers always write child nodes after their parent, a backwards reference forms a cycle
+/// and walking the tree would loop forever. Negative children are leafs and always valid.
+pub(crate) fn children_follow_parent(num: i32, children: &[i32]) -> bool {
+    children.iter().all(|&child| child < 0 || child > num)
+}
+
+/// Result of checking all cross-lump references of a map.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub invalid_references: Vec<InvalidReference>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_references.is_empty()
+    }
+
+    /// Turns the first invalid reference into an `InvalidIndex` error.
+    pub fn check(&self) -> Result<()> {
+        match self.invalid_references.first() {
+            Some(reference) => Err(Error::InvalidIndex {
+                lump: reference.target,
+                index: reference.index,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The lumps that take part in the validation.
+pub(crate) struct Lumps<'a> {
+    /// References into lumps that are not part of the profile are not checked.
+    pub profile: LoadProfile,
+    pub vertexes: usize,
+    pub planes: usize,
+    pub edges: &'a [dedge_t],
+    pub surf_edges: &'a [i32],
+    pub nodes: &'a [dnode_t],
+    pub leaves: &'a [dleaf_t],
+    pub faces: &'a [dface_t],
+    pub brushes: &'a [dbrush_t],
+    pub brush_sides: &'a [dbrushside_t],
+    pub leaf_faces: &'a [u16],
+    pub leaf_brushes: &'a [u16],
+    pub models: &'a [dmodel_t],
+}
+
+/// Checks every reference between the given lumps without failing on the first error.
+pub(crate) fn validate(lumps: &Lumps) -> ValidationReport {
+    let mut v = Validator {
+        profile: lumps.profile,
+        report: ValidationReport::default(),
+    };
+
+    for (i, face) in lumps.faces.iter().enumerate() {
+        v.index(
+            LumpIndex::Faces,
+            i,
+            LumpIndex::Planes,
+            face.plane_num as i64,
+            lumps.planes,
+        );
+        v.range(
+            LumpIndex::Faces,
+            i,
+            LumpIndex::SurfEdges,
+            face.first_edge as i64,
+            face.num_edges as i64,
+            lumps.surf_edges.len(),
+        );
+    }
+
+    for (i, &edge) in lumps.surf_edges.iter().enumerate() {
+        let edge = (edge as i64).abs();
+        v.index(
+            LumpIndex::SurfEdges,
+            i,
+            LumpIndex::Edges,
+            edge,
+            lumps.edges.len(),
+        );
+    }
+
+    for (i, edge) in lumps.edges.iter().enumerate() {
+        for &vertex in edge.v.iter() {
+            v.index(
+                LumpIndex::Edges,
+                i,
+                LumpIndex::Vertexes,
+                vertex as i64,
+                lumps.vertexes,
+            );
+        }
+    }
+
+    for (i, node) in lumps.nodes.iter().enumerate() {
+        v.index(
+            LumpIndex::Nodes,
+            i,
+            LumpIndex::Planes,
+            node.plane_num as i64,
+            lumps.planes,
+        );
+        for &child in node.children.iter() {
+            if child >= 0 {
+                if !children_follow_parent(i as i32, &[child]) {
+                    v.invalid(LumpIndex::Nodes, i, LumpIndex::Nodes, child as i64);
+                }
+                v.index(
+                    LumpIndex::Nodes,
+                    i,
+                    LumpIndex::Nodes,
+                    child as i64,
+                    lumps.nodes.len(),
+                );
+            } else {
+                let leaf = -1 - child as i64;
+                v.index(
+                    LumpIndex::Nodes,
+                    i,
+                    LumpIndex::Leafs,
+                    leaf,
+                    lumps.leaves.len(),
+                );
+            }
+        }
+    }
+
+    for (i, leaf) in lumps.leaves.iter().enumerate() {
+        v.range(
+            LumpIndex::Leafs,
+            i,
+            LumpIndex::LeafFaces,
+            leaf.first_leaf_face as i64,
+            leaf.num_leaf_faces as i64,
+            lumps.leaf_faces.len(),
+        );
+        v.range(
+            LumpIndex::Leafs,
+            i,
+            LumpIndex::LeafBrushes,
+            leaf.first_leaf_brush as i64,
+            leaf.num_leaf_brushes as i64,
+            lumps.leaf_brushes.len(),
+        );
+    }
+
+    for (i, &face) in lumps.leaf_faces.iter().enumerate() {
+        v.index(
+            LumpIndex::LeafFaces,
+            i,
+            LumpIndex::Faces,
+            face as i64,
+            lumps.faces.len(),
+        );
+    }
+
+    for (i, &brush) in lumps.leaf_brushes.iter().enumerate() {
+        let count = lumps.brushes.len();
+        v.index(
+            LumpIndex::LeafBrushes,
+            i,
+            LumpIndex::Brushes,
+            brush as i64,
+            count,
+        );
+    }
+
+    for (i, brush) in lumps.brushes.iter().enumerate() {
+        v.range(
+            LumpIndex::Brushes,
+            i,
+            LumpIndex::BrushSides,
+            brush.first_side as i64,
+            brush.num_sides as i64,
+            lumps.brush_sides.len(),
+        );
+    }
+
+    for (i, side) in lumps.brush_sides.iter().enumerate() {
+        let plane = side.plane_num as i64;
+        v.index(
+            LumpIndex::BrushSides,
+            i,
+            LumpIndex::Planes,
+            plane,
+            lumps.planes,
+        );
+    }
+
+    for (i, model) in lumps.models.iter().enumerate() {
+        v.index(
+            LumpIndex::Models,
+            i,
+            LumpIndex::Nodes,
+            model.head_node as i64,
+            lumps.nodes.len(),
+        );
+        v.range(
+            LumpIndex::Models,
+            i,
+            LumpIndex::Faces,
+            model.first_face as i64,
+            model.num_faces as i64,
+            lumps.faces.len(),
+        );
+    }
+
+    v.report
+}
+
+struct Validator {
+    profile: LoadProfile,
+    report: ValidationReport,
+}
+
+impl Validator {
+    fn index(
+        &mut self,
+        lump: LumpIndex,
+        element: usize,
+        target: LumpIndex,
+        index: i64,
+        len: usize,
+    ) {
+        if !self.profile.includes(target) {
+            return;
+        }
+
+        if index < 0 || index >= len as i64 {
+            self.invalid(lump, element, target, index);
+        }
+    }
+
+    /// Checks that `first..first + count` lies within the target lump.
+    fn range(
+        &mut self,
+        lump: LumpIndex,
+        element: usize,
+        target: LumpIndex,
+        first: i64,
+        count: i64,
+        len: usize,
+    ) {
+        if count <= 0 || !self.profile.includes(target) {
+            return;
+        }
+
+        if first < 0 {
+            self.invalid(lump, element, target, first);
+        } else if first + count > len as i64 {
+            self.invalid(lump, element, target, first.max(len as i64));
+        }
+    }
+
+    fn invalid(&mut self, lump: LumpIndex, element: usize, target: LumpIndex, index: i64) {
+        self.report.invalid_references.push(InvalidReference {
+            lump,
+            element,
+            target,
+            index,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::super::*;
+
+    use dataview::PodMethods;
+
+    #[test]
+    fn valid_map() {
+        let view = BSPView::new(MapBuilder::box_room().build()).unwrap();
+        let report = view.validate();
+        assert!(report.is_valid());
+        assert!(report.check().is_ok());
+    }
+
+    #[test]
+    fn invalid_references() {
+        let mut node = dnode_t::zeroed();
+        node.plane_num = 7;
+        node.children = [0, -3];
+        let brush = dbrush_t {
+            first_side: 4,
+            num_sides: 6,
+            contents: 1,
+        };
+        let builder = MapBuilder::box_room()
+            .lump(LumpIndex::Nodes, &[node][..])
+            .lump(LumpIndex::Brushes, &[brush][..])
+            .lump(LumpIndex::LeafBrushes, &[3u16][..]);
+
+        let view = BSPView::new(builder.build()).unwrap();
+        let report = view.validate();
+        let reference = |lump, target, index| InvalidReference {
+            lump,
+            element: 0,
+            target,
+            index,
+        };
+        assert_eq!(
+            report.invalid_references,
+            vec![
+                reference(LumpIndex::Nodes, LumpIndex::Planes, 7),
+                reference(LumpIndex::Nodes, LumpIndex::Nodes, 0),
+                reference(LumpIndex::Nodes, LumpIndex::Leafs, 2),
+                reference(LumpIndex::LeafBrushes, LumpIndex::Brushes, 3),
+                reference(LumpIndex::Brushes, LumpIndex::BrushSides, 6),
+            ]
+        );
+        assert!(matches!(
+            report.check(),
+            Err(Error::InvalidIndex {
+                lump: LumpIndex::Planes,
+                index: 7,
+            })
+        ));
+    }
+}
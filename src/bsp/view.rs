@@ -0,0 +1,340 @@
+use super::*;
+
+use std::sync::OnceLock;
+
+use dataview::{Pod, PodMethods};
+
+/// A memory-mapped bsp file.
+#[cfg(feature = "mmap")]
+pub type MappedBSP = BSPView<memmap2::Mmap>;
+
+/// Zero-copy view over a bsp held in memory.
+///
+/// Raw lumps are borrowed directly from the underlying buffer,
+/// derived data (planes, nodes and polygons) is only computed on first access.
+/// Version 0 leafs are converted into the version 1 layout on first access as well.
+pub struct BSPView<D> {
+    data: D,
+    header: dheader_t,
+    leaf_v0: bool,
+    leaves: OnceLock<Vec<dleaf_t>>,
+    planes: OnceLock<Vec<cplane_t>>,
+    nodes: OnceLock<Vec<snode_t>>,
+    polys: OnceLock<Vec<Polygon>>,
+}
+
+impl<D: AsRef<[u8]>> BSPView<D> {
+    /// Validates the header and the placement of all lumps exposed by the view.
+    pub fn new(data: D) -> Result<Self> {
+        let bytes = data.as_ref();
+        if bytes.len() < size_of::<dheader_t>() {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        let mut header = dheader_t::default();
+        header
+            .as_bytes_mut()
+            .copy_from_slice(&bytes[..size_of::<dheader_t>()]);
+        if header.ident == HEADER_MAGIC_BE {
+            return Err(Error::Unsupported(
+                "big-endian maps can not be viewed in place",
+            ));
+        }
+        check_header(&header)?;
+        if header.version == 17 {
+            return Err(Error::Unsupported(
+                "version 17 faces can not be viewed in place",
+            ));
+        }
+        normalize_lump_layout(&mut header, bytes.len() as u64, LumpLayout::Detect);
+        check_lump_placement(&header, bytes.len() as u64)?;
+
+        let leaf_v0 = is_leaf_v0(
+            &header,
+            header.lumps[LumpIndex::Leafs as usize].filelen.max(0) as usize,
+        );
+        let view = Self {
+            data,
+            header,
+            leaf_v0,
+            leaves: OnceLock::new(),
+            planes: OnceLock::new(),
+            nodes: OnceLock::new(),
+            polys: OnceLock::new(),
+        };
+
+        view.try_lump::<mvertex_t>(LumpIndex::Vertexes)?;
+        view.try_lump::<dplane_t>(LumpIndex::Planes)?;
+        view.try_lump::<dedge_t>(LumpIndex::Edges)?;
+        view.try_lump::<i32>(LumpIndex::SurfEdges)?;
+        if leaf_v0 {
+            view.try_lump::<dleaf_v0_t>(LumpIndex::Leafs)?;
+        } else {
+            view.try_lump::<dleaf_t>(LumpIndex::Leafs)?;
+        }
+        view.try_lump::<dnode_t>(LumpIndex::Nodes)?;
+        view.try_lump::<dface_t>(LumpIndex::Faces)?;
+        view.try_lump::<texinfo_t>(LumpIndex::TexInfo)?;
+        view.try_lump::<dbrush_t>(LumpIndex::Brushes)?;
+        view.try_lump::<dbrushside_t>(LumpIndex::BrushSides)?;
+        view.try_lump::<u16>(LumpIndex::LeafFaces)?;
+        view.try_lump::<u16>(LumpIndex::LeafBrushes)?;
+        view.try_lump::<dmodel_t>(LumpIndex::Models)?;
+
+        Ok(view)
+    }
+
+    fn try_lump<T: Pod>(&self, index: LumpIndex) -> Result<&[T]> {
+        let lump = &self.header.lumps[index as usize];
+        let out_of_bounds = || Error::LumpOutOfBounds {
+            lump: index,
+            offset: lump.fileofs as i64,
+            len: lump.filelen as i64,
+        };
+        if lump.fileofs < 0 || lump.filelen < 0 {
+            return Err(out_of_bounds());
+        }
+
+        let bytes = self
+            .data
+            .as_ref()
+            .get(lump.fileofs as usize..)
+            .and_then(|data| data.get(..lump.filelen as usize))
+            .ok_or_else(out_of_bounds)?;
+        if lump.is_compressed() {
+            return Err(Error::Unsupported(
+                "compressed lumps can not be viewed in place",
+            ));
+        }
+        if !bytes.len().is_multiple_of(size_of::<T>()) {
+            return Err(Error::LumpSizeMismatch {
+                lump: index,
+                len: bytes.len(),
+                element_size: size_of::<T>(),
+            });
+        }
+
+        bytes
+            .as_data_view()
+            .try_slice(0, bytes.len() / size_of::<T>())
+            .ok_or(Error::LumpMisaligned { lump: index })
+    }
+
+    fn lump<T: Pod>(&self, lump: LumpIndex) -> &[T] {
+        // all lumps are validated in `BSPView::new`
+        self.try_lump(lump).unwrap_or_default()
+    }
+
+    pub fn header(&self) -> &dheader_t {
+        &self.header
+    }
+
+    pub fn vertexes(&self) -> &[mvertex_t] {
+        self.lump(LumpIndex::Vertexes)
+    }
+
+    pub fn dplanes(&self) -> &[dplane_t] {
+        self.lump(LumpIndex::Planes)
+    }
+
+    pub fn edges(&self) -> &[dedge_t] {
+        self.lump(LumpIndex::Edges)
+    }
+
+    pub fn surf_edges(&self) -> &[i32] {
+        self.lump(LumpIndex::SurfEdges)
+    }
+
+    /// Leafs in the version 1 layout, version 0 leafs are converted on first access.
+    pub fn leaves(&self) -> &[dleaf_t] {
+        if !self.leaf_v0 {
+            return self.lump(LumpIndex::Leafs);
+        }
+
+        self.leaves.get_or_init(|| {
+            self.lump::<dleaf_v0_t>(LumpIndex::Leafs)
+                .iter()
+                .map(|l| dleaf_t::from(*l))
+                .collect()
+        })
+    }
+
+    pub fn dnodes(&self) -> &[dnode_t] {
+        self.lump(LumpIndex::Nodes)
+    }
+
+    pub fn faces(&self) -> &[dface_t] {
+        self.lump(LumpIndex::Faces)
+    }
+
+    pub fn tex_info(&self) -> &[texinfo_t] {
+        self.lump(LumpIndex::TexInfo)
+    }
+
+    pub fn brushes(&self) -> &[dbrush_t] {
+        self.lump(LumpIndex::Brushes)
+    }
+
+    pub fn brush_sides(&self) -> &[dbrushside_t] {
+        self.lump(LumpIndex::BrushSides)
+    }
+
+    pub fn leaf_faces(&self) -> &[u16] {
+        self.lump(LumpIndex::LeafFaces)
+    }
+
+    pub fn leaf_brushes(&self) -> &[u16] {
+        self.lump(LumpIndex::LeafBrushes)
+    }
+
+    pub fn models(&self) -> &[dmodel_t] {
+        self.lump(LumpIndex::Models)
+    }
+
+    /// Checks all cross-lump references of the map.
+    pub fn validate(&self) -> ValidationReport {
+        validate(&Lumps {
+            profile: LoadProfile::Everything,
+            vertexes: self.vertexes().len(),
+            planes: self.dplanes().len(),
+            edges: self.edges(),
+            surf_edges: self.surf_edges(),
+            nodes: self.dnodes(),
+            leaves: self.leaves(),
+            faces: self.faces(),
+            brushes: self.brushes(),
+            brush_sides: self.brush_sides(),
+            leaf_faces: self.leaf_faces(),
+            leaf_brushes: self.leaf_brushes(),
+            models: self.models(),
+        })
+    }
+
+    /// Planes with precomputed sign bits, computed on first access.
+    pub fn planes(&self) -> Result<&[cplane_t]> {
+        lazy(&self.planes, || parse_planes(self.dplanes()))
+    }
+
+    /// Nodes with resolved child indices, computed on first access.
+    pub fn nodes(&self) -> Result<&[snode_t]> {
+        lazy(&self.nodes, || parse_nodes(self.dnodes()))
+    }
+
+    /// Pre-processed polygons of all textured faces, computed on first access.
+    pub fn polys(&self) -> Result<&[Polygon]> {
+        let planes = self.planes()?;
+        lazy(&self.polys, || {
+            parse_polygons(
+                self.faces(),
+                self.surf_edges(),
+                self.edges(),
+                self.vertexes(),
+                planes,
+            )
+            .map(|(polys, _)| polys)
+        })
+    }
+}
+
+fn lazy<T, F>(cell: &OnceLock<Vec<T>>, init: F) -> Result<&[T]>
+where
+    F: FnOnce() -> Result<Vec<T>>,
+{
+    if let Some(value) = cell.get() {
+        return Ok(value);
+    }
+
+    let value = init()?;
+    Ok(cell.get_or_init(|| value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::*;
+
+    #[test]
+    fn view_lumps() {
+        let view = BSPView::new(MapBuilder::box_room().build()).unwrap();
+        assert_eq!(view.vertexes().len(), 4);
+        assert_eq!(view.dplanes().len(), 7);
+        assert_eq!(view.leaves().len(), 2);
+        assert_eq!(view.brush_sides().len(), 6);
+        assert_eq!(view.leaf_brushes(), &[0u16][..]);
+    }
+
+    #[test]
+    fn view_matches_bsp() {
+        let data = MapBuilder::box_room().build();
+        let map = BSP::from_bytes(&data).unwrap();
+        let view = BSPView::new(&data[..]).unwrap();
+
+        let planes = view.planes().unwrap();
+        assert_eq!(planes.len(), map.planes.len());
+        for (a, b) in planes.iter().zip(map.planes.iter()) {
+            assert_eq!(a.as_bytes(), b.as_bytes());
+        }
+        assert_eq!(view.nodes().unwrap()[0].children, map.nodes[0].children);
+        assert_eq!(view.polys().unwrap().len(), map.polys.len());
+    }
+
+    #[test]
+    fn view_leaf_v0() {
+        let leaves = [dleaf_v0_t::zeroed(), dleaf_v0_t::zeroed()];
+        let builder = MapBuilder::box_room()
+            .lump(LumpIndex::Leafs, &leaves[..])
+            .lump_version(LumpIndex::Leafs, 0);
+        let view = BSPView::new(builder.build()).unwrap();
+        assert_eq!(view.leaves().len(), 2);
+    }
+
+    #[test]
+    fn view_compressed() {
+        let builder = MapBuilder::box_room().compressed(LumpIndex::Planes);
+        assert!(BSPView::new(builder.build()).is_err());
+    }
+
+    #[test]
+    fn view_size_mismatch() {
+        let builder = MapBuilder::box_room().lump(LumpIndex::Vertexes, &[0u8; 13][..]);
+        let data = builder.build();
+        assert!(BSP::from_bytes(&data).is_err());
+        assert!(matches!(
+            BSPView::new(data),
+            Err(Error::LumpSizeMismatch {
+                lump: LumpIndex::Vertexes,
+                len: 13,
+                element_size: 12,
+            })
+        ));
+    }
+
+    #[test]
+    fn view_out_of_bounds() {
+        let mut data = MapBuilder::box_room().build();
+        data.truncate(data.len() - 4);
+        assert!(matches!(
+            BSPView::new(data),
+            Err(Error::LumpOutOfBounds {
+                lump: LumpIndex::BrushSides,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn open_mmap() {
+        let dir = std::env::temp_dir().join(format!("bsp_rs_{}_open_mmap", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("box_room.bsp");
+        std::fs::write(&path, MapBuilder::box_room().build()).unwrap();
+
+        let view = BSP::open_mmap(&path).unwrap();
+        assert_eq!(view.brushes().len(), 1);
+        assert_eq!(view.polys().unwrap().len(), 1);
+
+        drop(view);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
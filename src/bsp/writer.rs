@@ -0,0 +1,263 @@
+//! Writes modified lumps back into a bsp file.
+//!
+//! All other lumps are copied byte-for-byte in their original order, only the offsets in
+//! the header and the file-absolute offsets of the game lump directory are recomputed.
+
+use super::*;
+
+use dataview::PodMethods;
+
+/// Replaces a single lump of the bsp and returns the new file.
+///
+/// The header is written back in the byte order and lump layout of the original file.
+/// The replaced lump is stored uncompressed, lumps that were empty are appended at the end.
+pub fn replace_lump(bytes: &[u8], index: LumpIndex, data: &[u8]) -> Result<Vec<u8>> {
+    let (header, big_endian) = read_header(&mut Cursor::new(bytes), LumpLayout::Detect)?;
+    if i32::try_from(data.len()).is_err() {
+        return Err(Error::InvalidLump {
+            lump: index,
+            reason: "lump is too large",
+        });
+    }
+
+    // the layout is detected once more on the raw header so it can be written back
+    let mut raw = dheader_t::default();
+    raw.as_bytes_mut()
+        .copy_from_slice(&bytes[..size_of::<dheader_t>()]);
+    if big_endian {
+        raw.swap_bytes();
+    }
+    let layout = normalize_lump_layout(&mut raw, bytes.len() as u64, LumpLayout::Detect);
+
+    let mut order = LumpIndex::ALL
+        .iter()
+        .copied()
+        .filter(|&i| i == index || header.lump(i).filelen > 0)
+        .collect::<Vec<_>>();
+    order.sort_by_key(|&i| match header.lump(i) {
+        lump if lump.filelen == 0 => i64::MAX,
+        lump => lump.fileofs as i64,
+    });
+
+    let mut new_header = header.clone();
+    for lump in new_header.lumps.iter_mut().filter(|lump| lump.filelen == 0) {
+        lump.fileofs = 0;
+    }
+
+    let mut out = vec![0u8; size_of::<dheader_t>()];
+    for &i in order.iter() {
+        out.resize((out.len() + 3) & !3, 0);
+        let start = out.len();
+        let old = header.lump(i);
+        let lump = &mut new_header.lumps[i as usize];
+
+        if i == index {
+            out.extend_from_slice(data);
+            lump.filelen = data.len() as i32;
+            lump.four_cc = [0; 4];
+        } else {
+            let old_start = old.fileofs as usize;
+            out.extend_from_slice(&bytes[old_start..old_start + old.filelen as usize]);
+            if i == LumpIndex::GameLump {
+                relocate_game_lumps(
+                    &mut out[start..],
+                    old.fileofs as i64,
+                    start as i64 - old.fileofs as i64,
+                    big_endian,
+                )?;
+            }
+        }
+        lump.fileofs = if lump.filelen == 0 { 0 } else { start as i32 };
+    }
+
+    if i32::try_from(out.len()).is_err() {
+        return Err(Error::InvalidLump {
+            lump: index,
+            reason: "map is too large",
+        });
+    }
+
+    denormalize_lump_layout(&mut new_header, layout);
+    if big_endian {
+        new_header.swap_bytes();
+    }
+    out[..size_of::<dheader_t>()].copy_from_slice(new_header.as_bytes());
+    Ok(out)
+}
+
+/// Replaces the entity lump of the bsp and returns the new file.
+pub fn replace_entities(bytes: &[u8], entities: &[Entity]) -> Result<Vec<u8>> {
+    replace_lump(bytes, LumpIndex::Entities, &write_entities(entities)?)
+}
+
+/// Moves the offsets in the game lump directory that point into the lump by `delta`.
+fn relocate_game_lumps(lump: &mut [u8], fileofs: i64, delta: i64, big_endian: bool) -> Result<()> {
+    let header_len = size_of::<dgamelumpheader_t>();
+    if lump.len() < header_len {
+        return Ok(());
+    }
+
+    let mut game_header = dgamelumpheader_t::zeroed();
+    game_header
+        .as_bytes_mut()
+        .copy_from_slice(&lump[..header_len]);
+    if big_endian {
+        game_header.swap_bytes();
+    }
+
+    let entry_len = size_of::<dgamelump_t>();
+    let dir_len = game_header.lump_count as i64 * entry_len as i64;
+    if game_header.lump_count < 0 || dir_len > (lump.len() - header_len) as i64 {
+        return Err(Error::InvalidLump {
+            lump: LumpIndex::GameLump,
+            reason: "game lump directory exceeds the lump",
+        });
+    }
+
+    let end = fileofs + lump.len() as i64;
+    for i in 0..game_header.lump_count as usize {
+        let range = header_len + i * entry_len..header_len + (i + 1) * entry_len;
+        let mut entry = dgamelump_t::zeroed();
+        entry.as_bytes_mut().copy_from_slice(&lump[range.clone()]);
+        if big_endian {
+            entry.swap_bytes();
+        }
+
+        // offsets outside of the lump are invalid anyway and kept as they are
+        let ofs = entry.fileofs as i64;
+        if ofs >= fileofs && ofs <= end {
+            entry.fileofs = (ofs + delta) as i32;
+        }
+
+        if big_endian {
+            entry.swap_bytes();
+        }
+        lump[range].copy_from_slice(entry.as_bytes());
+    }
+    Ok(())
+}
+
+impl BSP {
+    /// Builds a `.lmp` lump file that replaces the entity lump of this map.
+    ///
+    /// Save it as `<mapname>_l_<n>.lmp` next to the map to apply it without rewriting the map.
+    pub fn entity_lump_file(&self, entities: &[Entity]) -> Result<LumpFile> {
+        Ok(LumpFile::new(
+            LumpIndex::Entities,
+            self.header.lump(LumpIndex::Entities).version,
+            self.header.map_revision,
+            &write_entities(entities)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::*;
+
+    const ENTITIES: &str = "{ \"classname\" \"worldspawn\" \"skyname\" \"sky_day01_01\" }\n\
+        { \"classname\" \"info_player_start\" \"origin\" \"0 0 0\" }\n\
+        { \"classname\" \"light\" \"origin\" \"0 0 64\" }\n\0";
+
+    fn edit(entities: &mut Vec<Entity>) {
+        entities[0].set("skyname", "sky_dust");
+        entities.remove(1);
+        let mut entity = Entity::new();
+        entity.add("classname", "prop_static");
+        entity.add("model", "models/props/crate.mdl");
+        entities.push(entity);
+    }
+
+    /// Rewrites the entity lump and checks that everything else survived.
+    fn round_trip(builder: MapBuilder) {
+        let bytes = builder.build();
+        let map = BSP::from_bytes(&bytes).unwrap();
+        let mut entities = map.entities().unwrap();
+        edit(&mut entities);
+
+        let written = replace_entities(&bytes, &entities).unwrap();
+        let written_map = BSP::from_bytes(&written).unwrap();
+        assert_eq!(written_map.entities().unwrap(), entities);
+        assert_eq!(written_map.header.version, map.header.version);
+        assert_eq!(written_map.header.map_revision, map.header.map_revision);
+
+        for &index in LumpIndex::ALL.iter() {
+            if index != LumpIndex::Entities && index != LumpIndex::GameLump {
+                assert_eq!(written_map.raw_lump(index), map.raw_lump(index));
+                assert_eq!(
+                    written_map.header.lump(index).version,
+                    map.header.lump(index).version
+                );
+            }
+        }
+
+        assert_eq!(written_map.game_lumps.len(), map.game_lumps.len());
+        for (written, original) in written_map.game_lumps.iter().zip(map.game_lumps.iter()) {
+            assert_eq!(written.id, original.id);
+            assert_eq!(written.version, original.version);
+            assert_eq!(written.data, original.data);
+        }
+    }
+
+    #[test]
+    fn replace_entity_lump() {
+        round_trip(
+            MapBuilder::box_room()
+                .lump(LumpIndex::Entities, ENTITIES.as_bytes())
+                .game_lump(0x73707270, 10, true, &[1u8; 300]) // 'sprp'
+                .game_lump(0x64707270, 4, false, &[2u8; 12]), // 'dprp'
+        );
+
+        // the entity lump is compressed and the header uses the left 4 dead 2 layout
+        let mut builder = MapBuilder::box_room()
+            .lump(LumpIndex::Entities, ENTITIES.as_bytes())
+            .compressed(LumpIndex::Entities)
+            .game_lump(0x73707270, 10, false, &[1u8; 30]);
+        builder.lump_layout = LumpLayout::Left4Dead2;
+        round_trip(builder);
+
+        round_trip(
+            MapBuilder::box_room_big_endian().lump(LumpIndex::Entities, ENTITIES.as_bytes()),
+        );
+    }
+
+    #[test]
+    fn replace_empty_lump() {
+        let bytes = MapBuilder::box_room()
+            .lump(LumpIndex::Entities, &[0u8; 0][..])
+            .build();
+        let mut entity = Entity::new();
+        entity.add("classname", "worldspawn");
+
+        let written = replace_entities(&bytes, &[entity.clone()]).unwrap();
+        let map = BSP::from_bytes(&written).unwrap();
+        assert_eq!(map.entities().unwrap(), vec![entity]);
+        // the lump did not have a place in the file and is appended
+        let entities = map.header.lump(LumpIndex::Entities);
+        assert_eq!(
+            (entities.fileofs + entities.filelen) as usize,
+            written.len()
+        );
+
+        assert!(replace_lump(&bytes[..16], LumpIndex::Entities, &[]).is_err());
+    }
+
+    #[test]
+    fn entity_lump_file() {
+        let bytes = MapBuilder::box_room()
+            .lump(LumpIndex::Entities, ENTITIES.as_bytes())
+            .build();
+        let map = BSP::from_bytes(&bytes).unwrap();
+        let mut entities = map.entities().unwrap();
+        edit(&mut entities);
+
+        let file = map.entity_lump_file(&entities).unwrap();
+        assert_eq!(file.header.map_revision, map.header.map_revision);
+        let map =
+            BSP::from_reader_with_overrides(Cursor::new(&bytes), &LoadOptions::new(), vec![file])
+                .unwrap();
+        assert_eq!(map.entities().unwrap(), entities);
+        assert_eq!(map.lump_overrides.len(), 1);
+    }
+}
@@ -0,0 +1,363 @@
+//! Counter-Strike gameplay zones.
+//!
+//! Bomb sites, buy zones and hostage rescue zones are brush entities, their `*N` model is
+//! resolved through the models lump into the brushes below the model's head node.
+//! Team spawns are point entities and use the standing player hull around their origin.
+
+use super::entity::{Entity, EntityModel};
+use super::BSP;
+use super::{math, validate};
+use crate::error::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Mins and maxs of the standing player hull relative to the origin of a spawn point.
+pub const PLAYER_HULL: ([f32; 3], [f32; 3]) = ([-16f32, -16f32, 0f32], [16f32, 16f32, 72f32]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ZoneKind {
+    /// `func_bomb_target`
+    BombTarget,
+    /// `func_buyzone`
+    BuyZone,
+    /// `func_hostage_rescue`
+    HostageRescue,
+    /// `info_player_terrorist`
+    TerroristSpawn,
+    /// `info_player_counterterrorist`
+    CounterTerroristSpawn,
+}
+
+impl ZoneKind {
+    pub fn from_classname(class: &str) -> Option<Self> {
+        let kind = match class.to_ascii_lowercase().as_str() {
+            "func_bomb_target" => ZoneKind::BombTarget,
+            "func_buyzone" => ZoneKind::BuyZone,
+            "func_hostage_rescue" => ZoneKind::HostageRescue,
+            "info_player_terrorist" => ZoneKind::TerroristSpawn,
+            "info_player_counterterrorist" => ZoneKind::CounterTerroristSpawn,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// Returns true for zones that are point entities.
+    pub fn is_spawn(self) -> bool {
+        matches!(
+            self,
+            ZoneKind::TerroristSpawn | ZoneKind::CounterTerroristSpawn
+        )
+    }
+}
+
+/// A gameplay zone in world space.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Zone {
+    pub kind: ZoneKind,
+    /// Index of the entity in the entity lump.
+    pub entity: usize,
+    pub targetname: Option<String>,
+    /// The team of buy zones (`TeamNum`, 2 = terrorists, 3 = counter-terrorists).
+    pub team: Option<i32>,
+    /// Index of the brush model, `None` for spawns.
+    pub model: Option<usize>,
+    /// Offset of the brushes, brush entities can be moved by their `origin`.
+    pub origin: [f32; 3],
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    /// Indices of the brushes of the model in the brushes lump.
+    pub brushes: Vec<usize>,
+}
+
+impl Zone {
+    /// Builds the zone of an entity, returns `None` for other entities and unresolved models.
+    fn from_entity(map: &BSP, index: usize, entity: &Entity) -> Option<Self> {
+        let kind = ZoneKind::from_classname(entity.classname()?)?;
+        let origin = entity.origin().unwrap_or([0f32; 3]);
+        let mut zone = Self {
+            kind,
+            entity: index,
+            targetname: entity.targetname().map(str::to_string),
+            team: entity.get_f32("TeamNum").map(|team| team as i32),
+            model: None,
+            origin,
+            mins: origin,
+            maxs: origin,
+            brushes: Vec::new(),
+        };
+
+        if kind.is_spawn() {
+            let (mins, maxs) = PLAYER_HULL;
+            for i in 0..3 {
+                zone.mins[i] += mins[i];
+                zone.maxs[i] += maxs[i];
+            }
+            return Some(zone);
+        }
+
+        let model = match entity.model()? {
+            EntityModel::Brush(model) => model,
+            EntityModel::File(_) => return None,
+        };
+        let bounds = map.models.get(model)?;
+        for i in 0..3 {
+            zone.mins[i] += bounds.mins[i];
+            zone.maxs[i] += bounds.maxs[i];
+        }
+        zone.model = Some(model);
+        zone.brushes = model_brushes(map, bounds.head_node);
+        Some(zone)
+    }
+
+    /// Returns true if the point lies within the bounds of the zone.
+    pub fn bounds_contain(&self, point: [f32; 3]) -> bool {
+        (0..3).all(|i| point[i] >= self.mins[i] && point[i] <= self.maxs[i])
+    }
+}
+
+/// Collects the brushes of all leafs below the node.
+///
+/// Nodes can share subtrees, every node is only visited once.
+fn model_brushes(map: &BSP, head_node: i32) -> Vec<usize> {
+    let mut brushes = Vec::new();
+    let mut visited = vec![false; map.nodes.len()];
+    let mut stack = vec![head_node];
+    while let Some(num) = stack.pop() {
+        if num < 0 {
+            let leaf = match map.leaves.get((-1 - num) as usize) {
+                Some(leaf) => leaf,
+                None => continue,
+            };
+            let first = leaf.first_leaf_brush as usize;
+            let last = first + leaf.num_leaf_brushes as usize;
+            if let Some(leaf_brushes) = map.leaf_brushes.get(first..last) {
+                brushes.extend(leaf_brushes.iter().map(|&brush| brush as usize));
+            }
+            continue;
+        }
+
+        let seen = match visited.get_mut(num as usize) {
+            Some(seen) => seen,
+            None => continue,
+        };
+        if std::mem::replace(seen, true) {
+            continue;
+        }
+        if let Some(node) = map.nodes.get(num as usize) {
+            if validate::children_follow_parent(num, &node.children) {
+                stack.extend_from_slice(&node.children);
+            }
+        }
+    }
+
+    brushes.sort_unstable();
+    brushes.dedup();
+    brushes
+}
+
+impl BSP {
+    /// Returns the bomb sites, buy zones, hostage rescue zones and team spawns of the map.
+    ///
+    /// Brush entities whose model can not be resolved are skipped.
+    pub fn zones(&self) -> Result<Vec<Zone>> {
+        Ok(self
+            .entities()?
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entity)| Zone::from_entity(self, i, entity))
+            .collect())
+    }
+
+    /// Returns true if the point lies within the zone.
+    ///
+    /// Brush zones test against the planes of their brushes, spawns against their bounds.
+    pub fn point_in_zone(&self, zone: &Zone, point: [f32; 3]) -> bool {
+        if !zone.bounds_contain(point) {
+            return false;
+        }
+        if zone.model.is_none() {
+            return true;
+        }
+
+        let local = [
+            point[0] - zone.origin[0],
+            point[1] - zone.origin[1],
+            point[2] - zone.origin[2],
+        ];
+        zone.brushes
+            .iter()
+            .filter_map(|&brush| self.brushes.get(brush))
+            .any(|brush| {
+                let first = brush.first_side.max(0) as usize;
+                let sides = match self
+                    .brush_sides
+                    .get(first..first + brush.num_sides.max(0) as usize)
+                {
+                    Some(sides) if !sides.is_empty() => sides,
+                    _ => return false,
+                };
+                sides.iter().all(|side| {
+                    self.planes
+                        .get(side.plane_num as usize)
+                        .is_some_and(|plane| {
+                            math::dot_product(plane.normal, local) - plane.distance <= 0f32
+                        })
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::MapBuilder;
+    use super::super::native::*;
+    use super::*;
+
+    use std::mem::size_of;
+
+    use dataview::PodMethods;
+
+    /// The box room with a bomb site using the box brush moved by its origin, a buy zone and
+    /// a hostage rescue zone using the same brush, one buy zone with a missing model and two
+    /// spawns.
+    fn map() -> BSP {
+        let world = dmodel_t {
+            mins: [-16f32; 3],
+            maxs: [16f32; 3],
+            origin: [0f32; 3],
+            head_node: 0,
+            first_face: 0,
+            num_faces: 1,
+        };
+        let mut site = world.clone();
+        site.head_node = 1;
+        site.num_faces = 0;
+
+        let mut builder = MapBuilder::box_room();
+        let mut nodes = builder.lumps[LumpIndex::Nodes as usize].data.clone();
+        nodes.extend_from_within(..);
+        // the second node leads to a leaf with the trigger brush
+        let children = size_of::<dnode_t>() + 4;
+        nodes[children..children + 4].copy_from_slice(&(-3i32).to_le_bytes());
+        nodes[children + 4..children + 8].copy_from_slice(&(-3i32).to_le_bytes());
+        builder.lumps[LumpIndex::Nodes as usize].data = nodes;
+
+        let mut leaves: Vec<dleaf_t> = vec![dleaf_t::zeroed(); 3];
+        for (i, leaf) in leaves.iter_mut().enumerate() {
+            leaf.num_leaf_brushes = 1;
+            leaf.first_leaf_brush = (i / 2) as u16;
+        }
+        let brushes = [
+            dbrush_t {
+                first_side: 0,
+                num_sides: 6,
+                contents: 0x1,
+            },
+            dbrush_t {
+                first_side: 0,
+                num_sides: 6,
+                contents: 0x40000000, // CONTENTS_TRIGGER
+            },
+        ];
+
+        let entities = "{ \"classname\" \"worldspawn\" }\n\
+            { \"classname\" \"func_bomb_target\" \"targetname\" \"site_a\" \"model\" \"*1\" \"origin\" \"256 0 0\" }\n\
+            { \"classname\" \"func_buyzone\" \"model\" \"*7\" \"TeamNum\" \"2\" }\n\
+            { \"classname\" \"func_buyzone\" \"model\" \"*1\" \"TeamNum\" \"3\" }\n\
+            { \"classname\" \"func_hostage_rescue\" \"model\" \"*1\" }\n\
+            { \"classname\" \"info_player_terrorist\" \"origin\" \"0 512 0\" }\n\
+            { \"classname\" \"info_player_counterterrorist\" \"origin\" \"0 -512 0\" }\n\
+            { \"classname\" \"light\" \"origin\" \"0 0 64\" }\n\0";
+
+        let builder = builder
+            .lump(LumpIndex::Leafs, &leaves[..])
+            .lump(LumpIndex::LeafBrushes, &[0u16, 1][..])
+            .lump(LumpIndex::Brushes, &brushes[..])
+            .lump(LumpIndex::Models, &[world, site][..])
+            .lump(LumpIndex::Entities, entities.as_bytes());
+        BSP::from_bytes(&builder.build()).unwrap()
+    }
+
+    #[test]
+    fn zones() {
+        let map = map();
+        let zones = map.zones().unwrap();
+        // the buy zone with the missing model `*7` is skipped
+        assert_eq!(zones.len(), 5);
+
+        let site = &zones[0];
+        assert_eq!(site.kind, ZoneKind::BombTarget);
+        assert_eq!(site.entity, 1);
+        assert_eq!(site.targetname.as_deref(), Some("site_a"));
+        assert_eq!(site.model, Some(1));
+        assert_eq!(site.mins, [240f32, -16f32, -16f32]);
+        assert_eq!(site.maxs, [272f32, 16f32, 16f32]);
+        assert_eq!(site.team, None);
+        assert_eq!(site.brushes, vec![1]);
+
+        let buy = &zones[1];
+        assert_eq!(buy.kind, ZoneKind::BuyZone);
+        assert_eq!(buy.entity, 3);
+        assert_eq!(buy.team, Some(3));
+        assert_eq!(buy.model, Some(1));
+        assert_eq!(buy.mins, [-16f32; 3]);
+        assert_eq!(buy.maxs, [16f32; 3]);
+        assert_eq!(buy.brushes, vec![1]);
+
+        let rescue = &zones[2];
+        assert_eq!(rescue.kind, ZoneKind::HostageRescue);
+        assert_eq!(rescue.entity, 4);
+        assert_eq!(rescue.team, None);
+        assert_eq!(rescue.model, Some(1));
+        assert_eq!(rescue.brushes, vec![1]);
+
+        let spawn = &zones[3];
+        assert_eq!(spawn.kind, ZoneKind::TerroristSpawn);
+        assert_eq!(spawn.model, None);
+        assert_eq!(spawn.mins, [-16f32, 496f32, 0f32]);
+        assert_eq!(spawn.maxs, [16f32, 528f32, 72f32]);
+        assert_eq!(zones[4].kind, ZoneKind::CounterTerroristSpawn);
+
+        assert_eq!(
+            ZoneKind::from_classname("FUNC_BUYZONE"),
+            Some(ZoneKind::BuyZone)
+        );
+        assert_eq!(ZoneKind::from_classname("info_player_start"), None);
+    }
+
+    #[test]
+    fn shared_subtrees() {
+        // every node points at the next one twice, without a visited set this would take
+        // 2^64 steps
+        let mut map = map();
+        map.nodes = vec![map.nodes[0].clone(); 64];
+        for (i, node) in map.nodes.iter_mut().enumerate() {
+            node.children = [i as i32 + 1, i as i32 + 1];
+        }
+        map.nodes[63].children = [-3, -3];
+        map.models[1].head_node = 0;
+        let zones = map.zones().unwrap();
+        assert_eq!(zones[0].brushes, vec![1]);
+    }
+
+    #[test]
+    fn point_in_zone() {
+        let map = map();
+        let zones = map.zones().unwrap();
+
+        assert!(map.point_in_zone(&zones[0], [256f32, 0f32, 0f32]));
+        assert!(map.point_in_zone(&zones[0], [270f32, 10f32, -10f32]));
+        assert!(!map.point_in_zone(&zones[0], [0f32, 0f32, 0f32]));
+        assert!(!map.point_in_zone(&zones[0], [280f32, 0f32, 0f32]));
+
+        assert!(map.point_in_zone(&zones[1], [0f32, 0f32, 0f32]));
+        assert!(!map.point_in_zone(&zones[1], [256f32, 0f32, 0f32]));
+        assert!(map.point_in_zone(&zones[2], [-10f32, 10f32, 10f32]));
+
+        assert!(map.point_in_zone(&zones[3], [0f32, 512f32, 36f32]));
+        assert!(!map.point_in_zone(&zones[3], [0f32, 512f32, -1f32]));
+    }
+}
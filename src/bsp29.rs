@@ -0,0 +1,335 @@
+//! Loading shared by Quake bsp v29 / BSP2 and GoldSrc v30 maps.
+//!
+//! GoldSrc kept the Quake layout and only added a fourth hull and texture palettes. Every map is
+//! widened into the BSP2 structures, so lump reading, validation and the hulls exist only once.
+
+use crate::bsp::validate::children_follow_parent;
+use crate::error::*;
+use crate::goldsrc::native as disk;
+use crate::goldsrc::{parse_textures, MipTex};
+use crate::lump_dir::{self, in_range, range_in_bounds};
+use crate::quake::native::*;
+use crate::trace::hull::{Hull, HullNode};
+
+use std::io::{Read, Seek, SeekFrom};
+
+use dataview::{Pod, PodMethods};
+
+/// Describes how a format of the family is stored.
+pub(crate) struct Layout {
+    /// Name of the format in errors.
+    pub name: &'static str,
+    /// Width of the indices and bounds on disk.
+    pub format: Format,
+    /// Mins and maxs of the hulls the compiler builds, hull 0 is the point hull.
+    pub hull_sizes: &'static [([f32; 3], [f32; 3])],
+    /// Textures store a palette after their last mip level.
+    pub palettes: bool,
+}
+
+/// The lumps of a map in the BSP2 layout.
+pub(crate) struct Lumps {
+    pub header: dheader_t,
+    pub planes: Vec<dplane_t>,
+    pub textures: Vec<Option<MipTex>>,
+    pub vertexes: Vec<mvertex_t>,
+    pub nodes: Vec<dnode_t>,
+    pub tex_info: Vec<texinfo_t>,
+    pub faces: Vec<dface_t>,
+    pub clip_nodes: Vec<dclipnode_t>,
+    pub leaves: Vec<dleaf_t>,
+    pub mark_surfaces: Vec<u32>,
+    pub edges: Vec<dedge_t>,
+    pub surf_edges: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+    pub hulls: Hulls,
+    pub raw_lumps: Vec<Vec<u8>>,
+}
+
+/// The nodes of all hulls of a map.
+pub(crate) struct Hulls {
+    /// Hull 0, the bsp nodes with leaf references replaced by the leaf contents.
+    point: Vec<HullNode>,
+    /// Hulls 1 and above share the clip nodes.
+    clip: Vec<HullNode>,
+    sizes: &'static [([f32; 3], [f32; 3])],
+}
+
+impl Hulls {
+    /// Returns the given hull of a model.
+    pub fn model_hull<'a>(
+        &'a self,
+        planes: &'a [dplane_t],
+        models: &[dmodel_t],
+        model: usize,
+        hull: usize,
+    ) -> Option<Hull<'a>> {
+        let model = models.get(model)?;
+        let (clip_mins, clip_maxs) = *self.sizes.get(hull)?;
+        let nodes = if hull == 0 { &self.point } else { &self.clip };
+
+        let head_node = model.head_nodes[hull];
+        if head_node >= 0 && head_node as usize >= nodes.len() {
+            return None;
+        }
+
+        Some(Hull {
+            nodes,
+            planes,
+            head_node,
+            clip_mins,
+            clip_maxs,
+        })
+    }
+}
+
+/// Reads a map, `layout` picks the layout for the version in the header.
+pub(crate) fn load<R: Read + Seek>(
+    mut reader: R,
+    layout: impl FnOnce(i32) -> Option<Layout>,
+) -> Result<(Lumps, Layout)> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+
+    let mut header = dheader_t::default();
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(header.as_bytes_mut())?;
+    let layout = layout(header.version).ok_or(Error::UnsupportedVersion(header.version))?;
+
+    let raw_lumps = LumpIndex::ALL
+        .iter()
+        .map(|&index| {
+            let lump = header.lump(index);
+            lump_dir::read_lump(
+                &mut reader,
+                layout.name,
+                index as usize,
+                lump.fileofs,
+                lump.filelen,
+                file_len,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let disk_lumps = LumpCaster {
+        name: layout.name,
+        raw_lumps: &raw_lumps,
+    };
+    let (nodes, faces, clip_nodes, leaves, mark_surfaces, edges) = match layout.format {
+        Format::Bsp29 => (
+            disk_lumps.widen::<disk::dnode_t, _>(LumpIndex::Nodes)?,
+            disk_lumps.widen::<disk::dface_t, _>(LumpIndex::Faces)?,
+            disk_lumps.widen::<disk::dclipnode_t, _>(LumpIndex::ClipNodes)?,
+            disk_lumps.widen::<disk::dleaf_t, _>(LumpIndex::Leafs)?,
+            disk_lumps.widen::<u16, _>(LumpIndex::MarkSurfaces)?,
+            disk_lumps.widen::<disk::dedge_t, _>(LumpIndex::Edges)?,
+        ),
+        Format::Bsp2 | Format::Bsp2Rmq => {
+            let (nodes, leaves) = if layout.format == Format::Bsp2Rmq {
+                (
+                    disk_lumps.widen::<dnode_rmq_t, _>(LumpIndex::Nodes)?,
+                    disk_lumps.widen::<dleaf_rmq_t, _>(LumpIndex::Leafs)?,
+                )
+            } else {
+                (
+                    disk_lumps.cast(LumpIndex::Nodes)?,
+                    disk_lumps.cast(LumpIndex::Leafs)?,
+                )
+            };
+            (
+                nodes,
+                disk_lumps.cast(LumpIndex::Faces)?,
+                disk_lumps.cast(LumpIndex::ClipNodes)?,
+                leaves,
+                disk_lumps.cast(LumpIndex::MarkSurfaces)?,
+                disk_lumps.cast(LumpIndex::Edges)?,
+            )
+        }
+    };
+    let textures = parse_textures(
+        layout.name,
+        &raw_lumps[LumpIndex::Textures as usize],
+        layout.palettes,
+    )?;
+
+    let mut lumps = Lumps {
+        header,
+        planes: disk_lumps.cast(LumpIndex::Planes)?,
+        textures,
+        vertexes: disk_lumps.cast(LumpIndex::Vertexes)?,
+        nodes,
+        tex_info: disk_lumps.cast(LumpIndex::TexInfo)?,
+        faces,
+        clip_nodes,
+        leaves,
+        mark_surfaces,
+        edges,
+        surf_edges: disk_lumps.cast(LumpIndex::SurfEdges)?,
+        models: disk_lumps.cast(LumpIndex::Models)?,
+        hulls: Hulls {
+            point: Vec::new(),
+            clip: Vec::new(),
+            sizes: layout.hull_sizes,
+        },
+        raw_lumps: Vec::new(),
+    };
+    validate(&lumps, layout.name)?;
+
+    lumps.hulls.point = lumps
+        .nodes
+        .iter()
+        .map(|node| HullNode {
+            plane_num: node.plane_num,
+            children: node.children.map(|child| {
+                if child >= 0 {
+                    child
+                } else {
+                    lumps.leaves[(-1 - child) as usize].contents
+                }
+            }),
+        })
+        .collect();
+    lumps.hulls.clip = lumps
+        .clip_nodes
+        .iter()
+        .map(|node| HullNode {
+            plane_num: node.plane_num,
+            children: node.children,
+        })
+        .collect();
+    lumps.raw_lumps = raw_lumps;
+
+    Ok((lumps, layout))
+}
+
+struct LumpCaster<'a> {
+    name: &'static str,
+    raw_lumps: &'a [Vec<u8>],
+}
+
+impl LumpCaster<'_> {
+    fn cast<T: Pod + Clone>(&self, index: LumpIndex) -> Result<Vec<T>> {
+        lump_dir::cast(self.name, index as usize, &self.raw_lumps[index as usize])
+    }
+
+    /// Reads a lump in the on-disk layout `D` and converts it into the BSP2 layout `T`.
+    fn widen<D: Pod + Clone, T: From<D>>(&self, index: LumpIndex) -> Result<Vec<T>> {
+        Ok(self.cast::<D>(index)?.into_iter().map(T::from).collect())
+    }
+}
+
+/// Checks all cross-lump references so the tree can be walked without further checks.
+fn validate(lumps: &Lumps, name: &'static str) -> Result<()> {
+    let check = |valid: bool, lump: LumpIndex, reason: &'static str| {
+        if valid {
+            Ok(())
+        } else {
+            Err(lump_dir::invalid(name, lump as usize, reason))
+        }
+    };
+    let planes = lumps.planes.len();
+
+    for (i, node) in lumps.nodes.iter().enumerate() {
+        check(
+            in_range(node.plane_num, planes),
+            LumpIndex::Nodes,
+            "invalid plane index",
+        )?;
+        let valid = children_follow_parent(i as i32, &node.children)
+            && node.children.iter().all(|&child| {
+                if child >= 0 {
+                    (child as usize) < lumps.nodes.len()
+                } else {
+                    in_range(-1 - child, lumps.leaves.len())
+                }
+            });
+        check(valid, LumpIndex::Nodes, "invalid child index")?;
+        check(
+            node.first_face as u64 + node.num_faces as u64 <= lumps.faces.len() as u64,
+            LumpIndex::Nodes,
+            "invalid face range",
+        )?;
+    }
+
+    for (i, node) in lumps.clip_nodes.iter().enumerate() {
+        check(
+            in_range(node.plane_num, planes),
+            LumpIndex::ClipNodes,
+            "invalid plane index",
+        )?;
+        let valid = children_follow_parent(i as i32, &node.children)
+            && node
+                .children
+                .iter()
+                .all(|&child| child < 0 || (child as usize) < lumps.clip_nodes.len());
+        check(valid, LumpIndex::ClipNodes, "invalid child index")?;
+    }
+
+    for leaf in lumps.leaves.iter() {
+        check(
+            leaf.first_mark_surface as u64 + leaf.num_mark_surfaces as u64
+                <= lumps.mark_surfaces.len() as u64,
+            LumpIndex::Leafs,
+            "invalid mark surface range",
+        )?;
+    }
+
+    for &face in lumps.mark_surfaces.iter() {
+        check(
+            (face as usize) < lumps.faces.len(),
+            LumpIndex::MarkSurfaces,
+            "invalid face index",
+        )?;
+    }
+
+    for face in lumps.faces.iter() {
+        check(
+            in_range(face.plane_num, planes),
+            LumpIndex::Faces,
+            "invalid plane index",
+        )?;
+        check(
+            range_in_bounds(face.first_edge, face.num_edges, lumps.surf_edges.len()),
+            LumpIndex::Faces,
+            "invalid surf edge range",
+        )?;
+        check(
+            in_range(face.tex_info, lumps.tex_info.len()),
+            LumpIndex::Faces,
+            "invalid tex info index",
+        )?;
+    }
+
+    for &edge in lumps.surf_edges.iter() {
+        check(
+            (edge as i64).unsigned_abs() < lumps.edges.len() as u64,
+            LumpIndex::SurfEdges,
+            "invalid edge index",
+        )?;
+    }
+
+    for edge in lumps.edges.iter() {
+        check(
+            edge.v.iter().all(|&v| (v as usize) < lumps.vertexes.len()),
+            LumpIndex::Edges,
+            "invalid vertex index",
+        )?;
+    }
+
+    for tex_info in lumps.tex_info.iter() {
+        check(
+            in_range(tex_info.miptex, lumps.textures.len()),
+            LumpIndex::TexInfo,
+            "invalid texture index",
+        )?;
+    }
+
+    for model in lumps.models.iter() {
+        check(
+            range_in_bounds(model.first_face, model.num_faces, lumps.faces.len()),
+            LumpIndex::Models,
+            "invalid face range",
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,66 +1,183 @@
 use std;
 
+use crate::bsp::LumpIndex;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub struct Error {
-    error: Box<dyn std::error::Error + Send + Sync>,
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading from the underlying file or buffer failed.
+    Io(std::io::Error),
+    /// The file does not start with a known bsp ident.
+    BadMagic(i32),
+    /// The bsp version is not supported by this parser.
+    UnsupportedVersion(i32),
+    /// The lump does not lie within the file.
+    LumpOutOfBounds {
+        lump: LumpIndex,
+        offset: i64,
+        len: i64,
+    },
+    /// The lump size is not a multiple of the element size.
+    LumpSizeMismatch {
+        lump: LumpIndex,
+        len: usize,
+        element_size: usize,
+    },
+    /// An element refers to an entry of `lump` that does not exist.
+    InvalidIndex { lump: LumpIndex, index: i64 },
+    /// The lump contents are malformed.
+    InvalidLump {
+        lump: LumpIndex,
+        reason: &'static str,
+    },
+    /// The lump is not aligned to its element type and can not be borrowed in place.
+    LumpMisaligned { lump: LumpIndex },
+    /// A lzma compressed lump could not be decompressed.
+    Decompress {
+        lump: LumpIndex,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    /// A lump of a map in another engine's format (GoldSrc, Quake) is malformed.
+    ///
+    /// `lump` is the slot of the lump in the header of that format.
+    InvalidMapLump {
+        format: &'static str,
+        lump: usize,
+        reason: &'static str,
+    },
+    /// A `.lmp` lump file is malformed.
+    InvalidLumpFile(&'static str),
+    /// The file is valid but uses a feature that is not supported by the requested loader.
+    Unsupported(&'static str),
+    /// Downloading a map failed.
+    Download {
+        reason: &'static str,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
 }
 
 impl Error {
-    pub fn new<E>(error: E) -> Error
+    /// Wraps the error of a failed download step.
+    #[cfg(feature = "workshop")]
+    pub(crate) fn download<E>(reason: &'static str) -> impl FnOnce(E) -> Error
     where
         E: Into<Box<dyn std::error::Error + Send + Sync>>,
     {
-        Error {
-            error: error.into(),
+        move |error| Error::Download {
+            reason,
+            source: Some(error.into()),
         }
     }
 }
 
-impl std::fmt::Debug for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Debug::fmt(&self.error, f)
-    }
-}
-
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.error)
+        match self {
+            Error::Io(error) => write!(f, "io error: {}", error),
+            Error::BadMagic(ident) => write!(f, "invalid bsp magic: 0x{:x}", ident),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported bsp version: {}", version)
+            }
+            Error::LumpOutOfBounds { lump, offset, len } => write!(
+                f,
+                "{:?} lump is out of bounds (offset: {}, length: {})",
+                lump, offset, len
+            ),
+            Error::LumpSizeMismatch {
+                lump,
+                len,
+                element_size,
+            } => write!(
+                f,
+                "{:?} lump has a size of {} bytes which does not fit elements of {} bytes",
+                lump, len, element_size
+            ),
+            Error::InvalidIndex { lump, index } => {
+                write!(f, "invalid index {} into {:?} lump", index, lump)
+            }
+            Error::InvalidLump { lump, reason } => write!(f, "invalid {:?} lump: {}", lump, reason),
+            Error::LumpMisaligned { lump } => write!(f, "{:?} lump is misaligned", lump),
+            Error::Decompress { lump, .. } => write!(f, "unable to decompress {:?} lump", lump),
+            Error::InvalidMapLump {
+                format,
+                lump,
+                reason,
+            } => write!(f, "invalid {} lump {}: {}", format, lump, reason),
+            Error::InvalidLumpFile(reason) => write!(f, "invalid lump file: {}", reason),
+            Error::Unsupported(reason) => write!(f, "unsupported bsp: {}", reason),
+            Error::Download { reason, .. } => write!(f, "download failed: {}", reason),
+        }
     }
 }
 
 // This is important for other errors to wrap this one.
 impl std::error::Error for Error {
-    #[allow(deprecated)]
-    fn description(&self) -> &str {
-        self.error.description()
-    }
-
-    #[allow(deprecated)]
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        self.error.cause()
-    }
-
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
-        self.error.source()
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Decompress {
+                source: Some(source),
+                ..
+            }
+            | Error::Download {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
     }
 }
 
-impl std::convert::From<&str> for Error {
-    fn from(error: &str) -> Self {
-        Self::new(error)
+impl std::convert::From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
     }
 }
 
-impl std::convert::From<String> for Error {
-    fn from(error: String) -> Self {
-        Self::new(error)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error as _;
+
+    #[test]
+    fn display() {
+        let error = Error::LumpOutOfBounds {
+            lump: LumpIndex::Leafs,
+            offset: 1024,
+            len: 64,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Leafs lump is out of bounds (offset: 1024, length: 64)"
+        );
+        assert_eq!(
+            Error::BadMagic(0x12345678).to_string(),
+            "invalid bsp magic: 0x12345678"
+        );
+        let error = Error::InvalidMapLump {
+            format: "GoldSrc",
+            lump: 9,
+            reason: "lump is out of bounds",
+        };
+        assert_eq!(
+            error.to_string(),
+            "invalid GoldSrc lump 9: lump is out of bounds"
+        );
     }
-}
 
-impl std::convert::From<std::io::Error> for Error {
-    fn from(error: std::io::Error) -> Self {
-        Self::new(error)
+    #[test]
+    fn source() {
+        let error = Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(error.source().is_some());
+        assert!(Error::UnsupportedVersion(17).source().is_none());
+
+        let error = Error::Decompress {
+            lump: LumpIndex::Planes,
+            source: Some(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        };
+        assert!(error.source().is_some());
     }
 }
@@ -0,0 +1,128 @@
+//! Deterministic mutation tests over all parsing entry points.
+//!
+//! The fixtures of each format are corrupted at random and every parser (and the traces on top
+//! of maps that still load) has to return an error instead of panicking or allocating without bounds.
+//! The `fuzz` directory runs the same entry points under libFuzzer.
+
+use crate::bsp::{self, BSPView, LoadOptions, BSP};
+use crate::goldsrc::{self, GoldSrcBSP};
+use crate::quake::{self, QuakeBSP};
+use crate::quake3::{self, Quake3BSP};
+use crate::trace;
+
+const ITERATIONS: usize = 2000;
+
+/// xorshift64, good enough to spread the mutations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Applies a few random byte flips, overwritten integers and truncations.
+fn mutate(data: &[u8], rng: &mut Rng) -> Vec<u8> {
+    const VALUES: [i32; 8] = [0, 1, -1, -2, 0x7fff, 0xffff, i32::MAX, i32::MIN];
+
+    let mut out = data.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        if out.len() < 4 {
+            break;
+        }
+        match rng.below(3) {
+            0 => {
+                let i = rng.below(out.len());
+                out[i] ^= 1 << rng.below(8);
+            }
+            1 => {
+                let i = rng.below(out.len() / 4) * 4;
+                let value = VALUES[rng.below(VALUES.len())];
+                out[i..i + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            _ => {
+                let len = rng.below(out.len());
+                out.truncate(len);
+            }
+        }
+    }
+    out
+}
+
+fn run<F: Fn(&[u8])>(seed: u64, data: &[u8], parse: F) {
+    let mut rng = Rng(seed);
+    for _ in 0..ITERATIONS {
+        parse(&mutate(data, &mut rng));
+    }
+}
+
+#[test]
+fn source() {
+    let data = bsp::fixture::MapBuilder::box_room().build();
+    run(0x5eed_0001, &data, |data| {
+        let _ = BSP::from_bytes(data);
+        let _ = BSPView::new(data);
+        let _ = bsp::crc_map_file(std::io::Cursor::new(data));
+        let _ = bsp::parse_entities(data);
+
+        let options = LoadOptions::new().lenient(true);
+        if let Ok(map) = BSP::from_bytes_with(data, &options) {
+            trace::is_visible(&map, [24f32, 0f32, 0f32], [-24f32, 0f32, 0f32]);
+            if let Ok(zones) = map.zones() {
+                for zone in zones.iter() {
+                    map.point_in_zone(zone, [0f32; 3]);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn goldsrc() {
+    let data = goldsrc::fixture::MapBuilder::box_room().build();
+    run(0x5eed_0002, &data, |data| {
+        if let Ok(map) = GoldSrcBSP::from_bytes(data) {
+            for hull in 0..goldsrc::MAX_MAP_HULLS {
+                if let Some(hull) = map.hull(hull) {
+                    trace::is_visible(&hull, [0f32; 3], [0f32, 0f32, 200f32]);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn quake() {
+    for &version in [quake::QUAKE_VERSION, quake::BSP2_VERSION].iter() {
+        let data = goldsrc::fixture::MapBuilder::quake_box_room()
+            .version(version)
+            .build();
+        run(0x5eed_0003 ^ version as u64, &data, |data| {
+            if let Ok(map) = QuakeBSP::from_bytes(data) {
+                if let Some(hull) = map.hull(1) {
+                    trace::is_visible(&hull, [0f32; 3], [0f32, 0f32, 200f32]);
+                }
+                map.faces.iter().for_each(|face| {
+                    map.face_vertices(face);
+                });
+            }
+        });
+    }
+}
+
+#[test]
+fn quake3() {
+    let data = quake3::fixture::MapBuilder::box_room().build();
+    run(0x5eed_0004, &data, |data| {
+        if let Ok(map) = Quake3BSP::from_bytes(data) {
+            trace::is_visible(&map, [-64f32, 0f32, 0f32], [128f32, 0f32, 0f32]);
+        }
+    });
+}
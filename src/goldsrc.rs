@@ -0,0 +1,268 @@
+//! Loader for GoldSrc (Half-Life 1, Counter-Strike 1.6) bsp v30 maps.
+
+pub mod native;
+
+pub use native::*;
+
+#[cfg(test)]
+pub(crate) mod fixture;
+
+use crate::bsp29::{self, Hulls, Layout};
+use crate::error::*;
+use crate::lump_dir;
+use crate::quake::native as wide;
+use crate::trace::hull::Hull;
+
+use std::fs::OpenOptions;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::mem::size_of;
+use std::path::Path;
+
+use dataview::PodMethods;
+
+const FORMAT: &str = "GoldSrc";
+
+/// A texture of the textures lump.
+#[derive(Clone, Debug)]
+pub struct MipTex {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// The pixels of all four mip levels, empty if the texture is stored in an external wad.
+    pub mip_levels: Vec<Vec<u8>>,
+    /// The palette that is stored after the last mip level, Quake textures use the global palette.
+    pub palette: Vec<[u8; 3]>,
+}
+
+/// A GoldSrc map, the lumps that differ in width between the Quake formats are widened into
+/// the BSP2 layout of `quake::native`.
+pub struct GoldSrcBSP {
+    pub header: dheader_t,
+    pub planes: Vec<dplane_t>,
+    /// Entries of the textures lump, missing textures are `None`.
+    pub textures: Vec<Option<MipTex>>,
+    pub vertexes: Vec<mvertex_t>,
+    pub nodes: Vec<wide::dnode_t>,
+    pub tex_info: Vec<texinfo_t>,
+    pub faces: Vec<wide::dface_t>,
+    pub clip_nodes: Vec<wide::dclipnode_t>,
+    pub leaves: Vec<wide::dleaf_t>,
+    pub mark_surfaces: Vec<u32>,
+    pub edges: Vec<wide::dedge_t>,
+    pub surf_edges: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+    hulls: Hulls,
+    raw_lumps: Vec<Vec<u8>>,
+}
+
+impl GoldSrcBSP {
+    /// Opens and parses the bsp file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Parses a bsp that is fully held in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    /// Parses a bsp from any seekable source.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        let (lumps, _) = bsp29::load(reader, |version| {
+            (version == GOLDSRC_VERSION).then_some(Layout {
+                name: FORMAT,
+                format: wide::Format::Bsp29,
+                hull_sizes: &HULL_SIZES,
+                palettes: true,
+            })
+        })?;
+
+        Ok(Self {
+            header: lumps.header,
+            planes: lumps.planes,
+            textures: lumps.textures,
+            vertexes: lumps.vertexes,
+            nodes: lumps.nodes,
+            tex_info: lumps.tex_info,
+            faces: lumps.faces,
+            clip_nodes: lumps.clip_nodes,
+            leaves: lumps.leaves,
+            mark_surfaces: lumps.mark_surfaces,
+            edges: lumps.edges,
+            surf_edges: lumps.surf_edges,
+            models: lumps.models,
+            hulls: lumps.hulls,
+            raw_lumps: lumps.raw_lumps,
+        })
+    }
+
+    /// Returns the given hull (0 to 3) of the world.
+    pub fn hull(&self, hull: usize) -> Option<Hull<'_>> {
+        self.model_hull(0, hull)
+    }
+
+    /// Returns the given hull (0 to 3) of a model, model 0 is the world.
+    pub fn model_hull(&self, model: usize, hull: usize) -> Option<Hull<'_>> {
+        self.hulls
+            .model_hull(&self.planes, &self.models, model, hull)
+    }
+
+    /// The contents of a lump as stored in the file.
+    pub fn raw_lump(&self, index: LumpIndex) -> &[u8] {
+        &self.raw_lumps[index as usize]
+    }
+}
+
+/// Parses the textures lump: a texture count followed by the offsets of each `dmiptex_t`.
+///
+/// Quake stores no palette after the mip levels, GoldSrc does.
+pub(crate) fn parse_textures(
+    format: &'static str,
+    data: &[u8],
+    palette: bool,
+) -> Result<Vec<Option<MipTex>>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let invalid = |reason| lump_dir::invalid(format, LumpIndex::Textures as usize, reason);
+    let out_of_bounds = || invalid("texture is out of bounds");
+    let read_i32 = |offset: usize| -> Result<i32> {
+        let bytes = data.get(offset..offset + 4).ok_or_else(out_of_bounds)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let count = read_i32(0)?;
+    if count < 0 || (count as usize + 1) * 4 > data.len() {
+        return Err(invalid("invalid texture count"));
+    }
+
+    (0..count as usize)
+        .map(|i| {
+            let offset = read_i32(4 + i * 4)?;
+            if offset < 0 {
+                return Ok(None);
+            }
+            parse_miptex(data, offset as usize, palette)
+                .map(Some)
+                .ok_or_else(out_of_bounds)
+        })
+        .collect()
+}
+
+fn parse_miptex(data: &[u8], offset: usize, with_palette: bool) -> Option<MipTex> {
+    let mut miptex = dmiptex_t::zeroed();
+    miptex
+        .as_bytes_mut()
+        .copy_from_slice(data.get(offset..offset.checked_add(size_of::<dmiptex_t>())?)?);
+
+    let name_len = miptex.name.iter().position(|&c| c == 0).unwrap_or(16);
+    let name = String::from_utf8_lossy(&miptex.name[..name_len]).to_string();
+
+    let mut mip_levels = Vec::new();
+    let mut palette = Vec::new();
+    if miptex.offsets[0] != 0 {
+        let mut end = 0usize;
+        for (level, &level_offset) in miptex.offsets.iter().enumerate() {
+            let size = ((miptex.width >> level) as usize)
+                .checked_mul((miptex.height >> level) as usize)?;
+            let start = offset.checked_add(level_offset as usize)?;
+            end = start.checked_add(size)?;
+            mip_levels.push(data.get(start..end)?.to_vec());
+        }
+
+        // the palette size is stored as a short in front of the colors
+        if with_palette {
+            let count = data.get(end..end + 2)?;
+            let count = u16::from_le_bytes([count[0], count[1]]) as usize;
+            let colors = data.get(end + 2..end + 2 + count * 3)?;
+            palette = colors.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+        }
+    }
+
+    Some(MipTex {
+        name,
+        width: miptex.width,
+        height: miptex.height,
+        mip_levels,
+        palette,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::MapBuilder;
+    use super::*;
+
+    #[test]
+    fn box_room() {
+        let map = GoldSrcBSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert_eq!(map.planes.len(), 24);
+        assert_eq!(map.nodes.len(), 6);
+        assert_eq!(map.clip_nodes.len(), 18);
+        assert_eq!(map.leaves.len(), 2);
+        assert_eq!(map.faces.len(), 1);
+        assert_eq!(map.models[0].head_nodes, [0, 0, 6, 12]);
+        assert!(map.raw_lump(LumpIndex::Entities).starts_with(b"{"));
+
+        assert_eq!(map.textures.len(), 3);
+        let floor = map.textures[0].as_ref().unwrap();
+        assert_eq!(floor.name, "floor");
+        assert_eq!((floor.width, floor.height), (16, 16));
+        assert_eq!(
+            floor.mip_levels.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![256, 64, 16, 4]
+        );
+        assert_eq!(floor.palette.len(), 256);
+        assert!(map.textures[1].is_none());
+        let sky = map.textures[2].as_ref().unwrap();
+        assert_eq!(sky.name, "sky");
+        assert!(sky.mip_levels.is_empty());
+
+        assert_eq!(map.hull(0).unwrap().nodes.len(), 6);
+        for hull in 1..MAX_MAP_HULLS {
+            assert_eq!(map.hull(hull).unwrap().nodes.len(), 18);
+        }
+        assert!(map.hull(MAX_MAP_HULLS).is_none());
+        assert!(map.model_hull(1, 0).is_none());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let data = MapBuilder::box_room().version(29).build();
+        assert!(matches!(
+            GoldSrcBSP::from_bytes(&data),
+            Err(Error::UnsupportedVersion(29))
+        ));
+    }
+
+    #[test]
+    fn lump_out_of_bounds() {
+        let mut data = MapBuilder::box_room().build();
+        data.truncate(data.len() - 4);
+        assert!(matches!(
+            GoldSrcBSP::from_bytes(&data),
+            Err(Error::InvalidMapLump {
+                format: "GoldSrc",
+                lump: 14,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn invalid_index() {
+        let mut builder = MapBuilder::box_room();
+        builder.clip_nodes[0].plane_num = 24;
+        let data = builder.build();
+        assert!(matches!(
+            GoldSrcBSP::from_bytes(&data),
+            Err(Error::InvalidMapLump {
+                lump: 9,
+                reason: "invalid plane index",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//! Synthetic GoldSrc and Quake maps for unit tests.
+//!
+//! The box room is an empty 256x256x256 room centered around the origin.
+//! Every hull consists of six nodes that clip against the walls moved inwards by the hull size.
+//! The lumps are kept in the BSP2 layout and written in the on-disk format of the version.
+
+use super::native as bsp29;
+use crate::quake::native::*;
+
+use std::mem::size_of;
+
+use dataview::PodMethods;
+
+pub const BOX_ENTITIES: &str = "{\n\"classname\" \"worldspawn\"\n}\n\0";
+
+/// Half of the size of the box room.
+pub const BOX_EXTENT: f32 = 128f32;
+
+#[derive(Clone)]
+pub struct MapBuilder {
+    pub version: i32,
+    pub entities: String,
+    pub planes: Vec<dplane_t>,
+    pub textures: Vec<u8>,
+    pub vertexes: Vec<mvertex_t>,
+    pub nodes: Vec<dnode_t>,
+    pub tex_info: Vec<texinfo_t>,
+    pub faces: Vec<dface_t>,
+    pub clip_nodes: Vec<dclipnode_t>,
+    pub leaves: Vec<dleaf_t>,
+    pub mark_surfaces: Vec<u32>,
+    pub edges: Vec<dedge_t>,
+    pub surf_edges: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+}
+
+impl MapBuilder {
+    /// The GoldSrc box room with four hulls and textures with palettes.
+    pub fn box_room() -> Self {
+        Self::with_hulls(bsp29::GOLDSRC_VERSION, &bsp29::HULL_SIZES, true)
+    }
+
+    /// The Quake box room with three hulls and textures without palettes.
+    pub fn quake_box_room() -> Self {
+        Self::with_hulls(QUAKE_VERSION, &HULL_SIZES, false)
+    }
+
+    fn with_hulls(version: i32, hull_sizes: &[([f32; 3], [f32; 3])], palettes: bool) -> Self {
+        let mut planes = Vec::new();
+        for &(mins, maxs) in hull_sizes.iter() {
+            for axis in 0..3 {
+                let mut normal = [0f32; 3];
+                normal[axis] = 1f32;
+                planes.push(dplane_t {
+                    normal,
+                    distance: BOX_EXTENT - maxs[axis],
+                    typ: axis as i32,
+                });
+                planes.push(dplane_t {
+                    normal,
+                    distance: -BOX_EXTENT - mins[axis],
+                    typ: axis as i32,
+                });
+            }
+        }
+
+        // leaf 0 is the shared solid leaf, leaf 1 the inside of the room
+        let e = BOX_EXTENT;
+        let nodes = (0..6)
+            .map(|i| dnode_t {
+                plane_num: i,
+                children: if i % 2 == 0 {
+                    [-1, i + 1]
+                } else if i < 5 {
+                    [i + 1, -1]
+                } else {
+                    [-2, -1]
+                },
+                mins: [-e; 3],
+                maxs: [e; 3],
+                first_face: 0,
+                num_faces: if i == 5 { 1 } else { 0 },
+            })
+            .collect();
+
+        let mut clip_nodes = Vec::new();
+        let mut head_nodes = [0; MAX_MAP_HULLS];
+        for hull in 1..hull_sizes.len() as i32 {
+            let first = (hull - 1) * 6;
+            head_nodes[hull as usize] = first;
+            for i in 0..6 {
+                let next = first + i + 1;
+                clip_nodes.push(dclipnode_t {
+                    plane_num: hull * 6 + i,
+                    children: if i % 2 == 0 {
+                        [CONTENTS_SOLID, next]
+                    } else if i < 5 {
+                        [next, CONTENTS_SOLID]
+                    } else {
+                        [CONTENTS_EMPTY, CONTENTS_SOLID]
+                    },
+                });
+            }
+        }
+
+        let leaf = |contents, num_mark_surfaces| dleaf_t {
+            contents,
+            vis_offset: -1,
+            mins: [-e; 3],
+            maxs: [e; 3],
+            first_mark_surface: 0,
+            num_mark_surfaces,
+            ambient_level: [0; 4],
+        };
+
+        // the floor of the room
+        let vertexes = [[-e, -e, -e], [e, -e, -e], [e, e, -e], [-e, e, -e]]
+            .iter()
+            .map(|&position| mvertex_t { position })
+            .collect();
+        let edges = [[0, 0], [0, 1], [1, 2], [2, 3], [3, 0]]
+            .iter()
+            .map(|&v| dedge_t { v })
+            .collect();
+        let face = dface_t {
+            plane_num: 5,
+            side: 0,
+            first_edge: 0,
+            num_edges: 4,
+            tex_info: 0,
+            styles: [0, 255, 255, 255],
+            light_ofs: -1,
+        };
+        let tex_info = texinfo_t {
+            vecs: [[1f32, 0f32, 0f32, 0f32], [0f32, 1f32, 0f32, 0f32]],
+            miptex: 0,
+            flags: 0,
+        };
+
+        let model = dmodel_t {
+            mins: [-e; 3],
+            maxs: [e; 3],
+            origin: [0f32; 3],
+            head_nodes,
+            vis_leafs: 1,
+            first_face: 0,
+            num_faces: 1,
+        };
+
+        Self {
+            version,
+            entities: BOX_ENTITIES.to_string(),
+            planes,
+            textures: textures(palettes),
+            vertexes,
+            nodes,
+            tex_info: vec![tex_info],
+            faces: vec![face],
+            clip_nodes,
+            leaves: vec![leaf(CONTENTS_SOLID, 0), leaf(CONTENTS_EMPTY, 1)],
+            mark_surfaces: vec![0],
+            edges,
+            surf_edges: vec![1, 2, 3, 4],
+            models: vec![model],
+        }
+    }
+
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Writes the map in the format of its version, anything but BSP2 and 2PSB uses the
+    /// BSP29 layout.
+    pub fn build(&self) -> Vec<u8> {
+        let mut lumps = vec![Vec::new(); HEADER_LUMP_COUNT];
+        let mut set = |index: LumpIndex, data: &[u8]| lumps[index as usize] = data.to_vec();
+        set(LumpIndex::Entities, self.entities.as_bytes());
+        set(LumpIndex::Planes, self.planes.as_bytes());
+        set(LumpIndex::Textures, &self.textures);
+        set(LumpIndex::Vertexes, self.vertexes.as_bytes());
+        set(LumpIndex::TexInfo, self.tex_info.as_bytes());
+        set(LumpIndex::SurfEdges, self.surf_edges.as_bytes());
+        set(LumpIndex::Models, self.models.as_bytes());
+
+        match Format::from_version(self.version) {
+            Some(format @ Format::Bsp2) | Some(format @ Format::Bsp2Rmq) => {
+                if format == Format::Bsp2Rmq {
+                    set(LumpIndex::Nodes, rmq_nodes(&self.nodes).as_bytes());
+                    set(LumpIndex::Leafs, rmq_leaves(&self.leaves).as_bytes());
+                } else {
+                    set(LumpIndex::Nodes, self.nodes.as_bytes());
+                    set(LumpIndex::Leafs, self.leaves.as_bytes());
+                }
+                set(LumpIndex::Faces, self.faces.as_bytes());
+                set(LumpIndex::ClipNodes, self.clip_nodes.as_bytes());
+                set(LumpIndex::MarkSurfaces, self.mark_surfaces.as_bytes());
+                set(LumpIndex::Edges, self.edges.as_bytes());
+            }
+            _ => {
+                set(LumpIndex::Nodes, narrow_nodes(&self.nodes).as_bytes());
+                set(LumpIndex::Faces, narrow_faces(&self.faces).as_bytes());
+                set(
+                    LumpIndex::ClipNodes,
+                    narrow_clip_nodes(&self.clip_nodes).as_bytes(),
+                );
+                set(LumpIndex::Leafs, narrow_leaves(&self.leaves).as_bytes());
+                let mark_surfaces = self
+                    .mark_surfaces
+                    .iter()
+                    .map(|&face| face as u16)
+                    .collect::<Vec<_>>();
+                set(LumpIndex::MarkSurfaces, mark_surfaces.as_bytes());
+                let edges = self
+                    .edges
+                    .iter()
+                    .map(|edge| bsp29::dedge_t {
+                        v: [edge.v[0] as u16, edge.v[1] as u16],
+                    })
+                    .collect::<Vec<_>>();
+                set(LumpIndex::Edges, edges.as_bytes());
+            }
+        }
+
+        let mut header = dheader_t {
+            version: self.version,
+            ..Default::default()
+        };
+
+        let mut data = vec![0u8; size_of::<dheader_t>()];
+        for (i, lump) in lumps.iter().enumerate() {
+            while !data.len().is_multiple_of(4) {
+                data.push(0);
+            }
+            header.lumps[i] = lump_t {
+                fileofs: data.len() as i32,
+                filelen: lump.len() as i32,
+            };
+            data.extend_from_slice(lump);
+        }
+
+        data[..size_of::<dheader_t>()].copy_from_slice(header.as_bytes());
+        data
+    }
+}
+
+fn shorts(v: [f32; 3]) -> [i16; 3] {
+    [v[0] as i16, v[1] as i16, v[2] as i16]
+}
+
+fn narrow_nodes(nodes: &[dnode_t]) -> Vec<bsp29::dnode_t> {
+    nodes
+        .iter()
+        .map(|node| bsp29::dnode_t {
+            plane_num: node.plane_num,
+            children: [node.children[0] as i16, node.children[1] as i16],
+            mins: shorts(node.mins),
+            maxs: shorts(node.maxs),
+            first_face: node.first_face as u16,
+            num_faces: node.num_faces as u16,
+        })
+        .collect()
+}
+
+fn narrow_faces(faces: &[dface_t]) -> Vec<bsp29::dface_t> {
+    faces
+        .iter()
+        .map(|face| bsp29::dface_t {
+            plane_num: face.plane_num as u16,
+            side: face.side as i16,
+            first_edge: face.first_edge,
+            num_edges: face.num_edges as i16,
+            tex_info: face.tex_info as i16,
+            styles: face.styles,
+            light_ofs: face.light_ofs,
+        })
+        .collect()
+}
+
+fn narrow_clip_nodes(nodes: &[dclipnode_t]) -> Vec<bsp29::dclipnode_t> {
+    nodes
+        .iter()
+        .map(|node| bsp29::dclipnode_t {
+            plane_num: node.plane_num,
+            children: [node.children[0] as i16, node.children[1] as i16],
+        })
+        .collect()
+}
+
+fn narrow_leaves(leaves: &[dleaf_t]) -> Vec<bsp29::dleaf_t> {
+    leaves
+        .iter()
+        .map(|leaf| bsp29::dleaf_t {
+            contents: leaf.contents,
+            vis_offset: leaf.vis_offset,
+            mins: shorts(leaf.mins),
+            maxs: shorts(leaf.maxs),
+            first_mark_surface: leaf.first_mark_surface as u16,
+            num_mark_surfaces: leaf.num_mark_surfaces as u16,
+            ambient_level: leaf.ambient_level,
+        })
+        .collect()
+}
+
+fn rmq_nodes(nodes: &[dnode_t]) -> Vec<dnode_rmq_t> {
+    nodes
+        .iter()
+        .map(|node| dnode_rmq_t {
+            plane_num: node.plane_num,
+            children: node.children,
+            mins: shorts(node.mins),
+            maxs: shorts(node.maxs),
+            first_face: node.first_face,
+            num_faces: node.num_faces,
+        })
+        .collect()
+}
+
+fn rmq_leaves(leaves: &[dleaf_t]) -> Vec<dleaf_rmq_t> {
+    leaves
+        .iter()
+        .map(|leaf| dleaf_rmq_t {
+            contents: leaf.contents,
+            vis_offset: leaf.vis_offset,
+            mins: shorts(leaf.mins),
+            maxs: shorts(leaf.maxs),
+            first_mark_surface: leaf.first_mark_surface,
+            num_mark_surfaces: leaf.num_mark_surfaces,
+            ambient_level: leaf.ambient_level,
+        })
+        .collect()
+}
+
+/// A 16x16 texture with all mip levels, a missing texture and a texture stored in a wad.
+///
+/// GoldSrc textures store a palette after their last mip level.
+fn textures(palettes: bool) -> Vec<u8> {
+    let mut floor = dmiptex_t::zeroed();
+    floor.name[..5].copy_from_slice(b"floor");
+    floor.width = 16;
+    floor.height = 16;
+    let mut offset = size_of::<dmiptex_t>() as u32;
+    for (level, mip_offset) in floor.offsets.iter_mut().enumerate() {
+        *mip_offset = offset;
+        offset += 256 >> (level * 2);
+    }
+
+    let mut sky = dmiptex_t::zeroed();
+    sky.name[..3].copy_from_slice(b"sky");
+    sky.width = 64;
+    sky.height = 64;
+
+    let mut floor_data = floor.as_bytes().to_vec();
+    floor_data.resize(offset as usize, 7);
+    if palettes {
+        floor_data.extend_from_slice(&256u16.to_le_bytes());
+        floor_data.extend_from_slice(&[0x80; 256 * 3]);
+        floor_data.extend_from_slice(&[0u8; 2]);
+    }
+
+    let floor_offset = 16;
+    let sky_offset = floor_offset + floor_data.len() as i32;
+    let mut data = Vec::new();
+    for value in [3, floor_offset, -1, sky_offset].iter() {
+        data.extend_from_slice(&i32::to_le_bytes(*value));
+    }
+    data.extend_from_slice(&floor_data);
+    data.extend_from_slice(sky.as_bytes());
+    data
+}
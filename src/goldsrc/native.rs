@@ -0,0 +1,191 @@
+#![allow(dead_code)]
+
+use dataview::Pod;
+
+pub use crate::bsp::{dedge_t, dplane_t, mvertex_t};
+
+pub const GOLDSRC_VERSION: i32 = 30;
+pub const HEADER_LUMP_COUNT: usize = 15;
+pub const MAX_MAP_HULLS: usize = 4;
+
+/// Mins and maxs of the boxes the hulls were expanded by, hull 0 is the point hull.
+pub const HULL_SIZES: [([f32; 3], [f32; 3]); MAX_MAP_HULLS] = [
+    ([0f32, 0f32, 0f32], [0f32, 0f32, 0f32]),
+    ([-16f32, -16f32, -36f32], [16f32, 16f32, 36f32]),
+    ([-32f32, -32f32, -32f32], [32f32, 32f32, 32f32]),
+    ([-16f32, -16f32, -18f32], [16f32, 16f32, 18f32]),
+];
+
+// leaf and clip node contents
+pub const CONTENTS_EMPTY: i32 = -1;
+pub const CONTENTS_SOLID: i32 = -2;
+pub const CONTENTS_WATER: i32 = -3;
+pub const CONTENTS_SLIME: i32 = -4;
+pub const CONTENTS_LAVA: i32 = -5;
+pub const CONTENTS_SKY: i32 = -6;
+/// removed by the compiler
+pub const CONTENTS_ORIGIN: i32 = -7;
+/// changed to CONTENTS_SOLID by the compiler
+pub const CONTENTS_CLIP: i32 = -8;
+pub const CONTENTS_CURRENT_0: i32 = -9;
+pub const CONTENTS_CURRENT_90: i32 = -10;
+pub const CONTENTS_CURRENT_180: i32 = -11;
+pub const CONTENTS_CURRENT_270: i32 = -12;
+pub const CONTENTS_CURRENT_UP: i32 = -13;
+pub const CONTENTS_CURRENT_DOWN: i32 = -14;
+pub const CONTENTS_TRANSLUCENT: i32 = -15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LumpIndex {
+    Entities = 0,
+    Planes = 1,
+    Textures = 2,
+    Vertexes = 3,
+    Visibility = 4,
+    Nodes = 5,
+    TexInfo = 6,
+    Faces = 7,
+    Lighting = 8,
+    ClipNodes = 9,
+    Leafs = 10,
+    MarkSurfaces = 11,
+    Edges = 12,
+    SurfEdges = 13,
+    Models = 14,
+}
+
+impl LumpIndex {
+    /// All lumps in header order.
+    pub const ALL: [LumpIndex; HEADER_LUMP_COUNT] = [
+        LumpIndex::Entities,
+        LumpIndex::Planes,
+        LumpIndex::Textures,
+        LumpIndex::Vertexes,
+        LumpIndex::Visibility,
+        LumpIndex::Nodes,
+        LumpIndex::TexInfo,
+        LumpIndex::Faces,
+        LumpIndex::Lighting,
+        LumpIndex::ClipNodes,
+        LumpIndex::Leafs,
+        LumpIndex::MarkSurfaces,
+        LumpIndex::Edges,
+        LumpIndex::SurfEdges,
+        LumpIndex::Models,
+    ];
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct lump_t {
+    pub fileofs: i32, // 0x0
+    pub filelen: i32, // 0x4
+} //Size=0x8
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dheader_t {
+    pub version: i32,                       // 0x00
+    pub lumps: [lump_t; HEADER_LUMP_COUNT], // 0x04
+} //Size=0x7C
+
+impl Default for dheader_t {
+    fn default() -> Self {
+        unsafe { core::mem::zeroed() }
+    }
+}
+
+impl dheader_t {
+    pub fn lump(&self, index: LumpIndex) -> &lump_t {
+        &self.lumps[index as usize]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dnode_t {
+    pub plane_num: i32,     // 0x00
+    pub children: [i16; 2], // 0x04 - negative numbers are -(leafs+1), not nodes
+    pub mins: [i16; 3],     // 0x08
+    pub maxs: [i16; 3],     // 0x0E
+    pub first_face: u16,    // 0x14
+    pub num_faces: u16,     // 0x16
+} //Size=0x18
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dclipnode_t {
+    pub plane_num: i32,     // 0x0
+    pub children: [i16; 2], // 0x4 - negative numbers are contents
+} //Size=0x8
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dleaf_t {
+    pub contents: i32,           // 0x00
+    pub vis_offset: i32,         // 0x04 - -1 = no visibility info
+    pub mins: [i16; 3],          // 0x08
+    pub maxs: [i16; 3],          // 0x0E
+    pub first_mark_surface: u16, // 0x14
+    pub num_mark_surfaces: u16,  // 0x16
+    pub ambient_level: [u8; 4],  // 0x18
+} //Size=0x1C
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dface_t {
+    pub plane_num: u16,  // 0x00
+    pub side: i16,       // 0x02
+    pub first_edge: i32, // 0x04
+    pub num_edges: i16,  // 0x08
+    pub tex_info: i16,   // 0x0A
+    pub styles: [u8; 4], // 0x0C
+    pub light_ofs: i32,  // 0x10 - start of [numstyles*surfsize] samples
+} //Size=0x14
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct texinfo_t {
+    pub vecs: [[f32; 4]; 2], // 0x00 - [s/t][xyz offset]
+    pub miptex: i32,         // 0x20
+    pub flags: i32,          // 0x24
+} //Size=0x28
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dmodel_t {
+    pub mins: [f32; 3],                   // 0x00
+    pub maxs: [f32; 3],                   // 0x0C
+    pub origin: [f32; 3],                 // 0x18
+    pub head_nodes: [i32; MAX_MAP_HULLS], // 0x24
+    pub vis_leafs: i32,                   // 0x34 - not including the solid leaf 0
+    pub first_face: i32,                  // 0x38
+    pub num_faces: i32,                   // 0x3C
+} //Size=0x40
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dmiptex_t {
+    pub name: [u8; 16],    // 0x00
+    pub width: u32,        // 0x10
+    pub height: u32,       // 0x14
+    pub offsets: [u32; 4], // 0x18 - four mip maps stored, zero if the texture is stored in a wad
+} //Size=0x28
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn struct_sizes() {
+        assert_eq!(size_of::<dheader_t>(), 0x7C);
+        assert_eq!(size_of::<dnode_t>(), 0x18);
+        assert_eq!(size_of::<dclipnode_t>(), 0x8);
+        assert_eq!(size_of::<dleaf_t>(), 0x1C);
+        assert_eq!(size_of::<dface_t>(), 0x14);
+        assert_eq!(size_of::<texinfo_t>(), 0x28);
+        assert_eq!(size_of::<dmodel_t>(), 0x40);
+        assert_eq!(size_of::<dmiptex_t>(), 0x28);
+    }
+}
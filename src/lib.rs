@@ -1,5 +1,12 @@
 pub mod bsp;
+mod bsp29;
 pub mod error;
+#[cfg(test)]
+mod fuzz;
+pub mod goldsrc;
+mod lump_dir;
+pub mod quake;
+pub mod quake3;
 pub mod trace;
 
 #[cfg(feature = "workshop")]
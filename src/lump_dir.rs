@@ -0,0 +1,74 @@
+//! Lump reading for the id tech based formats (GoldSrc, Quake, Quake 3).
+//!
+//! Their headers only store an offset and a length per lump and lumps are never compressed.
+
+use crate::error::*;
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::mem::size_of;
+
+use dataview::{Pod, PodMethods};
+
+pub(crate) fn invalid(format: &'static str, lump: usize, reason: &'static str) -> Error {
+    Error::InvalidMapLump {
+        format,
+        lump,
+        reason,
+    }
+}
+
+/// Reads the lump at `fileofs` after checking it against the size of the file.
+pub(crate) fn read_lump<R: Read + Seek>(
+    reader: &mut R,
+    format: &'static str,
+    lump: usize,
+    fileofs: i32,
+    filelen: i32,
+    file_len: u64,
+) -> Result<Vec<u8>> {
+    let out_of_bounds = || invalid(format, lump, "lump is out of bounds");
+    let end = fileofs as i64 + filelen as i64;
+    if fileofs < 0 || filelen < 0 || end as u64 > file_len {
+        return Err(out_of_bounds());
+    }
+
+    let mut out = vec![0u8; filelen as usize];
+    reader.seek(SeekFrom::Start(fileofs as u64))?;
+    reader.read_exact(&mut out).map_err(|err| {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            out_of_bounds()
+        } else {
+            Error::Io(err)
+        }
+    })?;
+    Ok(out)
+}
+
+/// Copies the lump data into a vector of `T`, the lump size has to be a multiple of `T`.
+pub(crate) fn cast<T: Pod + Clone>(
+    format: &'static str,
+    lump: usize,
+    data: &[u8],
+) -> Result<Vec<T>> {
+    if !data.len().is_multiple_of(size_of::<T>()) {
+        return Err(invalid(
+            format,
+            lump,
+            "lump size is not a multiple of its element size",
+        ));
+    }
+
+    let mut out: Vec<T> = vec![unsafe { core::mem::zeroed() }; data.len() / size_of::<T>()];
+    out.as_bytes_mut().copy_from_slice(data);
+    Ok(out)
+}
+
+/// Returns true if `index` is a valid index into a lump with `len` elements.
+pub(crate) fn in_range(index: i32, len: usize) -> bool {
+    index >= 0 && (index as usize) < len
+}
+
+/// Returns true if `first..first + count` lies within a lump with `len` elements.
+pub(crate) fn range_in_bounds(first: i32, count: i32, len: usize) -> bool {
+    first >= 0 && count >= 0 && first as i64 + count as i64 <= len as i64
+}
@@ -1,8 +1,5 @@
-mod bsp;
-mod error;
-mod trace;
-
-use bsp::*;
+use bsp_rs::bsp::*;
+use bsp_rs::trace;
 
 use std::env;
 
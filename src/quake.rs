@@ -0,0 +1,207 @@
+//! Loader for Quake bsp v29 maps and the BSP2 / 2PSB formats for large maps.
+//!
+//! All formats are widened into the BSP2 structures so the rest of the crate only has to deal
+//! with one layout. They are loaded and traced with the same code as GoldSrc maps.
+
+pub mod native;
+
+pub use native::*;
+
+use crate::bsp29::{self, Hulls, Layout};
+use crate::error::*;
+use crate::goldsrc::MipTex;
+use crate::trace::hull::Hull;
+
+use std::fs::OpenOptions;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+const FORMAT: &str = "Quake";
+
+pub struct QuakeBSP {
+    pub header: dheader_t,
+    pub format: Format,
+    pub planes: Vec<dplane_t>,
+    /// Entries of the textures lump, missing textures are `None`.
+    pub textures: Vec<Option<MipTex>>,
+    pub vertexes: Vec<mvertex_t>,
+    pub nodes: Vec<dnode_t>,
+    pub tex_info: Vec<texinfo_t>,
+    pub faces: Vec<dface_t>,
+    pub clip_nodes: Vec<dclipnode_t>,
+    pub leaves: Vec<dleaf_t>,
+    pub mark_surfaces: Vec<u32>,
+    pub edges: Vec<dedge_t>,
+    pub surf_edges: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+    hulls: Hulls,
+    raw_lumps: Vec<Vec<u8>>,
+}
+
+impl QuakeBSP {
+    /// Opens and parses the bsp file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Parses a bsp that is fully held in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    /// Parses a bsp from any seekable source.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        let (lumps, layout) = bsp29::load(reader, |version| {
+            Format::from_version(version).map(|format| Layout {
+                name: FORMAT,
+                format,
+                hull_sizes: &HULL_SIZES,
+                palettes: false,
+            })
+        })?;
+
+        Ok(Self {
+            header: lumps.header,
+            format: layout.format,
+            planes: lumps.planes,
+            textures: lumps.textures,
+            vertexes: lumps.vertexes,
+            nodes: lumps.nodes,
+            tex_info: lumps.tex_info,
+            faces: lumps.faces,
+            clip_nodes: lumps.clip_nodes,
+            leaves: lumps.leaves,
+            mark_surfaces: lumps.mark_surfaces,
+            edges: lumps.edges,
+            surf_edges: lumps.surf_edges,
+            models: lumps.models,
+            hulls: lumps.hulls,
+            raw_lumps: lumps.raw_lumps,
+        })
+    }
+
+    /// Returns the given hull (0 to 2) of the world.
+    pub fn hull(&self, hull: usize) -> Option<Hull<'_>> {
+        self.model_hull(0, hull)
+    }
+
+    /// Returns the given hull (0 to 2) of a model, model 0 is the world.
+    pub fn model_hull(&self, model: usize, hull: usize) -> Option<Hull<'_>> {
+        self.hulls
+            .model_hull(&self.planes, &self.models, model, hull)
+    }
+
+    /// The vertices of a face in winding order.
+    pub fn face_vertices(&self, face: &dface_t) -> Vec<[f32; 3]> {
+        (0..face.num_edges.max(0) as usize)
+            .filter_map(|i| {
+                let surf_edge = *self.surf_edges.get(face.first_edge as usize + i)?;
+                let edge = self.edges.get(surf_edge.unsigned_abs() as usize)?;
+                let v = if surf_edge >= 0 { edge.v[0] } else { edge.v[1] };
+                Some(self.vertexes.get(v as usize)?.position)
+            })
+            .collect()
+    }
+
+    /// The contents of a lump as stored in the file.
+    pub fn raw_lump(&self, index: LumpIndex) -> &[u8] {
+        &self.raw_lumps[index as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::goldsrc::fixture::MapBuilder;
+    use crate::trace::{self, hull};
+
+    fn formats() -> [i32; 3] {
+        [QUAKE_VERSION, BSP2_VERSION, BSP2_RMQ_VERSION]
+    }
+
+    #[test]
+    fn box_room() {
+        for &version in formats().iter() {
+            let data = MapBuilder::quake_box_room().version(version).build();
+            let map = QuakeBSP::from_bytes(&data).unwrap();
+            assert_eq!(Some(map.format), Format::from_version(version));
+            assert_eq!(map.planes.len(), 18);
+            assert_eq!(map.nodes.len(), 6);
+            assert_eq!(map.nodes[5].children, [-2, -1]);
+            assert_eq!(map.nodes[5].mins, [-128f32; 3]);
+            assert_eq!(map.clip_nodes.len(), 12);
+            assert_eq!(map.clip_nodes[5].children, [CONTENTS_EMPTY, CONTENTS_SOLID]);
+            assert_eq!(map.leaves[1].num_mark_surfaces, 1);
+            assert_eq!(map.mark_surfaces, vec![0]);
+            assert_eq!(map.faces[0].num_edges, 4);
+            assert!(map.raw_lump(LumpIndex::Entities).starts_with(b"{"));
+
+            let floor = map.textures[0].as_ref().unwrap();
+            assert_eq!(floor.name, "floor");
+            assert_eq!(floor.mip_levels.len(), 4);
+            assert!(floor.palette.is_empty());
+
+            assert_eq!(
+                map.face_vertices(&map.faces[0]),
+                vec![
+                    [-128f32, -128f32, -128f32],
+                    [128f32, -128f32, -128f32],
+                    [128f32, 128f32, -128f32],
+                    [-128f32, 128f32, -128f32],
+                ]
+            );
+
+            assert!(map.hull(2).is_some());
+            assert!(map.hull(3).is_none());
+        }
+    }
+
+    #[test]
+    fn hull_traces() {
+        for &version in formats().iter() {
+            let data = MapBuilder::quake_box_room().version(version).build();
+            let map = QuakeBSP::from_bytes(&data).unwrap();
+
+            let point = map.hull(0).unwrap();
+            assert_eq!(hull::point_contents(&point, [0f32; 3]), CONTENTS_EMPTY);
+            assert_eq!(
+                hull::point_contents(&point, [0f32, 0f32, 200f32]),
+                CONTENTS_SOLID
+            );
+            assert!(!trace::is_visible(&point, [0f32; 3], [0f32, 0f32, 200f32]));
+            assert!(!trace::is_visible(&map, [0f32; 3], [0f32, 0f32, 200f32]));
+            assert!(trace::is_visible(&map, [0f32; 3], [0f32, 0f32, 100f32]));
+
+            // the player hull stops 32 units below the ceiling
+            let player = map.hull(1).unwrap();
+            let mut trace = trace::Trace::new();
+            trace::ray_cast(&player, [0f32; 3], [0f32, 0f32, 200f32], &mut trace);
+            assert!(trace.fraction < 1f32);
+            assert!((trace.end_pos[2] - 96f32).abs() < 0.1f32);
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let data = MapBuilder::quake_box_room().version(30).build();
+        assert!(matches!(
+            QuakeBSP::from_bytes(&data),
+            Err(Error::UnsupportedVersion(30))
+        ));
+    }
+
+    #[test]
+    fn invalid_index() {
+        let mut builder = MapBuilder::quake_box_room().version(BSP2_VERSION);
+        builder.mark_surfaces[0] = 70000;
+        assert!(matches!(
+            QuakeBSP::from_bytes(&builder.build()),
+            Err(Error::InvalidMapLump {
+                format: "Quake",
+                lump: 11,
+                reason: "invalid face index",
+            })
+        ));
+    }
+}
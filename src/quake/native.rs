@@ -0,0 +1,225 @@
+#![allow(dead_code)]
+
+use dataview::Pod;
+
+use crate::goldsrc::native as bsp29;
+
+pub use crate::goldsrc::native::{
+    dheader_t, dmiptex_t, dmodel_t, dplane_t, lump_t, mvertex_t, texinfo_t, LumpIndex,
+    CONTENTS_CURRENT_0, CONTENTS_CURRENT_180, CONTENTS_CURRENT_270, CONTENTS_CURRENT_90,
+    CONTENTS_CURRENT_DOWN, CONTENTS_CURRENT_UP, CONTENTS_EMPTY, CONTENTS_LAVA, CONTENTS_SKY,
+    CONTENTS_SLIME, CONTENTS_SOLID, CONTENTS_WATER, HEADER_LUMP_COUNT, MAX_MAP_HULLS,
+};
+
+pub const QUAKE_VERSION: i32 = 29;
+/// BSP2, 32 bit indices and float bounding boxes.
+pub const BSP2_VERSION: i32 = 0x32505342; // 'BSP2'
+/// The older 2PSB variant of BSP2, 32 bit indices with short bounding boxes.
+pub const BSP2_RMQ_VERSION: i32 = 0x42535032; // '2PSB'
+
+/// Mins and maxs of the boxes the hulls were expanded by, hull 0 is the point hull.
+///
+/// Quake only compiles three hulls, the fourth head node of the models is unused.
+pub const HULL_SIZES: [([f32; 3], [f32; 3]); 3] = [
+    ([0f32, 0f32, 0f32], [0f32, 0f32, 0f32]),
+    ([-16f32, -16f32, -24f32], [16f32, 16f32, 32f32]),
+    ([-32f32, -32f32, -24f32], [32f32, 32f32, 64f32]),
+];
+
+/// The on-disk formats of a Quake bsp, they only differ in the width of their indices and bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Bsp29,
+    Bsp2,
+    Bsp2Rmq,
+}
+
+impl Format {
+    pub fn from_version(version: i32) -> Option<Self> {
+        match version {
+            QUAKE_VERSION => Some(Format::Bsp29),
+            BSP2_VERSION => Some(Format::Bsp2),
+            BSP2_RMQ_VERSION => Some(Format::Bsp2Rmq),
+            _ => None,
+        }
+    }
+}
+
+// The structures below are the BSP2 layout, the other formats are widened into them on load.
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dnode_t {
+    pub plane_num: i32,     // 0x00
+    pub children: [i32; 2], // 0x04 - negative numbers are -(leafs+1), not nodes
+    pub mins: [f32; 3],     // 0x0C
+    pub maxs: [f32; 3],     // 0x18
+    pub first_face: u32,    // 0x24
+    pub num_faces: u32,     // 0x28
+} //Size=0x2C
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dclipnode_t {
+    pub plane_num: i32,     // 0x0
+    pub children: [i32; 2], // 0x4 - negative numbers are contents
+} //Size=0xC
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dleaf_t {
+    pub contents: i32,           // 0x00
+    pub vis_offset: i32,         // 0x04 - -1 = no visibility info
+    pub mins: [f32; 3],          // 0x08
+    pub maxs: [f32; 3],          // 0x14
+    pub first_mark_surface: u32, // 0x20
+    pub num_mark_surfaces: u32,  // 0x24
+    pub ambient_level: [u8; 4],  // 0x28
+} //Size=0x2C
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dface_t {
+    pub plane_num: i32,  // 0x00
+    pub side: i32,       // 0x04
+    pub first_edge: i32, // 0x08
+    pub num_edges: i32,  // 0x0C
+    pub tex_info: i32,   // 0x10
+    pub styles: [u8; 4], // 0x14
+    pub light_ofs: i32,  // 0x18 - start of [numstyles*surfsize] samples
+} //Size=0x1C
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dedge_t {
+    pub v: [u32; 2], // 0x0 - vertex numbers
+} //Size=0x8
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dnode_rmq_t {
+    pub plane_num: i32,     // 0x00
+    pub children: [i32; 2], // 0x04
+    pub mins: [i16; 3],     // 0x0C
+    pub maxs: [i16; 3],     // 0x12
+    pub first_face: u32,    // 0x18
+    pub num_faces: u32,     // 0x1C
+} //Size=0x20
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dleaf_rmq_t {
+    pub contents: i32,           // 0x00
+    pub vis_offset: i32,         // 0x04
+    pub mins: [i16; 3],          // 0x08
+    pub maxs: [i16; 3],          // 0x0E
+    pub first_mark_surface: u32, // 0x14
+    pub num_mark_surfaces: u32,  // 0x18
+    pub ambient_level: [u8; 4],  // 0x1C
+} //Size=0x20
+
+fn widen(v: [i16; 3]) -> [f32; 3] {
+    [v[0] as f32, v[1] as f32, v[2] as f32]
+}
+
+impl From<bsp29::dnode_t> for dnode_t {
+    fn from(node: bsp29::dnode_t) -> Self {
+        Self {
+            plane_num: node.plane_num,
+            children: [node.children[0] as i32, node.children[1] as i32],
+            mins: widen(node.mins),
+            maxs: widen(node.maxs),
+            first_face: node.first_face as u32,
+            num_faces: node.num_faces as u32,
+        }
+    }
+}
+
+impl From<dnode_rmq_t> for dnode_t {
+    fn from(node: dnode_rmq_t) -> Self {
+        Self {
+            plane_num: node.plane_num,
+            children: node.children,
+            mins: widen(node.mins),
+            maxs: widen(node.maxs),
+            first_face: node.first_face,
+            num_faces: node.num_faces,
+        }
+    }
+}
+
+impl From<bsp29::dclipnode_t> for dclipnode_t {
+    fn from(node: bsp29::dclipnode_t) -> Self {
+        Self {
+            plane_num: node.plane_num,
+            children: [node.children[0] as i32, node.children[1] as i32],
+        }
+    }
+}
+
+impl From<bsp29::dleaf_t> for dleaf_t {
+    fn from(leaf: bsp29::dleaf_t) -> Self {
+        Self {
+            contents: leaf.contents,
+            vis_offset: leaf.vis_offset,
+            mins: widen(leaf.mins),
+            maxs: widen(leaf.maxs),
+            first_mark_surface: leaf.first_mark_surface as u32,
+            num_mark_surfaces: leaf.num_mark_surfaces as u32,
+            ambient_level: leaf.ambient_level,
+        }
+    }
+}
+
+impl From<dleaf_rmq_t> for dleaf_t {
+    fn from(leaf: dleaf_rmq_t) -> Self {
+        Self {
+            contents: leaf.contents,
+            vis_offset: leaf.vis_offset,
+            mins: widen(leaf.mins),
+            maxs: widen(leaf.maxs),
+            first_mark_surface: leaf.first_mark_surface,
+            num_mark_surfaces: leaf.num_mark_surfaces,
+            ambient_level: leaf.ambient_level,
+        }
+    }
+}
+
+impl From<bsp29::dface_t> for dface_t {
+    fn from(face: bsp29::dface_t) -> Self {
+        Self {
+            plane_num: face.plane_num as i32,
+            side: face.side as i32,
+            first_edge: face.first_edge,
+            num_edges: face.num_edges as i32,
+            tex_info: face.tex_info as i32,
+            styles: face.styles,
+            light_ofs: face.light_ofs,
+        }
+    }
+}
+
+impl From<bsp29::dedge_t> for dedge_t {
+    fn from(edge: bsp29::dedge_t) -> Self {
+        Self {
+            v: [edge.v[0] as u32, edge.v[1] as u32],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn struct_sizes() {
+        assert_eq!(size_of::<dnode_t>(), 0x2C);
+        assert_eq!(size_of::<dclipnode_t>(), 0xC);
+        assert_eq!(size_of::<dleaf_t>(), 0x2C);
+        assert_eq!(size_of::<dface_t>(), 0x1C);
+        assert_eq!(size_of::<dedge_t>(), 0x8);
+        assert_eq!(size_of::<dnode_rmq_t>(), 0x20);
+        assert_eq!(size_of::<dleaf_rmq_t>(), 0x20);
+    }
+}
@@ -0,0 +1,374 @@
+//! Loader for Quake 3 (IBSP v46) and Return to Castle Wolfenstein / Enemy Territory (IBSP v47) maps.
+
+pub mod native;
+pub mod patch;
+
+pub use native::*;
+pub use patch::*;
+
+#[cfg(test)]
+pub(crate) mod fixture;
+
+use crate::error::*;
+use crate::lump_dir::{self, in_range, range_in_bounds};
+
+use std::fs::OpenOptions;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use dataview::{Pod, PodMethods};
+
+const FORMAT: &str = "Quake 3";
+
+pub struct Quake3BSP {
+    pub header: dheader_t,
+    pub shaders: Vec<dshader_t>,
+    pub planes: Vec<dplane_t>,
+    pub nodes: Vec<dnode_t>,
+    pub leaves: Vec<dleaf_t>,
+    pub leaf_surfaces: Vec<i32>,
+    pub leaf_brushes: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+    pub brushes: Vec<dbrush_t>,
+    pub brush_sides: Vec<dbrushside_t>,
+    pub draw_verts: Vec<drawvert_t>,
+    pub draw_indexes: Vec<i32>,
+    pub surfaces: Vec<dsurface_t>,
+    /// The triangles of each surface, patches are tessellated with `PATCH_SUBDIVISIONS`.
+    pub meshes: Vec<SurfaceMesh>,
+    raw_lumps: Vec<Vec<u8>>,
+}
+
+impl Quake3BSP {
+    /// Opens and parses the bsp file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(false).open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Parses a bsp that is fully held in memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    /// Parses a bsp from any seekable source.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+
+        let mut header = dheader_t::default();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(header.as_bytes_mut())?;
+        if header.ident != HEADER_MAGIC {
+            return Err(Error::BadMagic(header.ident));
+        }
+        if header.version != QUAKE3_VERSION && header.version != QUAKE3_VERSION_RTCW {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+
+        let raw_lumps = LumpIndex::ALL
+            .iter()
+            .map(|&index| {
+                let lump = header.lump(index);
+                lump_dir::read_lump(
+                    &mut reader,
+                    FORMAT,
+                    index as usize,
+                    lump.fileofs,
+                    lump.filelen,
+                    file_len,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut map = Self {
+            header,
+            shaders: cast(&raw_lumps, LumpIndex::Shaders)?,
+            planes: cast(&raw_lumps, LumpIndex::Planes)?,
+            nodes: cast(&raw_lumps, LumpIndex::Nodes)?,
+            leaves: cast(&raw_lumps, LumpIndex::Leafs)?,
+            leaf_surfaces: cast(&raw_lumps, LumpIndex::LeafSurfaces)?,
+            leaf_brushes: cast(&raw_lumps, LumpIndex::LeafBrushes)?,
+            models: cast(&raw_lumps, LumpIndex::Models)?,
+            brushes: cast(&raw_lumps, LumpIndex::Brushes)?,
+            brush_sides: cast(&raw_lumps, LumpIndex::BrushSides)?,
+            draw_verts: cast(&raw_lumps, LumpIndex::DrawVerts)?,
+            draw_indexes: cast(&raw_lumps, LumpIndex::DrawIndexes)?,
+            surfaces: cast(&raw_lumps, LumpIndex::Surfaces)?,
+            meshes: Vec::new(),
+            raw_lumps,
+        };
+        map.validate()?;
+
+        map.meshes = map
+            .surfaces
+            .iter()
+            .map(|surface| map.surface_mesh(surface, PATCH_SUBDIVISIONS))
+            .collect();
+
+        Ok(map)
+    }
+
+    /// Builds the triangles of a surface, patches are tessellated with the given subdivisions.
+    pub fn surface_mesh(&self, surface: &dsurface_t, subdivisions: usize) -> SurfaceMesh {
+        let vertices = match self.draw_verts.get(
+            surface.first_vert.max(0) as usize
+                ..(surface.first_vert as i64 + surface.num_verts as i64).max(0) as usize,
+        ) {
+            Some(vertices) => vertices,
+            None => return SurfaceMesh::default(),
+        };
+
+        match surface.surface_type {
+            MST_PATCH => tessellate_patch(
+                vertices,
+                surface.patch_width as usize,
+                surface.patch_height as usize,
+                subdivisions,
+            ),
+            MST_PLANAR | MST_TRIANGLE_SOUP => {
+                let indices = self
+                    .draw_indexes
+                    .get(
+                        surface.first_index.max(0) as usize
+                            ..(surface.first_index as i64 + surface.num_indexes as i64).max(0)
+                                as usize,
+                    )
+                    .unwrap_or(&[]);
+                SurfaceMesh {
+                    vertices: vertices.to_vec(),
+                    indices: indices
+                        .chunks_exact(3)
+                        .filter(|triangle| triangle.iter().all(|&i| in_range(i, vertices.len())))
+                        .flatten()
+                        .map(|&i| i as u32)
+                        .collect(),
+                }
+            }
+            _ => SurfaceMesh::default(),
+        }
+    }
+
+    /// The contents of a lump as stored in the file.
+    ///
+    /// This also gives access to the lumps that are not parsed (fogs, lightmaps, light grid and visibility).
+    pub fn raw_lump(&self, index: LumpIndex) -> &[u8] {
+        &self.raw_lumps[index as usize]
+    }
+
+    /// Checks all cross-lump references so the tree can be walked without further checks.
+    fn validate(&self) -> Result<()> {
+        let planes = self.planes.len();
+        let shaders = self.shaders.len();
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            check(
+                in_range(node.plane_num, planes),
+                LumpIndex::Nodes,
+                "invalid plane index",
+            )?;
+            for &child in node.children.iter() {
+                let valid = if child >= 0 {
+                    child as usize > i && (child as usize) < self.nodes.len()
+                } else {
+                    in_range(-1 - child, self.leaves.len())
+                };
+                check(valid, LumpIndex::Nodes, "invalid child index")?;
+            }
+        }
+
+        for leaf in self.leaves.iter() {
+            check(
+                range_in_bounds(
+                    leaf.first_leaf_surface,
+                    leaf.num_leaf_surfaces,
+                    self.leaf_surfaces.len(),
+                ),
+                LumpIndex::Leafs,
+                "invalid leaf surface range",
+            )?;
+            check(
+                range_in_bounds(
+                    leaf.first_leaf_brush,
+                    leaf.num_leaf_brushes,
+                    self.leaf_brushes.len(),
+                ),
+                LumpIndex::Leafs,
+                "invalid leaf brush range",
+            )?;
+        }
+
+        for &surface in self.leaf_surfaces.iter() {
+            check(
+                in_range(surface, self.surfaces.len()),
+                LumpIndex::LeafSurfaces,
+                "invalid surface index",
+            )?;
+        }
+
+        for &brush in self.leaf_brushes.iter() {
+            check(
+                in_range(brush, self.brushes.len()),
+                LumpIndex::LeafBrushes,
+                "invalid brush index",
+            )?;
+        }
+
+        for brush in self.brushes.iter() {
+            check(
+                range_in_bounds(brush.first_side, brush.num_sides, self.brush_sides.len()),
+                LumpIndex::Brushes,
+                "invalid brush side range",
+            )?;
+            check(
+                in_range(brush.shader_num, shaders),
+                LumpIndex::Brushes,
+                "invalid shader index",
+            )?;
+        }
+
+        for side in self.brush_sides.iter() {
+            check(
+                in_range(side.plane_num, planes),
+                LumpIndex::BrushSides,
+                "invalid plane index",
+            )?;
+            check(
+                in_range(side.shader_num, shaders),
+                LumpIndex::BrushSides,
+                "invalid shader index",
+            )?;
+        }
+
+        for surface in self.surfaces.iter() {
+            check(
+                in_range(surface.shader_num, shaders),
+                LumpIndex::Surfaces,
+                "invalid shader index",
+            )?;
+            check(
+                range_in_bounds(surface.first_vert, surface.num_verts, self.draw_verts.len()),
+                LumpIndex::Surfaces,
+                "invalid vertex range",
+            )?;
+            check(
+                range_in_bounds(
+                    surface.first_index,
+                    surface.num_indexes,
+                    self.draw_indexes.len(),
+                ),
+                LumpIndex::Surfaces,
+                "invalid index range",
+            )?;
+            if surface.surface_type == MST_PATCH {
+                check(
+                    is_valid_patch(surface.patch_width, surface.patch_height)
+                        && surface.patch_width as i64 * surface.patch_height as i64
+                            == surface.num_verts as i64,
+                    LumpIndex::Surfaces,
+                    "invalid patch size",
+                )?;
+            }
+        }
+
+        for model in self.models.iter() {
+            check(
+                range_in_bounds(model.first_surface, model.num_surfaces, self.surfaces.len()),
+                LumpIndex::Models,
+                "invalid surface range",
+            )?;
+            check(
+                range_in_bounds(model.first_brush, model.num_brushes, self.brushes.len()),
+                LumpIndex::Models,
+                "invalid brush range",
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(lump: LumpIndex, reason: &'static str) -> Error {
+    lump_dir::invalid(FORMAT, lump as usize, reason)
+}
+
+fn check(valid: bool, lump: LumpIndex, reason: &'static str) -> Result<()> {
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid(lump, reason))
+    }
+}
+
+fn cast<T: Pod + Clone>(raw_lumps: &[Vec<u8>], index: LumpIndex) -> Result<Vec<T>> {
+    lump_dir::cast(FORMAT, index as usize, &raw_lumps[index as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::MapBuilder;
+    use super::*;
+
+    #[test]
+    fn box_room() {
+        let map = Quake3BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert_eq!(map.shaders[1].name(), "textures/base/curve");
+        assert_eq!(map.planes.len(), 7);
+        assert_eq!(map.brushes.len(), 1);
+        assert_eq!(map.brush_sides.len(), 6);
+        assert_eq!(map.surfaces.len(), 2);
+        assert!(map.raw_lump(LumpIndex::Entities).starts_with(b"{"));
+
+        // the planar surface is a quad
+        assert_eq!(map.meshes[0].vertices.len(), 4);
+        assert_eq!(map.meshes[0].triangles().count(), 2);
+
+        // the patch bulges out to x = 72 in its center
+        let patch = &map.meshes[1];
+        let side = PATCH_SUBDIVISIONS + 1;
+        assert_eq!(patch.vertices.len(), side * side);
+        assert_eq!(
+            patch.triangles().count(),
+            PATCH_SUBDIVISIONS * PATCH_SUBDIVISIONS * 2
+        );
+        assert_eq!(patch.vertices[side * side / 2].xyz, [72f32, 0f32, 0f32]);
+
+        let coarse = map.surface_mesh(&map.surfaces[1], 2);
+        assert_eq!(coarse.vertices.len(), 9);
+    }
+
+    #[test]
+    fn rtcw_version() {
+        let data = MapBuilder::box_room().version(QUAKE3_VERSION_RTCW).build();
+        assert!(Quake3BSP::from_bytes(&data).is_ok());
+
+        let data = MapBuilder::box_room().version(45).build();
+        assert!(matches!(
+            Quake3BSP::from_bytes(&data),
+            Err(Error::UnsupportedVersion(45))
+        ));
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut data = MapBuilder::box_room().build();
+        data[0] = b'V';
+        assert!(matches!(
+            Quake3BSP::from_bytes(&data),
+            Err(Error::BadMagic(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_patch() {
+        let mut builder = MapBuilder::box_room();
+        builder.surfaces[1].patch_width = 2;
+        assert!(matches!(
+            Quake3BSP::from_bytes(&builder.build()),
+            Err(Error::InvalidMapLump {
+                format: "Quake 3",
+                lump: 13,
+                reason: "invalid patch size",
+            })
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+//! Synthetic Quake 3 maps for unit tests.
+//!
+//! The box room consists of a single solid 32x32x32 brush centered around the origin
+//! which sits in a tree with one node splitting the world at x=0.
+//! A 3x3 patch at x=64 bulges out to x=72 in its center.
+
+use super::native::*;
+
+use std::mem::size_of;
+
+use dataview::PodMethods;
+
+pub const BOX_ENTITIES: &str = "{\n\"classname\" \"worldspawn\"\n}\n\0";
+
+#[derive(Clone)]
+pub struct MapBuilder {
+    pub version: i32,
+    pub entities: String,
+    pub shaders: Vec<dshader_t>,
+    pub planes: Vec<dplane_t>,
+    pub nodes: Vec<dnode_t>,
+    pub leaves: Vec<dleaf_t>,
+    pub leaf_surfaces: Vec<i32>,
+    pub leaf_brushes: Vec<i32>,
+    pub models: Vec<dmodel_t>,
+    pub brushes: Vec<dbrush_t>,
+    pub brush_sides: Vec<dbrushside_t>,
+    pub draw_verts: Vec<drawvert_t>,
+    pub draw_indexes: Vec<i32>,
+    pub surfaces: Vec<dsurface_t>,
+}
+
+pub fn shader(name: &str, surface_flags: i32, content_flags: i32) -> dshader_t {
+    let mut shader = dshader_t::zeroed();
+    shader.shader[..name.len()].copy_from_slice(name.as_bytes());
+    shader.surface_flags = surface_flags;
+    shader.content_flags = content_flags;
+    shader
+}
+
+fn vertex(xyz: [f32; 3]) -> drawvert_t {
+    let mut vertex = drawvert_t::zeroed();
+    vertex.xyz = xyz;
+    vertex.normal = [-1f32, 0f32, 0f32];
+    vertex.color = [255; 4];
+    vertex
+}
+
+impl MapBuilder {
+    pub fn box_room() -> Self {
+        let shaders = vec![
+            shader("textures/base/wall", 0, CONTENTS_SOLID),
+            shader("textures/base/curve", SURF_NOMARKS, CONTENTS_SOLID),
+        ];
+
+        // the six outward facing sides of the brush and the splitting plane
+        let mut planes = Vec::new();
+        for axis in 0..3 {
+            for &sign in [1f32, -1f32].iter() {
+                let mut normal = [0f32; 3];
+                normal[axis] = sign;
+                planes.push(dplane_t {
+                    normal,
+                    distance: 16f32,
+                });
+            }
+        }
+        planes.push(dplane_t {
+            normal: [1f32, 0f32, 0f32],
+            distance: 0f32,
+        });
+
+        let mut node = dnode_t::zeroed();
+        node.plane_num = 6;
+        node.children = [-1, -2];
+        node.mins = [-128; 3];
+        node.maxs = [128; 3];
+
+        let mut front = dleaf_t::zeroed();
+        front.num_leaf_surfaces = 2;
+        front.num_leaf_brushes = 1;
+        let mut back = dleaf_t::zeroed();
+        back.first_leaf_surface = 2;
+        back.first_leaf_brush = 1;
+        back.num_leaf_brushes = 1;
+
+        let brush_sides = (0..6)
+            .map(|plane_num| dbrushside_t {
+                plane_num,
+                shader_num: 0,
+            })
+            .collect();
+
+        // the +x side of the brush as a quad and the patch
+        let mut draw_verts = vec![
+            vertex([16f32, -16f32, -16f32]),
+            vertex([16f32, 16f32, -16f32]),
+            vertex([16f32, 16f32, 16f32]),
+            vertex([16f32, -16f32, 16f32]),
+        ];
+        for &z in [-32f32, 0f32, 32f32].iter() {
+            for &(x, y) in [(64f32, -32f32), (80f32, 0f32), (64f32, 32f32)].iter() {
+                draw_verts.push(vertex([x, y, z]));
+            }
+        }
+
+        let mut quad = dsurface_t::zeroed();
+        quad.surface_type = MST_PLANAR;
+        quad.fog_num = -1;
+        quad.num_verts = 4;
+        quad.num_indexes = 6;
+        let mut patch = dsurface_t::zeroed();
+        patch.shader_num = 1;
+        patch.surface_type = MST_PATCH;
+        patch.fog_num = -1;
+        patch.first_vert = 4;
+        patch.num_verts = 9;
+        patch.patch_width = 3;
+        patch.patch_height = 3;
+
+        let model = dmodel_t {
+            mins: [-128f32; 3],
+            maxs: [128f32; 3],
+            first_surface: 0,
+            num_surfaces: 2,
+            first_brush: 0,
+            num_brushes: 1,
+        };
+
+        Self {
+            version: QUAKE3_VERSION,
+            entities: BOX_ENTITIES.to_string(),
+            shaders,
+            planes,
+            nodes: vec![node],
+            leaves: vec![front, back],
+            leaf_surfaces: vec![0, 1],
+            leaf_brushes: vec![0, 0],
+            models: vec![model],
+            brushes: vec![dbrush_t {
+                first_side: 0,
+                num_sides: 6,
+                shader_num: 0,
+            }],
+            brush_sides,
+            draw_verts,
+            draw_indexes: vec![0, 1, 2, 0, 2, 3],
+            surfaces: vec![quad, patch],
+        }
+    }
+
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut lumps = vec![Vec::new(); HEADER_LUMP_COUNT];
+        let mut set = |index: LumpIndex, data: &[u8]| lumps[index as usize] = data.to_vec();
+        set(LumpIndex::Entities, self.entities.as_bytes());
+        set(LumpIndex::Shaders, self.shaders.as_bytes());
+        set(LumpIndex::Planes, self.planes.as_bytes());
+        set(LumpIndex::Nodes, self.nodes.as_bytes());
+        set(LumpIndex::Leafs, self.leaves.as_bytes());
+        set(LumpIndex::LeafSurfaces, self.leaf_surfaces.as_bytes());
+        set(LumpIndex::LeafBrushes, self.leaf_brushes.as_bytes());
+        set(LumpIndex::Models, self.models.as_bytes());
+        set(LumpIndex::Brushes, self.brushes.as_bytes());
+        set(LumpIndex::BrushSides, self.brush_sides.as_bytes());
+        set(LumpIndex::DrawVerts, self.draw_verts.as_bytes());
+        set(LumpIndex::DrawIndexes, self.draw_indexes.as_bytes());
+        set(LumpIndex::Surfaces, self.surfaces.as_bytes());
+
+        let mut header = dheader_t {
+            ident: HEADER_MAGIC,
+            version: self.version,
+            ..Default::default()
+        };
+
+        let mut data = vec![0u8; size_of::<dheader_t>()];
+        for (i, lump) in lumps.iter().enumerate() {
+            while !data.len().is_multiple_of(4) {
+                data.push(0);
+            }
+            header.lumps[i] = lump_t {
+                fileofs: data.len() as i32,
+                filelen: lump.len() as i32,
+            };
+            data.extend_from_slice(lump);
+        }
+
+        data[..size_of::<dheader_t>()].copy_from_slice(header.as_bytes());
+        data
+    }
+}
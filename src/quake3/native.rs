@@ -0,0 +1,271 @@
+#![allow(dead_code)]
+
+use dataview::Pod;
+
+pub const HEADER_MAGIC: i32 = 0x50534249; // 'IBSP'
+pub const QUAKE3_VERSION: i32 = 46;
+/// Return to Castle Wolfenstein, Enemy Territory and Quake Live.
+pub const QUAKE3_VERSION_RTCW: i32 = 47;
+pub const HEADER_LUMP_COUNT: usize = 17;
+
+// content flags of shaders and brushes
+pub const CONTENTS_SOLID: i32 = 0x1;
+pub const CONTENTS_LAVA: i32 = 0x8;
+pub const CONTENTS_SLIME: i32 = 0x10;
+pub const CONTENTS_WATER: i32 = 0x20;
+pub const CONTENTS_FOG: i32 = 0x40;
+pub const CONTENTS_NOTTEAM1: i32 = 0x80;
+pub const CONTENTS_NOTTEAM2: i32 = 0x100;
+pub const CONTENTS_NOBOTCLIP: i32 = 0x200;
+pub const CONTENTS_AREAPORTAL: i32 = 0x8000;
+pub const CONTENTS_PLAYERCLIP: i32 = 0x10000;
+pub const CONTENTS_MONSTERCLIP: i32 = 0x20000;
+pub const CONTENTS_TELEPORTER: i32 = 0x40000;
+pub const CONTENTS_JUMPPAD: i32 = 0x80000;
+pub const CONTENTS_CLUSTERPORTAL: i32 = 0x100000;
+pub const CONTENTS_DONOTENTER: i32 = 0x200000;
+pub const CONTENTS_BOTCLIP: i32 = 0x400000;
+pub const CONTENTS_MOVER: i32 = 0x800000;
+/// removed before bsping an entity
+pub const CONTENTS_ORIGIN: i32 = 0x1000000;
+/// should never be on a brush, only in game
+pub const CONTENTS_BODY: i32 = 0x2000000;
+pub const CONTENTS_CORPSE: i32 = 0x4000000;
+/// brushes not used for the bsp
+pub const CONTENTS_DETAIL: i32 = 0x8000000;
+/// brushes used for the bsp
+pub const CONTENTS_STRUCTURAL: i32 = 0x10000000;
+/// don't consume surface fragments inside
+pub const CONTENTS_TRANSLUCENT: i32 = 0x20000000;
+pub const CONTENTS_TRIGGER: i32 = 0x40000000;
+/// don't leave bodies or items (death fog, lava)
+pub const CONTENTS_NODROP: i32 = 0x80000000u32 as i32;
+
+pub const MASK_SOLID: i32 = CONTENTS_SOLID;
+pub const MASK_SHOT: i32 = CONTENTS_SOLID | CONTENTS_BODY | CONTENTS_CORPSE;
+pub const MASK_OPAQUE: i32 = CONTENTS_SOLID | CONTENTS_SLIME | CONTENTS_LAVA;
+
+// surface flags of shaders
+/// never give falling damage
+pub const SURF_NODAMAGE: i32 = 0x1;
+/// affects game physics
+pub const SURF_SLICK: i32 = 0x2;
+/// lighting from environment map
+pub const SURF_SKY: i32 = 0x4;
+/// don't make missile explosions
+pub const SURF_NOIMPACT: i32 = 0x10;
+/// don't leave missile marks
+pub const SURF_NOMARKS: i32 = 0x20;
+/// don't generate a drawsurface at all
+pub const SURF_NODRAW: i32 = 0x80;
+/// don't collide against curves with this set
+pub const SURF_NONSOLID: i32 = 0x4000;
+
+// surface types
+pub const MST_BAD: i32 = 0;
+pub const MST_PLANAR: i32 = 1;
+pub const MST_PATCH: i32 = 2;
+pub const MST_TRIANGLE_SOUP: i32 = 3;
+pub const MST_FLARE: i32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LumpIndex {
+    Entities = 0,
+    Shaders = 1,
+    Planes = 2,
+    Nodes = 3,
+    Leafs = 4,
+    LeafSurfaces = 5,
+    LeafBrushes = 6,
+    Models = 7,
+    Brushes = 8,
+    BrushSides = 9,
+    DrawVerts = 10,
+    DrawIndexes = 11,
+    Fogs = 12,
+    Surfaces = 13,
+    Lightmaps = 14,
+    LightGrid = 15,
+    Visibility = 16,
+}
+
+impl LumpIndex {
+    /// All lumps in header order.
+    pub const ALL: [LumpIndex; HEADER_LUMP_COUNT] = [
+        LumpIndex::Entities,
+        LumpIndex::Shaders,
+        LumpIndex::Planes,
+        LumpIndex::Nodes,
+        LumpIndex::Leafs,
+        LumpIndex::LeafSurfaces,
+        LumpIndex::LeafBrushes,
+        LumpIndex::Models,
+        LumpIndex::Brushes,
+        LumpIndex::BrushSides,
+        LumpIndex::DrawVerts,
+        LumpIndex::DrawIndexes,
+        LumpIndex::Fogs,
+        LumpIndex::Surfaces,
+        LumpIndex::Lightmaps,
+        LumpIndex::LightGrid,
+        LumpIndex::Visibility,
+    ];
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct lump_t {
+    pub fileofs: i32, // 0x0
+    pub filelen: i32, // 0x4
+} //Size=0x8
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dheader_t {
+    pub ident: i32,                         // 0x00
+    pub version: i32,                       // 0x04
+    pub lumps: [lump_t; HEADER_LUMP_COUNT], // 0x08
+} //Size=0x90
+
+impl Default for dheader_t {
+    fn default() -> Self {
+        unsafe { core::mem::zeroed() }
+    }
+}
+
+impl dheader_t {
+    pub fn lump(&self, index: LumpIndex) -> &lump_t {
+        &self.lumps[index as usize]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dshader_t {
+    pub shader: [u8; 64],   // 0x00
+    pub surface_flags: i32, // 0x40
+    pub content_flags: i32, // 0x44
+} //Size=0x48
+
+impl dshader_t {
+    /// The shader name up to the first nul byte.
+    pub fn name(&self) -> String {
+        let len = self.shader.iter().position(|&c| c == 0).unwrap_or(64);
+        String::from_utf8_lossy(&self.shader[..len]).to_string()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dplane_t {
+    pub normal: [f32; 3], // 0x00
+    pub distance: f32,    // 0x0C
+} //Size=0x10
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dnode_t {
+    pub plane_num: i32,     // 0x00
+    pub children: [i32; 2], // 0x04 - negative numbers are -(leafs+1), not nodes
+    pub mins: [i32; 3],     // 0x0C
+    pub maxs: [i32; 3],     // 0x18
+} //Size=0x24
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dleaf_t {
+    pub cluster: i32,            // 0x00 - -1 = opaque cluster
+    pub area: i32,               // 0x04
+    pub mins: [i32; 3],          // 0x08
+    pub maxs: [i32; 3],          // 0x14
+    pub first_leaf_surface: i32, // 0x20
+    pub num_leaf_surfaces: i32,  // 0x24
+    pub first_leaf_brush: i32,   // 0x28
+    pub num_leaf_brushes: i32,   // 0x2C
+} //Size=0x30
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dmodel_t {
+    pub mins: [f32; 3],     // 0x00
+    pub maxs: [f32; 3],     // 0x0C
+    pub first_surface: i32, // 0x18
+    pub num_surfaces: i32,  // 0x1C
+    pub first_brush: i32,   // 0x20
+    pub num_brushes: i32,   // 0x24
+} //Size=0x28
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dbrush_t {
+    pub first_side: i32, // 0x0
+    pub num_sides: i32,  // 0x4
+    pub shader_num: i32, // 0x8 - the shader that determines the contents flags
+} //Size=0xC
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dbrushside_t {
+    pub plane_num: i32,  // 0x0 - positive plane side faces out of the leaf
+    pub shader_num: i32, // 0x4
+} //Size=0x8
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct drawvert_t {
+    pub xyz: [f32; 3],      // 0x00
+    pub st: [f32; 2],       // 0x0C
+    pub lightmap: [f32; 2], // 0x14
+    pub normal: [f32; 3],   // 0x1C
+    pub color: [u8; 4],     // 0x28
+} //Size=0x2C
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dfog_t {
+    pub shader: [u8; 64],  // 0x00
+    pub brush_num: i32,    // 0x40
+    pub visible_side: i32, // 0x44 - the brush side that ray tests need to clip against (-1 == none)
+} //Size=0x48
+
+#[repr(C)]
+#[derive(Clone, Debug, Pod)]
+pub struct dsurface_t {
+    pub shader_num: i32,              // 0x00
+    pub fog_num: i32,                 // 0x04
+    pub surface_type: i32,            // 0x08
+    pub first_vert: i32,              // 0x0C
+    pub num_verts: i32,               // 0x10
+    pub first_index: i32,             // 0x14
+    pub num_indexes: i32,             // 0x18
+    pub lightmap_num: i32,            // 0x1C
+    pub lightmap_x: i32,              // 0x20
+    pub lightmap_y: i32,              // 0x24
+    pub lightmap_width: i32,          // 0x28
+    pub lightmap_height: i32,         // 0x2C
+    pub lightmap_origin: [f32; 3],    // 0x30
+    pub lightmap_vecs: [[f32; 3]; 3], // 0x3C - for patches, [2] is the normal of flat patches
+    pub patch_width: i32,             // 0x60
+    pub patch_height: i32,            // 0x64
+} //Size=0x68
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn struct_sizes() {
+        assert_eq!(size_of::<dheader_t>(), 0x90);
+        assert_eq!(size_of::<dshader_t>(), 0x48);
+        assert_eq!(size_of::<dplane_t>(), 0x10);
+        assert_eq!(size_of::<dnode_t>(), 0x24);
+        assert_eq!(size_of::<dleaf_t>(), 0x30);
+        assert_eq!(size_of::<dmodel_t>(), 0x28);
+        assert_eq!(size_of::<dbrush_t>(), 0xC);
+        assert_eq!(size_of::<dbrushside_t>(), 0x8);
+        assert_eq!(size_of::<drawvert_t>(), 0x2C);
+        assert_eq!(size_of::<dfog_t>(), 0x48);
+        assert_eq!(size_of::<dsurface_t>(), 0x68);
+    }
+}
@@ -0,0 +1,168 @@
+//! Tessellation of bezier patch surfaces.
+//!
+//! A patch is a grid of `patch_width * patch_height` control points (both odd) that forms
+//! a mesh of biquadratic bezier patches, neighbouring patches share their border control points.
+
+use super::native::*;
+use crate::bsp::math;
+
+/// Subdivisions per bezier patch used by the loader.
+pub const PATCH_SUBDIVISIONS: usize = 8;
+
+/// Triangles of a surface, patches are tessellated.
+#[derive(Clone, Debug, Default)]
+pub struct SurfaceMesh {
+    pub vertices: Vec<drawvert_t>,
+    /// Three indices into `vertices` per triangle.
+    pub indices: Vec<u32>,
+}
+
+impl SurfaceMesh {
+    /// Iterates over the corner positions of all triangles.
+    pub fn triangles(&self) -> impl Iterator<Item = [[f32; 3]; 3]> + '_ {
+        self.indices.chunks_exact(3).map(move |triangle| {
+            [
+                self.vertices[triangle[0] as usize].xyz,
+                self.vertices[triangle[1] as usize].xyz,
+                self.vertices[triangle[2] as usize].xyz,
+            ]
+        })
+    }
+}
+
+/// Returns true if the control point grid describes a valid patch.
+pub fn is_valid_patch(width: i32, height: i32) -> bool {
+    width >= 3 && height >= 3 && width % 2 == 1 && height % 2 == 1
+}
+
+/// Tessellates the control points of a patch with `subdivisions` steps per bezier patch.
+///
+/// The control points are stored row by row, `control_points.len()` must be `width * height`.
+pub fn tessellate_patch(
+    control_points: &[drawvert_t],
+    width: usize,
+    height: usize,
+    subdivisions: usize,
+) -> SurfaceMesh {
+    if !is_valid_patch(width as i32, height as i32)
+        || control_points.len() != width * height
+        || subdivisions == 0
+    {
+        return SurfaceMesh::default();
+    }
+
+    let patches_x = (width - 1) / 2;
+    let patches_y = (height - 1) / 2;
+    let columns = patches_x * subdivisions + 1;
+    let rows = patches_y * subdivisions + 1;
+
+    // splits a grid position into the bezier patch and the position inside of it
+    let locate = |position: usize, patches: usize| {
+        let patch = (position / subdivisions).min(patches - 1);
+        let t = (position - patch * subdivisions) as f32 / subdivisions as f32;
+        (patch * 2, t)
+    };
+
+    let mut vertices = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        let (y, v) = locate(row, patches_y);
+        for column in 0..columns {
+            let (x, u) = locate(column, patches_x);
+
+            // evaluate the three rows of the patch along u and then the results along v
+            let mut curve = Vec::with_capacity(3);
+            for i in 0..3 {
+                let row = &control_points[(y + i) * width + x..];
+                curve.push(bezier(&row[0], &row[1], &row[2], u));
+            }
+            let mut vertex = bezier(&curve[0], &curve[1], &curve[2], v);
+            vertex.normal = math::normalize(vertex.normal);
+            vertices.push(vertex);
+        }
+    }
+
+    let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+    for row in 0..rows - 1 {
+        for column in 0..columns - 1 {
+            let v0 = (row * columns + column) as u32;
+            let v1 = v0 + 1;
+            let v2 = v0 + columns as u32;
+            let v3 = v2 + 1;
+            indices.extend_from_slice(&[v0, v2, v1, v1, v2, v3]);
+        }
+    }
+
+    SurfaceMesh { vertices, indices }
+}
+
+fn bezier(a: &drawvert_t, b: &drawvert_t, c: &drawvert_t, t: f32) -> drawvert_t {
+    let w = [(1f32 - t) * (1f32 - t), 2f32 * t * (1f32 - t), t * t];
+    let blend = |a: f32, b: f32, c: f32| a * w[0] + b * w[1] + c * w[2];
+
+    let mut out = a.clone();
+    for i in 0..3 {
+        out.xyz[i] = blend(a.xyz[i], b.xyz[i], c.xyz[i]);
+        out.normal[i] = blend(a.normal[i], b.normal[i], c.normal[i]);
+    }
+    for i in 0..2 {
+        out.st[i] = blend(a.st[i], b.st[i], c.st[i]);
+        out.lightmap[i] = blend(a.lightmap[i], b.lightmap[i], c.lightmap[i]);
+    }
+    for i in 0..4 {
+        let color = blend(a.color[i] as f32, b.color[i] as f32, c.color[i] as f32);
+        out.color[i] = color.round().clamp(0f32, 255f32) as u8;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dataview::PodMethods;
+
+    fn grid(width: usize, height: usize) -> Vec<drawvert_t> {
+        let mut points = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let mut vertex = drawvert_t::zeroed();
+                // bulge the odd columns outwards
+                vertex.xyz = [(x % 2) as f32 * 16f32, x as f32 * 32f32, y as f32 * 32f32];
+                vertex.normal = [1f32, 0f32, 0f32];
+                vertex.color = [255; 4];
+                points.push(vertex);
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn tessellate() {
+        let mesh = tessellate_patch(&grid(3, 3), 3, 3, 4);
+        assert_eq!(mesh.vertices.len(), 25);
+        assert_eq!(mesh.indices.len(), 4 * 4 * 6);
+        assert_eq!(mesh.triangles().count(), 32);
+
+        // corners lie on the control points, the middle is pulled halfway towards the bulge
+        assert_eq!(mesh.vertices[0].xyz, [0f32, 0f32, 0f32]);
+        assert_eq!(mesh.vertices[24].xyz, [0f32, 64f32, 64f32]);
+        assert_eq!(mesh.vertices[12].xyz, [8f32, 32f32, 32f32]);
+        assert_eq!(mesh.vertices[12].color, [255; 4]);
+        assert_eq!(mesh.vertices[12].normal, [1f32, 0f32, 0f32]);
+    }
+
+    #[test]
+    fn tessellate_multiple_patches() {
+        let mesh = tessellate_patch(&grid(5, 3), 5, 3, 2);
+        assert_eq!(mesh.vertices.len(), 5 * 3);
+        // the shared border of both patches passes through the control points
+        assert_eq!(mesh.vertices[2].xyz, [0f32, 64f32, 0f32]);
+    }
+
+    #[test]
+    fn invalid_patches() {
+        assert!(tessellate_patch(&grid(4, 3), 4, 3, 4).vertices.is_empty());
+        assert!(tessellate_patch(&grid(3, 3), 3, 5, 4).vertices.is_empty());
+        assert!(!is_valid_patch(1, 3));
+    }
+}
@@ -1,5 +1,14 @@
+pub mod hull;
+pub mod quake3;
+pub mod world;
+
+pub use world::CollisionWorld;
+
 use crate::bsp::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 pub const CONTENTS_EMPTY: i32 = 0;
 /// No contents
 pub const CONTENTS_SOLID: i32 = 0x1;
@@ -57,6 +66,7 @@ pub const MASK_SHOT_HULL: i32 = CONTENTS_SOLID
 
 pub const DIST_EPSILON: f32 = 0.03125f32;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Trace {
     pub all_solid: bool,
     pub start_solid: bool,
@@ -65,6 +75,8 @@ pub struct Trace {
     pub end_pos: [f32; 3],
     pub plane: Option<cplane_t>, // BSP::cplane_t*
     pub contents: i32,
+    /// Surface flags of the hit surface, only set for formats with surface flags (Quake 3).
+    pub surface_flags: i32,
     pub brush: Option<dbrush_t>, // BSP::dbrush_t*
     pub brush_side: i32,
 }
@@ -79,21 +91,63 @@ impl Trace {
             end_pos: [0f32; 3],
             plane: None,
             contents: 0,
+            surface_flags: 0,
             brush: None,
             brush_side: 0,
         }
     }
 }
 
-pub fn is_visible(bsp: &BSP, from: [f32; 3], to: [f32; 3]) -> bool {
+/// A map that rays can be cast against, implemented by all supported formats.
+///
+/// Every `CollisionWorld` traces its brushes and faces with the code below, GoldSrc and Quake
+/// maps trace the point hull of the world and Quake 3 maps their brushes and patches.
+pub trait Traceable {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace);
+}
+
+impl<W: CollisionWorld + ?Sized> Traceable for W {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+        ray_cast_world(self, from, to, trace);
+    }
+}
+
+pub fn is_visible<W: Traceable + ?Sized>(world: &W, from: [f32; 3], to: [f32; 3]) -> bool {
     let mut trace = Trace::new();
-    ray_cast(bsp, from, to, &mut trace);
+    ray_cast(world, from, to, &mut trace);
 
     !(trace.fraction < 1f32)
 }
 
-pub fn ray_cast(bsp: &BSP, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
-    if bsp.planes.is_empty() {
+pub fn ray_cast<W: Traceable + ?Sized>(world: &W, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+    world.ray_cast(from, to, trace);
+}
+
+/// Builds the plane a trace reports as hit, the sign bits are derived from the normal.
+pub(crate) fn make_plane(normal: [f32; 3], distance: f32, typ: u8) -> cplane_t {
+    let mut sign_bits = 0u8;
+    for (i, &n) in normal.iter().enumerate() {
+        if n < 0f32 {
+            sign_bits |= 1 << i;
+        }
+    }
+    cplane_t {
+        normal,
+        distance,
+        typ,
+        sign_bits,
+        pad0: [0u8; 2],
+    }
+}
+
+fn ray_cast_world<W: CollisionWorld + ?Sized>(
+    world: &W,
+    from: [f32; 3],
+    to: [f32; 3],
+    trace: &mut Trace,
+) {
+    // a world without planes has nothing to collide with
+    if world.plane(0).is_none() {
         return;
     }
 
@@ -102,7 +156,7 @@ pub fn ray_cast(bsp: &BSP, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
     trace.fraction = 1f32;
     trace.fraction_left_solid = 0f32;
 
-    ray_cast_node(bsp, from, to, 0, 0f32, 1f32, trace);
+    ray_cast_node(world, from, to, world.head_node(), 0f32, 1f32, trace);
 
     if trace.fraction < 1f32 {
         for i in 0..3 {
@@ -113,8 +167,8 @@ pub fn ray_cast(bsp: &BSP, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
     }
 }
 
-fn ray_cast_node(
-    bsp: &BSP,
+fn ray_cast_node<W: CollisionWorld + ?Sized>(
+    world: &W,
     from: [f32; 3],
     to: [f32; 3],
     node_idx: i32,
@@ -127,24 +181,17 @@ fn ray_cast_node(
     }
 
     if node_idx < 0 {
-        let leaf = &bsp.leaves[(-node_idx - 1) as usize];
-        for i in 0..(leaf.num_leaf_brushes) {
-            let leaf_idx = (leaf.first_leaf_brush + i) as usize;
-            if leaf_idx >= bsp.leaf_brushes.len() {
-                println!("shouldnt happen?");
-                continue;
-            }
-            let brush_idx = bsp.leaf_brushes[leaf_idx] as i32;
-
-            if brush_idx as usize >= bsp.brushes.len() {
-                continue;
-            }
-            let brush = &bsp.brushes[brush_idx as usize];
+        let leaf = -1 - node_idx;
+        for &brush_idx in world.leaf_brushes(leaf).iter() {
+            let brush = match world.brush(brush_idx as i32) {
+                Some(brush) => brush,
+                None => continue,
+            };
             if (brush.contents & MASK_SHOT_HULL) == 0 {
                 continue;
             }
 
-            ray_cast_brush(bsp, from, to, brush, trace);
+            ray_cast_brush(world, from, to, brush, trace);
             if trace.fraction == 0f32 {
                 return;
             }
@@ -158,30 +205,22 @@ fn ray_cast_node(
             return;
         }
 
-        for i in 0..(leaf.num_leaf_faces) {
-            ray_cast_surface(
-                bsp,
-                from,
-                to,
-                bsp.leaf_faces[(leaf.first_leaf_face + i) as usize] as i32,
-                trace,
-            );
+        for &leaf_face in world.leaf_faces(leaf).iter() {
+            ray_cast_surface(world, from, to, leaf_face as i32, trace);
         }
 
         return;
     }
 
     // regular path
-    if node_idx as usize >= bsp.nodes.len() {
-        return;
-    }
-    let node = &bsp.nodes[node_idx as usize];
+    let (plane, children) = match world.node(node_idx) {
+        Some(node) => node,
+        None => return,
+    };
 
-    if node.plane_idx as usize >= bsp.planes.len() {
+    if !validate::children_follow_parent(node_idx, &children) {
         return;
     }
-    let plane = &bsp.planes[node.plane_idx as usize];
-    // check plane count
 
     let (start_dist, end_dist) = {
         if plane.typ < 3 {
@@ -198,25 +237,9 @@ fn ray_cast_node(
     };
 
     if start_dist >= 0f32 && end_dist >= 0f32 {
-        ray_cast_node(
-            bsp,
-            from,
-            to,
-            node.children[0],
-            start_fract,
-            end_fract,
-            trace,
-        );
+        ray_cast_node(world, from, to, children[0], start_fract, end_fract, trace);
     } else if start_dist < 0f32 && end_dist < 0f32 {
-        ray_cast_node(
-            bsp,
-            from,
-            to,
-            node.children[1],
-            start_fract,
-            end_fract,
-            trace,
-        );
+        ray_cast_node(world, from, to, children[1], start_fract, end_fract, trace);
     } else {
         let mut side_id = 0i32;
         let mut fraction_first = 0f32;
@@ -263,10 +286,10 @@ fn ray_cast_node(
         }
 
         ray_cast_node(
-            bsp,
+            world,
             from,
             middle,
-            node.children[side_id as usize],
+            children[side_id as usize],
             start_fract,
             fraction_middle,
             trace,
@@ -277,10 +300,10 @@ fn ray_cast_node(
         }
 
         ray_cast_node(
-            bsp,
+            world,
             middle,
             to,
-            node.children[{
+            children[{
                 if side_id > 0 {
                     0
                 } else {
@@ -294,7 +317,13 @@ fn ray_cast_node(
     }
 }
 
-fn ray_cast_brush(bsp: &BSP, from: [f32; 3], to: [f32; 3], brush: &dbrush_t, trace: &mut Trace) {
+fn ray_cast_brush<W: CollisionWorld + ?Sized>(
+    world: &W,
+    from: [f32; 3],
+    to: [f32; 3],
+    brush: &dbrush_t,
+    trace: &mut Trace,
+) {
     if brush.num_sides == 0 {
         return;
     }
@@ -304,20 +333,15 @@ fn ray_cast_brush(bsp: &BSP, from: [f32; 3], to: [f32; 3], brush: &dbrush_t, tra
     let mut starts_out = false;
     let mut ends_out = false;
 
-    for i in 0..(brush.num_sides) {
-        let brush_side_idx = (brush.first_side + i) as usize;
-        if brush_side_idx >= bsp.brush_sides.len() {
-            continue;
-        }
-        let brush_side = &bsp.brush_sides[brush_side_idx];
+    for brush_side in world.brush_sides(brush).iter() {
         if brush_side.bevel != 0 {
             continue;
         }
 
-        if brush_side.plane_num as usize >= bsp.planes.len() {
-            continue;
-        }
-        let plane = &bsp.planes[brush_side.plane_num as usize];
+        let plane = match world.plane(brush_side.plane_num as i32) {
+            Some(plane) => plane,
+            None => continue,
+        };
 
         let start_dist = math::dot_product(from, plane.normal) - plane.distance;
         let end_dist = math::dot_product(to, plane.normal) - plane.distance;
@@ -386,11 +410,17 @@ fn ray_cast_brush(bsp: &BSP, from: [f32; 3], to: [f32; 3], brush: &dbrush_t, tra
     }
 }
 
-fn ray_cast_surface(bsp: &BSP, from: [f32; 3], to: [f32; 3], surface_idx: i32, trace: &mut Trace) {
-    if surface_idx as usize >= bsp.polys.len() {
-        return;
-    }
-    let poly = &bsp.polys[surface_idx as usize];
+fn ray_cast_surface<W: CollisionWorld + ?Sized>(
+    world: &W,
+    from: [f32; 3],
+    to: [f32; 3],
+    surface_idx: i32,
+    trace: &mut Trace,
+) {
+    let poly = match world.face_polygon(surface_idx) {
+        Some(poly) => poly,
+        None => return,
+    };
 
     let plane = &poly.plane;
     let dot1 = math::dot_product(plane.origin, from) - plane.distance;
@@ -422,3 +452,19 @@ fn ray_cast_surface(bsp: &BSP, from: [f32; 3], to: [f32; 3], surface_idx: i32, t
         trace.end_pos = intersection;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plane_sign_bits() {
+        let plane = make_plane([-1f32, 0f32, 0f32], -64f32, 0);
+        assert_eq!(plane.sign_bits, 1);
+        assert_eq!(plane.typ, 0);
+        assert_eq!(plane.distance, -64f32);
+
+        let normal = math::normalize([1f32, -1f32, -1f32]);
+        assert_eq!(make_plane(normal, 0f32, 3).sign_bits, 6);
+    }
+}
@@ -0,0 +1,321 @@
+//! Traces against the clip hulls of GoldSrc and Quake maps.
+//!
+//! Hull 0 is the point hull built from the bsp nodes, the other hulls were expanded by the
+//! compiler so tracing the origin of a box through them clips the whole box against the world.
+
+use super::{make_plane, Trace, Traceable, DIST_EPSILON};
+use crate::bsp::{dplane_t, math, validate};
+use crate::goldsrc::{GoldSrcBSP, CONTENTS_EMPTY, CONTENTS_SOLID};
+use crate::quake::QuakeBSP;
+
+/// A node of a clip hull, negative children are the contents of the space behind them.
+#[derive(Clone, Debug)]
+pub struct HullNode {
+    pub plane_num: i32,
+    pub children: [i32; 2],
+}
+
+/// A single clip hull of a model.
+#[derive(Clone, Copy)]
+pub struct Hull<'a> {
+    pub nodes: &'a [HullNode],
+    pub planes: &'a [dplane_t],
+    pub head_node: i32,
+    pub clip_mins: [f32; 3],
+    pub clip_maxs: [f32; 3],
+}
+
+impl Hull<'static> {
+    /// A hull without nodes, everything is empty space.
+    pub const EMPTY: Self = Self {
+        nodes: &[],
+        planes: &[],
+        head_node: CONTENTS_EMPTY,
+        clip_mins: [0f32; 3],
+        clip_maxs: [0f32; 3],
+    };
+}
+
+impl<'a> Hull<'a> {
+    /// Returns the node together with its plane if all of its references are valid.
+    fn node(&self, num: i32) -> Option<(&'a HullNode, &'a dplane_t)> {
+        let node = self.nodes.get(num as usize)?;
+        if !validate::children_follow_parent(num, &node.children) {
+            return None;
+        }
+        let plane = self.planes.get(node.plane_num as usize)?;
+        Some((node, plane))
+    }
+}
+
+/// Returns the contents (`CONTENTS_*`) of the hull at the given point.
+///
+/// Invalid nodes are treated as empty space.
+pub fn point_contents(hull: &Hull, point: [f32; 3]) -> i32 {
+    contents_at(hull, hull.head_node, point)
+}
+
+/// Traces a point (or a box of the hull size around the point) from `from` to `to`.
+impl Traceable for Hull<'_> {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+        trace.all_solid = true;
+        trace.start_solid = false;
+        trace.fraction = 1f32;
+        trace.fraction_left_solid = 0f32;
+        trace.end_pos = to;
+        trace.plane = None;
+        trace.contents = 0;
+        trace.surface_flags = 0;
+        trace.brush = None;
+
+        recursive_hull_check(self, self.head_node, 0f32, 1f32, from, to, trace);
+
+        if trace.all_solid {
+            trace.start_solid = true;
+            trace.fraction = 0f32;
+            trace.end_pos = from;
+            trace.contents = CONTENTS_SOLID;
+        }
+    }
+}
+
+/// Traces a point through the point hull of the world, use `hull` for boxes.
+impl Traceable for GoldSrcBSP {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+        self.hull(0)
+            .unwrap_or(Hull::EMPTY)
+            .ray_cast(from, to, trace);
+    }
+}
+
+/// Traces a point through the point hull of the world, use `hull` for boxes.
+impl Traceable for QuakeBSP {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+        self.hull(0)
+            .unwrap_or(Hull::EMPTY)
+            .ray_cast(from, to, trace);
+    }
+}
+
+fn contents_at(hull: &Hull, mut num: i32, point: [f32; 3]) -> i32 {
+    while num >= 0 {
+        let (node, plane) = match hull.node(num) {
+            Some(node) => node,
+            None => return CONTENTS_EMPTY,
+        };
+
+        num = if plane_dist(plane, point) < 0f32 {
+            node.children[1]
+        } else {
+            node.children[0]
+        };
+    }
+    num
+}
+
+fn plane_dist(plane: &dplane_t, point: [f32; 3]) -> f32 {
+    if (0..3).contains(&plane.typ) {
+        point[plane.typ as usize] - plane.distance
+    } else {
+        math::dot_product(plane.normal, point) - plane.distance
+    }
+}
+
+/// Returns false if the trace was stopped inside of this node.
+fn recursive_hull_check(
+    hull: &Hull,
+    num: i32,
+    start_fract: f32,
+    end_fract: f32,
+    from: [f32; 3],
+    to: [f32; 3],
+    trace: &mut Trace,
+) -> bool {
+    if num < 0 {
+        if num == CONTENTS_SOLID {
+            trace.start_solid = true;
+        } else {
+            trace.all_solid = false;
+        }
+        return true;
+    }
+
+    let (node, plane) = match hull.node(num) {
+        Some(node) => node,
+        None => {
+            trace.all_solid = false;
+            return true;
+        }
+    };
+
+    let start_dist = plane_dist(plane, from);
+    let end_dist = plane_dist(plane, to);
+    if start_dist >= 0f32 && end_dist >= 0f32 {
+        return recursive_hull_check(
+            hull,
+            node.children[0],
+            start_fract,
+            end_fract,
+            from,
+            to,
+            trace,
+        );
+    }
+    if start_dist < 0f32 && end_dist < 0f32 {
+        return recursive_hull_check(
+            hull,
+            node.children[1],
+            start_fract,
+            end_fract,
+            from,
+            to,
+            trace,
+        );
+    }
+
+    // put the crosspoint DIST_EPSILON pixels on the near side
+    let mut fraction = if start_dist < 0f32 {
+        (start_dist + DIST_EPSILON) / (start_dist - end_dist)
+    } else {
+        (start_dist - DIST_EPSILON) / (start_dist - end_dist)
+    };
+    // corrupt planes can produce a nan fraction, which would never leave the loop below
+    if fraction.is_nan() {
+        fraction = 0f32;
+    }
+    fraction = fraction.clamp(0f32, 1f32);
+
+    let mut fraction_middle = start_fract + (end_fract - start_fract) * fraction;
+    let mut middle = math::lerp(from, to, fraction);
+
+    let side = (start_dist < 0f32) as usize;
+    if !recursive_hull_check(
+        hull,
+        node.children[side],
+        start_fract,
+        fraction_middle,
+        from,
+        middle,
+        trace,
+    ) {
+        return false;
+    }
+
+    if contents_at(hull, node.children[side ^ 1], middle) != CONTENTS_SOLID {
+        return recursive_hull_check(
+            hull,
+            node.children[side ^ 1],
+            fraction_middle,
+            end_fract,
+            middle,
+            to,
+            trace,
+        );
+    }
+
+    // never got out of the solid area
+    if trace.all_solid {
+        return false;
+    }
+
+    // the other side of the node is solid, this is the impact point
+    trace.plane = Some(if side == 0 {
+        make_plane(plane.normal, plane.distance, plane.typ as u8)
+    } else {
+        let normal = [-plane.normal[0], -plane.normal[1], -plane.normal[2]];
+        make_plane(normal, -plane.distance, plane.typ as u8)
+    });
+    trace.contents = CONTENTS_SOLID;
+
+    // the epsilon can push the point into a solid on the other side of a nearby plane
+    while point_contents(hull, middle) == CONTENTS_SOLID {
+        fraction -= 0.1f32;
+        if fraction < 0f32 {
+            break;
+        }
+        fraction_middle = start_fract + (end_fract - start_fract) * fraction;
+        middle = math::lerp(from, to, fraction);
+    }
+
+    trace.fraction = fraction_middle;
+    trace.end_pos = middle;
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::goldsrc::fixture::MapBuilder;
+    use crate::trace::{is_visible, ray_cast};
+
+    #[test]
+    fn point_hull() {
+        let map = GoldSrcBSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        let hull = map.hull(0).unwrap();
+
+        assert_eq!(point_contents(&hull, [0f32; 3]), CONTENTS_EMPTY);
+        assert_eq!(point_contents(&hull, [0f32, 0f32, 200f32]), CONTENTS_SOLID);
+        assert!(is_visible(
+            &hull,
+            [-100f32, -100f32, -100f32],
+            [100f32, 100f32, 100f32]
+        ));
+
+        let mut trace = Trace::new();
+        ray_cast(&hull, [0f32; 3], [200f32, 0f32, 0f32], &mut trace);
+        assert!(!trace.start_solid);
+        assert!((trace.fraction - 128f32 / 200f32).abs() < 0.01f32);
+        assert!((trace.end_pos[0] - 128f32).abs() < 0.1f32);
+        assert!(trace.end_pos[0] < 128f32);
+        assert_eq!(trace.plane.as_ref().unwrap().normal, [-1f32, 0f32, 0f32]);
+
+        ray_cast(&hull, [0f32; 3], [0f32, -200f32, 0f32], &mut trace);
+        assert_eq!(trace.plane.as_ref().unwrap().normal, [0f32, 1f32, 0f32]);
+
+        ray_cast(
+            &hull,
+            [0f32, 0f32, 200f32],
+            [0f32, 0f32, 300f32],
+            &mut trace,
+        );
+        assert!(trace.all_solid);
+        assert_eq!(trace.fraction, 0f32);
+
+        // the map itself traces its point hull
+        ray_cast(&map, [0f32; 3], [200f32, 0f32, 0f32], &mut trace);
+        assert!((trace.end_pos[0] - 128f32).abs() < 0.1f32);
+        assert!(!is_visible(&map, [0f32; 3], [0f32, 0f32, 200f32]));
+        assert!(is_visible(&Hull::EMPTY, [0f32; 3], [0f32, 0f32, 200f32]));
+    }
+
+    #[test]
+    fn clip_hulls() {
+        let map = GoldSrcBSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+
+        // standing player hull
+        let hull = map.hull(1).unwrap();
+        assert_eq!(hull.clip_maxs, [16f32, 16f32, 36f32]);
+        assert_eq!(point_contents(&hull, [100f32, 0f32, 0f32]), CONTENTS_EMPTY);
+        assert_eq!(point_contents(&hull, [120f32, 0f32, 0f32]), CONTENTS_SOLID);
+        assert_eq!(point_contents(&hull, [0f32, 0f32, -100f32]), CONTENTS_SOLID);
+
+        let mut trace = Trace::new();
+        ray_cast(&hull, [0f32; 3], [0f32, 0f32, -200f32], &mut trace);
+        assert!((trace.end_pos[2] + 92f32).abs() < 0.1f32);
+        assert!(!is_visible(&hull, [0f32; 3], [0f32, 0f32, -100f32]));
+        assert!(is_visible(
+            &map.hull(0).unwrap(),
+            [0f32; 3],
+            [0f32, 0f32, -100f32]
+        ));
+
+        // large hull
+        let hull = map.hull(2).unwrap();
+        ray_cast(&hull, [0f32; 3], [-200f32, 0f32, 0f32], &mut trace);
+        assert!((trace.end_pos[0] + 96f32).abs() < 0.1f32);
+
+        // crouching player hull
+        let hull = map.hull(3).unwrap();
+        assert_eq!(point_contents(&hull, [0f32, 0f32, -100f32]), CONTENTS_EMPTY);
+    }
+}
@@ -0,0 +1,424 @@
+//! Traces against the brushes and patches of Quake 3 maps.
+//!
+//! Brushes are clipped like the engine does (`CM_TraceThroughBrush`), the trace stops
+//! `SURFACE_CLIP_EPSILON` units in front of the brush side that was entered last.
+//! Patches are tested against the triangles of their tessellated mesh.
+
+use super::{make_plane, Trace, Traceable};
+use crate::bsp::{math, validate};
+use crate::quake3::*;
+
+pub const SURFACE_CLIP_EPSILON: f32 = 0.125f32;
+
+/// Traces a ray against everything that blocks shots (`MASK_SHOT`).
+impl Traceable for Quake3BSP {
+    fn ray_cast(&self, from: [f32; 3], to: [f32; 3], trace: &mut Trace) {
+        ray_cast_masked(self, from, to, MASK_SHOT, trace);
+    }
+}
+
+/// Traces a ray against all brushes and patches whose contents match the mask.
+pub fn ray_cast_masked(
+    map: &Quake3BSP,
+    from: [f32; 3],
+    to: [f32; 3],
+    mask: i32,
+    trace: &mut Trace,
+) {
+    trace.all_solid = false;
+    trace.start_solid = false;
+    trace.fraction = 1f32;
+    trace.fraction_left_solid = 0f32;
+    trace.plane = None;
+    trace.contents = 0;
+    trace.surface_flags = 0;
+    trace.brush = None;
+    trace.brush_side = 0;
+
+    if !map.nodes.is_empty() {
+        let mut tw = TraceWork {
+            map,
+            from,
+            to,
+            mask,
+            trace,
+        };
+        tw.trace_node(0, 0f32, 1f32, from, to);
+    }
+
+    if trace.fraction < 1f32 {
+        for i in 0..3 {
+            trace.end_pos[i] = from[i] + trace.fraction * (to[i] - from[i]);
+        }
+    } else {
+        trace.end_pos = to;
+    }
+}
+
+struct TraceWork<'a, 'b> {
+    map: &'a Quake3BSP,
+    from: [f32; 3],
+    to: [f32; 3],
+    mask: i32,
+    trace: &'b mut Trace,
+}
+
+impl<'a, 'b> TraceWork<'a, 'b> {
+    fn trace_node(
+        &mut self,
+        num: i32,
+        start_fract: f32,
+        end_fract: f32,
+        p1: [f32; 3],
+        p2: [f32; 3],
+    ) {
+        // already hit something nearer
+        if self.trace.fraction <= start_fract {
+            return;
+        }
+
+        if num < 0 {
+            self.trace_leaf(-1 - num);
+            return;
+        }
+
+        let node = match self.map.nodes.get(num as usize) {
+            Some(node) => node,
+            None => return,
+        };
+        if !validate::children_follow_parent(num, &node.children) {
+            return;
+        }
+        let plane = match self.map.planes.get(node.plane_num as usize) {
+            Some(plane) => plane,
+            None => return,
+        };
+
+        let t1 = math::dot_product(p1, plane.normal) - plane.distance;
+        let t2 = math::dot_product(p2, plane.normal) - plane.distance;
+
+        // see which sides we need to consider
+        if t1 >= 1f32 && t2 >= 1f32 {
+            self.trace_node(node.children[0], start_fract, end_fract, p1, p2);
+            return;
+        }
+        if t1 < -1f32 && t2 < -1f32 {
+            self.trace_node(node.children[1], start_fract, end_fract, p1, p2);
+            return;
+        }
+
+        // put the crosspoint SURFACE_CLIP_EPSILON pixels on the near side
+        let (side, fraction, fraction2) = if t1 < t2 {
+            let inverse_dist = 1f32 / (t1 - t2);
+            (
+                1,
+                (t1 + SURFACE_CLIP_EPSILON) * inverse_dist,
+                (t1 + SURFACE_CLIP_EPSILON) * inverse_dist,
+            )
+        } else if t1 > t2 {
+            let inverse_dist = 1f32 / (t1 - t2);
+            (
+                0,
+                (t1 + SURFACE_CLIP_EPSILON) * inverse_dist,
+                (t1 - SURFACE_CLIP_EPSILON) * inverse_dist,
+            )
+        } else {
+            (0, 1f32, 0f32)
+        };
+
+        // move up to the node
+        let fraction = fraction.clamp(0f32, 1f32);
+        let middle_fract = start_fract + (end_fract - start_fract) * fraction;
+        let middle = math::lerp(p1, p2, fraction);
+        self.trace_node(node.children[side], start_fract, middle_fract, p1, middle);
+
+        // go past the node
+        let fraction2 = fraction2.clamp(0f32, 1f32);
+        let middle_fract = start_fract + (end_fract - start_fract) * fraction2;
+        let middle = math::lerp(p1, p2, fraction2);
+        self.trace_node(node.children[side ^ 1], middle_fract, end_fract, middle, p2);
+    }
+
+    fn trace_leaf(&mut self, leaf: i32) {
+        let map = self.map;
+        let leaf = match map.leaves.get(leaf as usize) {
+            Some(leaf) => leaf,
+            None => return,
+        };
+
+        for i in 0..leaf.num_leaf_brushes.max(0) as usize {
+            let brush = match map
+                .leaf_brushes
+                .get(leaf.first_leaf_brush as usize + i)
+                .and_then(|&brush| map.brushes.get(brush as usize))
+            {
+                Some(brush) => brush,
+                None => continue,
+            };
+            let contents = match map.shaders.get(brush.shader_num as usize) {
+                Some(shader) => shader.content_flags,
+                None => continue,
+            };
+            if contents & self.mask == 0 {
+                continue;
+            }
+
+            self.trace_brush(brush, contents);
+            if self.trace.all_solid {
+                return;
+            }
+        }
+
+        for i in 0..leaf.num_leaf_surfaces.max(0) as usize {
+            let surface_idx = match map.leaf_surfaces.get(leaf.first_leaf_surface as usize + i) {
+                Some(&surface) => surface as usize,
+                None => continue,
+            };
+            let (surface, mesh) = match (map.surfaces.get(surface_idx), map.meshes.get(surface_idx))
+            {
+                (Some(surface), Some(mesh)) if surface.surface_type == MST_PATCH => (surface, mesh),
+                _ => continue,
+            };
+            let shader = match map.shaders.get(surface.shader_num as usize) {
+                Some(shader) => shader,
+                None => continue,
+            };
+            if shader.content_flags & self.mask == 0 || shader.surface_flags & SURF_NONSOLID != 0 {
+                continue;
+            }
+
+            self.trace_patch(mesh, shader);
+        }
+    }
+
+    fn trace_brush(&mut self, brush: &dbrush_t, contents: i32) {
+        if brush.num_sides <= 0 {
+            return;
+        }
+
+        let map = self.map;
+        let mut enter_fraction = -1f32;
+        let mut leave_fraction = 1f32;
+        let mut clip: Option<(usize, &dplane_t, &dbrushside_t)> = None;
+        let mut starts_out = false;
+        let mut ends_out = false;
+
+        // compare the trace against all planes of the brush, find the latest time
+        // the trace crosses a plane towards the interior and an earlier time
+        // the trace crosses a plane towards the exterior
+        for i in 0..brush.num_sides as usize {
+            let side_idx = brush.first_side as usize + i;
+            let (side, plane) = match map
+                .brush_sides
+                .get(side_idx)
+                .and_then(|side| Some((side, map.planes.get(side.plane_num as usize)?)))
+            {
+                Some(side) => side,
+                None => continue,
+            };
+
+            let d1 = math::dot_product(self.from, plane.normal) - plane.distance;
+            let d2 = math::dot_product(self.to, plane.normal) - plane.distance;
+
+            if d2 > 0f32 {
+                // endpoint is not in solid
+                ends_out = true;
+            }
+            if d1 > 0f32 {
+                starts_out = true;
+            }
+
+            // if completely in front of face, no intersection with the entire brush
+            if d1 > 0f32 && (d2 >= SURFACE_CLIP_EPSILON || d2 >= d1) {
+                return;
+            }
+
+            // if it doesn't cross the plane, the plane isn't relevant
+            if d1 <= 0f32 && d2 <= 0f32 {
+                continue;
+            }
+
+            // crosses face
+            if d1 > d2 {
+                // enter
+                let fraction = ((d1 - SURFACE_CLIP_EPSILON) / (d1 - d2)).max(0f32);
+                if fraction > enter_fraction {
+                    enter_fraction = fraction;
+                    clip = Some((side_idx, plane, side));
+                }
+            } else {
+                // leave
+                let fraction = ((d1 + SURFACE_CLIP_EPSILON) / (d1 - d2)).min(1f32);
+                if fraction < leave_fraction {
+                    leave_fraction = fraction;
+                }
+            }
+        }
+
+        // all planes have been checked, and the trace was not completely outside the brush
+        if !starts_out {
+            self.trace.start_solid = true;
+            if !ends_out {
+                self.trace.all_solid = true;
+                self.trace.fraction = 0f32;
+                self.trace.contents = contents;
+            }
+            return;
+        }
+
+        if enter_fraction < leave_fraction
+            && enter_fraction > -1f32
+            && enter_fraction < self.trace.fraction
+        {
+            if let Some((side_idx, plane, side)) = clip {
+                self.trace.fraction = enter_fraction.max(0f32);
+                self.trace.plane = Some(make_plane(
+                    plane.normal,
+                    plane.distance,
+                    plane_type(plane.normal),
+                ));
+                self.trace.surface_flags = map
+                    .shaders
+                    .get(side.shader_num as usize)
+                    .map(|shader| shader.surface_flags)
+                    .unwrap_or(0);
+                self.trace.contents = contents;
+                self.trace.brush_side = side_idx as i32;
+            }
+        }
+    }
+
+    fn trace_patch(&mut self, mesh: &SurfaceMesh, shader: &dshader_t) {
+        for triangle in mesh.triangles() {
+            let edge1 = math::sub(triangle[1], triangle[0]);
+            let edge2 = math::sub(triangle[2], triangle[0]);
+            let normal = math::normalize(math::cross(edge1, edge2));
+            if normal == [0f32; 3] {
+                continue;
+            }
+            let distance = math::dot_product(normal, triangle[0]);
+
+            let d1 = math::dot_product(self.from, normal) - distance;
+            let d2 = math::dot_product(self.to, normal) - distance;
+            if (d1 > 0f32) == (d2 > 0f32) || d1 == d2 {
+                continue;
+            }
+
+            // intersection with the triangle plane, has to be inside of all three edges
+            let point = math::lerp(self.from, self.to, d1 / (d1 - d2));
+            let inside = (0..3).all(|i| {
+                let edge = math::sub(triangle[(i + 1) % 3], triangle[i]);
+                let to_point = math::sub(point, triangle[i]);
+                math::dot_product(math::cross(edge, to_point), normal) >= 0f32
+            });
+            if !inside {
+                continue;
+            }
+
+            // stop in front of the side facing the start of the trace
+            let (normal, distance, d1, d2) = if d1 < 0f32 {
+                ([-normal[0], -normal[1], -normal[2]], -distance, -d1, -d2)
+            } else {
+                (normal, distance, d1, d2)
+            };
+            let fraction = ((d1 - SURFACE_CLIP_EPSILON) / (d1 - d2)).max(0f32);
+            if fraction < self.trace.fraction {
+                self.trace.fraction = fraction;
+                self.trace.plane = Some(make_plane(normal, distance, plane_type(normal)));
+                self.trace.surface_flags = shader.surface_flags;
+                self.trace.contents = shader.content_flags;
+            }
+        }
+    }
+}
+
+/// The plane type of a normal, axial planes facing the positive axis use the axis.
+fn plane_type(normal: [f32; 3]) -> u8 {
+    normal.iter().position(|&n| n == 1f32).unwrap_or(3) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quake3::fixture::{self, MapBuilder};
+    use crate::trace::{is_visible, ray_cast};
+
+    fn box_room() -> Quake3BSP {
+        Quake3BSP::from_bytes(&MapBuilder::box_room().build()).unwrap()
+    }
+
+    #[test]
+    fn brush() {
+        let map = box_room();
+        assert!(is_visible(
+            &map,
+            [-64f32, 32f32, 0f32],
+            [48f32, 32f32, 0f32]
+        ));
+        assert!(is_visible(&map, [-64f32, 0f32, 0f32], [-32f32, 0f32, 0f32]));
+        assert!(!is_visible(&map, [-64f32, 0f32, 0f32], [64f32, 0f32, 0f32]));
+
+        let mut trace = Trace::new();
+        ray_cast(&map, [-64f32, 0f32, 0f32], [32f32, 0f32, 0f32], &mut trace);
+        assert!(!trace.start_solid);
+        assert!(trace.fraction < 1f32);
+        assert!((trace.end_pos[0] + 16.125f32).abs() < 0.01f32);
+        assert_eq!(trace.plane.as_ref().unwrap().normal, [-1f32, 0f32, 0f32]);
+        assert_eq!(trace.brush_side, 1);
+        assert_eq!(trace.contents, CONTENTS_SOLID);
+
+        // starting inside of the brush
+        ray_cast(&map, [0f32; 3], [0f32, 0f32, 8f32], &mut trace);
+        assert!(trace.all_solid);
+        assert_eq!(trace.fraction, 0f32);
+
+        ray_cast(&map, [0f32; 3], [0f32, 0f32, 64f32], &mut trace);
+        assert!(trace.start_solid);
+        assert!(!trace.all_solid);
+        assert_eq!(trace.fraction, 1f32);
+    }
+
+    #[test]
+    fn patch() {
+        let map = box_room();
+        let mut trace = Trace::new();
+        ray_cast(&map, [32f32, 0f32, 0f32], [128f32, 0f32, 0f32], &mut trace);
+        assert!(trace.fraction < 1f32);
+        assert!((trace.end_pos[0] - 72f32).abs() < 0.5f32);
+        assert!(trace.end_pos[0] < 72f32);
+        assert_eq!(trace.surface_flags, SURF_NOMARKS);
+        assert!(trace.plane.as_ref().unwrap().normal[0] < 0f32);
+
+        // the patch only spans -32 to 32
+        assert!(is_visible(
+            &map,
+            [32f32, 48f32, 0f32],
+            [128f32, 48f32, 0f32]
+        ));
+
+        // patches that are not solid are ignored
+        let mut builder = MapBuilder::box_room();
+        builder.shaders[1] = fixture::shader("textures/base/curve", SURF_NONSOLID, CONTENTS_SOLID);
+        let map = Quake3BSP::from_bytes(&builder.build()).unwrap();
+        assert!(is_visible(&map, [32f32, 0f32, 0f32], [128f32, 0f32, 0f32]));
+    }
+
+    #[test]
+    fn mask() {
+        let mut builder = MapBuilder::box_room();
+        builder.shaders[0].content_flags = CONTENTS_WATER;
+        let map = Quake3BSP::from_bytes(&builder.build()).unwrap();
+        assert!(is_visible(&map, [-64f32, 0f32, 0f32], [-8f32, 0f32, 0f32]));
+
+        let mut trace = Trace::new();
+        ray_cast_masked(
+            &map,
+            [-64f32, 0f32, 0f32],
+            [-8f32, 0f32, 0f32],
+            CONTENTS_WATER,
+            &mut trace,
+        );
+        assert!(trace.fraction < 1f32);
+        assert_eq!(trace.contents, CONTENTS_WATER);
+    }
+}
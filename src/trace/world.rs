@@ -0,0 +1,288 @@
+//! The view of a map that the trace code works on.
+//!
+//! `BSP` implements `CollisionWorld` directly, other backends (synthetic worlds, flattened
+//! caches, other bsp variants) only have to provide the lookups below to reuse `trace::ray_cast`.
+
+use crate::bsp::*;
+
+use std::convert::TryFrom;
+
+/// Lookups into the bsp tree, brushes and face polygons of a map.
+///
+/// Leafs are addressed by their leaf index, node children are either node indices or
+/// `-1 - leaf` for leafs. Invalid indices return `None` or empty slices, the trace code skips them.
+pub trait CollisionWorld {
+    /// The node the traversal starts at.
+    fn head_node(&self) -> i32 {
+        0
+    }
+
+    /// Returns the plane with the given index.
+    fn plane(&self, index: i32) -> Option<&cplane_t>;
+
+    /// Returns the splitting plane and the two children (front, back) of a node.
+    fn node(&self, index: i32) -> Option<(&cplane_t, [i32; 2])>;
+
+    /// Returns the contents (`CONTENTS_*`) of a leaf.
+    fn leaf_contents(&self, leaf: i32) -> Option<i32>;
+
+    /// Returns the indices of all brushes in a leaf.
+    fn leaf_brushes(&self, leaf: i32) -> &[u16];
+
+    /// Returns the indices of all faces in a leaf.
+    fn leaf_faces(&self, leaf: i32) -> &[u16];
+
+    /// Returns the brush with the given index.
+    fn brush(&self, index: i32) -> Option<&dbrush_t>;
+
+    /// Returns the sides of a brush, their planes are looked up with `plane`.
+    fn brush_sides(&self, brush: &dbrush_t) -> &[dbrushside_t];
+
+    /// Returns the polygon of a face, only textured faces have one.
+    fn face_polygon(&self, face: i32) -> Option<&Polygon>;
+
+    /// Walks the tree down to the leaf that contains the point.
+    ///
+    /// Returns `None` if the tree references an invalid node.
+    fn leaf_at(&self, point: [f32; 3]) -> Option<i32> {
+        let mut num = self.head_node();
+        while num >= 0 {
+            let (plane, children) = self.node(num)?;
+            if !validate::children_follow_parent(num, &children) {
+                return None;
+            }
+
+            let dist = math::dot_product(point, plane.normal) - plane.distance;
+            num = if dist >= 0f32 {
+                children[0]
+            } else {
+                children[1]
+            };
+        }
+        Some(-1 - num)
+    }
+
+    /// Returns the contents of the leaf that contains the point, empty if there is none.
+    fn point_contents(&self, point: [f32; 3]) -> i32 {
+        self.leaf_at(point)
+            .and_then(|leaf| self.leaf_contents(leaf))
+            .unwrap_or(0)
+    }
+}
+
+/// Returns the part of `first..first + count` that lies within `data`.
+fn clamped<T>(data: &[T], first: i64, count: i64) -> &[T] {
+    let start = first.clamp(0, data.len() as i64) as usize;
+    let end = (first + count).clamp(start as i64, data.len() as i64) as usize;
+    &data[start..end]
+}
+
+impl CollisionWorld for BSP {
+    fn plane(&self, index: i32) -> Option<&cplane_t> {
+        self.planes.get(usize::try_from(index).ok()?)
+    }
+
+    fn node(&self, index: i32) -> Option<(&cplane_t, [i32; 2])> {
+        let node = self.nodes.get(usize::try_from(index).ok()?)?;
+        let plane = self.planes.get(node.plane_idx as usize)?;
+        Some((plane, node.children))
+    }
+
+    fn leaf_contents(&self, leaf: i32) -> Option<i32> {
+        let leaf = self.leaves.get(usize::try_from(leaf).ok()?)?;
+        Some(leaf.contents)
+    }
+
+    fn leaf_brushes(&self, leaf: i32) -> &[u16] {
+        match usize::try_from(leaf).ok().and_then(|i| self.leaves.get(i)) {
+            Some(leaf) => clamped(
+                &self.leaf_brushes,
+                leaf.first_leaf_brush as i64,
+                leaf.num_leaf_brushes as i64,
+            ),
+            None => &[],
+        }
+    }
+
+    fn leaf_faces(&self, leaf: i32) -> &[u16] {
+        match usize::try_from(leaf).ok().and_then(|i| self.leaves.get(i)) {
+            Some(leaf) => clamped(
+                &self.leaf_faces,
+                leaf.first_leaf_face as i64,
+                leaf.num_leaf_faces as i64,
+            ),
+            None => &[],
+        }
+    }
+
+    fn brush(&self, index: i32) -> Option<&dbrush_t> {
+        self.brushes.get(usize::try_from(index).ok()?)
+    }
+
+    fn brush_sides(&self, brush: &dbrush_t) -> &[dbrushside_t] {
+        clamped(
+            &self.brush_sides,
+            brush.first_side as i64,
+            brush.num_sides.max(0) as i64,
+        )
+    }
+
+    fn face_polygon(&self, face: i32) -> Option<&Polygon> {
+        let poly = (*self.face_polys.get(usize::try_from(face).ok()?)?)?;
+        self.polys.get(poly as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::fixture::MapBuilder;
+    use crate::trace::{self, Trace};
+
+    use dataview::PodMethods;
+
+    /// A single solid 32x32x32 brush around the origin without any tree, everything is one leaf.
+    struct BrushWorld {
+        planes: Vec<cplane_t>,
+        brush: dbrush_t,
+        sides: Vec<dbrushside_t>,
+    }
+
+    impl BrushWorld {
+        fn new() -> Self {
+            let mut planes = Vec::new();
+            for axis in 0..3 {
+                for &sign in [1f32, -1f32].iter() {
+                    let mut normal = [0f32; 3];
+                    normal[axis] = sign;
+                    planes.push(cplane_t {
+                        normal,
+                        distance: 16f32,
+                        typ: 3,
+                        sign_bits: 0,
+                        pad0: [0; 2],
+                    });
+                }
+            }
+            let sides = (0..6)
+                .map(|plane_num| dbrushside_t {
+                    plane_num,
+                    tex_info: 0,
+                    disp_info: -1,
+                    bevel: 0,
+                    thin: 0,
+                })
+                .collect();
+
+            Self {
+                planes,
+                brush: dbrush_t {
+                    first_side: 0,
+                    num_sides: 6,
+                    contents: trace::CONTENTS_SOLID,
+                },
+                sides,
+            }
+        }
+    }
+
+    impl CollisionWorld for BrushWorld {
+        fn head_node(&self) -> i32 {
+            -1
+        }
+
+        fn plane(&self, index: i32) -> Option<&cplane_t> {
+            self.planes.get(index as usize)
+        }
+
+        fn node(&self, _index: i32) -> Option<(&cplane_t, [i32; 2])> {
+            None
+        }
+
+        fn leaf_contents(&self, _leaf: i32) -> Option<i32> {
+            Some(trace::CONTENTS_EMPTY)
+        }
+
+        fn leaf_brushes(&self, _leaf: i32) -> &[u16] {
+            &[0]
+        }
+
+        fn leaf_faces(&self, _leaf: i32) -> &[u16] {
+            &[]
+        }
+
+        fn brush(&self, index: i32) -> Option<&dbrush_t> {
+            if index == 0 {
+                Some(&self.brush)
+            } else {
+                None
+            }
+        }
+
+        fn brush_sides(&self, _brush: &dbrush_t) -> &[dbrushside_t] {
+            &self.sides
+        }
+
+        fn face_polygon(&self, _face: i32) -> Option<&Polygon> {
+            None
+        }
+    }
+
+    #[test]
+    fn synthetic_world() {
+        let world = BrushWorld::new();
+        assert_eq!(world.leaf_at([0f32; 3]), Some(0));
+        assert!(!trace::is_visible(
+            &world,
+            [-64f32, 0f32, 0f32],
+            [64f32, 0f32, 0f32]
+        ));
+        assert!(trace::is_visible(
+            &world,
+            [-64f32, 32f32, 0f32],
+            [64f32, 32f32, 0f32]
+        ));
+
+        let mut trace = Trace::new();
+        trace::ray_cast(
+            &world,
+            [-64f32, 0f32, 0f32],
+            [64f32, 0f32, 0f32],
+            &mut trace,
+        );
+        assert!((trace.end_pos[0] + 16f32).abs() < 0.1f32);
+    }
+
+    #[test]
+    fn bsp_world() {
+        let map = BSP::from_bytes(&MapBuilder::box_room().build()).unwrap();
+        assert_eq!(map.leaf_at([8f32, 0f32, 0f32]), Some(0));
+        assert_eq!(map.leaf_at([-8f32, 0f32, 0f32]), Some(1));
+        assert_eq!(map.point_contents([8f32, 0f32, 0f32]), 0);
+        assert_eq!(map.leaf_brushes(0), &[0u16][..]);
+        assert!(map.leaf_brushes(2).is_empty());
+        assert_eq!(map.brush_sides(&map.brushes[0]).len(), 6);
+        assert!(map.face_polygon(0).is_some());
+    }
+
+    #[test]
+    fn face_polygon() {
+        // an untextured face without polygon is stored before the textured one
+        let builder = MapBuilder::box_room();
+        let mut face = dface_t::zeroed();
+        face.as_bytes_mut()
+            .copy_from_slice(&builder.lumps[LumpIndex::Faces as usize].data);
+        let mut untextured = face.clone();
+        untextured.tex_info = 0;
+        let builder = builder
+            .lump(LumpIndex::Faces, &[untextured, face][..])
+            .lump(LumpIndex::LeafFaces, &[1u16][..]);
+        let map = BSP::from_bytes(&builder.build()).unwrap();
+
+        assert_eq!(map.polys.len(), 1);
+        assert_eq!(map.face_polys, vec![None, Some(0)]);
+        assert!(map.face_polygon(0).is_none());
+        assert!(std::ptr::eq(map.face_polygon(1).unwrap(), &map.polys[0]));
+        assert!(map.face_polygon(2).is_none());
+    }
+}
@@ -61,8 +61,11 @@ struct FileTag {
 }
 
 fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
-    if id == "" {
-        return Err(Error::new("no workshop id supplied"));
+    if id.is_empty() {
+        return Err(Error::Download {
+            reason: "no workshop id supplied",
+            source: None,
+        });
     }
 
     info!("downloading file from steam workshop with id={}", id);
@@ -82,12 +85,15 @@ fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
         .post("http://api.steampowered.com/ISteamRemoteStorage/GetPublishedFileDetails/v0001")
         .form(&params)
         .send()
-        .map_err(|_| Error::new("http request send error"))?
+        .map_err(Error::download("http request send error"))?
         .json()
-        .map_err(|_| Error::new("json parse error"))?;
+        .map_err(Error::download("json parse error"))?;
 
     if file_info_resp.response.publishedfiledetails.is_empty() {
-        return Err(Error::new("published file details not found"));
+        return Err(Error::Download {
+            reason: "published file details not found",
+            source: None,
+        });
     }
 
     debug!("file_info_resp: {:?}", file_info_resp);
@@ -100,7 +106,7 @@ fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
     let mut zip_file_resp = client
         .get(&file_info_resp.response.publishedfiledetails[0].file_url)
         .send()
-        .map_err(|_| Error::new("http error"))?;
+        .map_err(Error::download("http error"))?;
 
     // write to buffer
     let mut zip_buffer = Vec::new();
@@ -108,18 +114,18 @@ fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
         let mut zip_writer = BufWriter::new(&mut zip_buffer);
         zip_file_resp
             .copy_to(&mut zip_writer)
-            .map_err(|_| Error::new("zip archive write error"))?;
+            .map_err(Error::download("zip archive write error"))?;
     }
 
     // unpack zip
     {
         let zip_cursor = std::io::Cursor::new(&zip_buffer[..]);
         let mut zip_archive =
-            ZipArchive::new(zip_cursor).map_err(|_| Error::new("zip archive error"))?;
+            ZipArchive::new(zip_cursor).map_err(Error::download("zip archive error"))?;
         for i in 0..zip_archive.len() {
             let mut file = zip_archive
                 .by_index(i)
-                .map_err(|_| Error::new("zip archive error"))?;
+                .map_err(Error::download("zip archive error"))?;
             let outpath = if use_full_path {
                 // create a ./workshop/{id}/mapname.bsp file
                 file_dest_folder.join(file.mangled_name())
@@ -133,8 +139,8 @@ fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
                 id, outpath
             );
 
-            let mut outfile = File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
+            let mut outfile = File::create(&outpath)?;
+            io::copy(&mut file, &mut outfile)?;
         }
     }
 
@@ -142,12 +148,18 @@ fn download_workshop(id: &str, use_full_path: bool) -> Result<()> {
 }
 
 fn download_fastdl(map_name: &str, fast_dl: &str) -> Result<()> {
-    if map_name == "" {
-        return Err(Error::new("no map name supplied"));
+    if map_name.is_empty() {
+        return Err(Error::Download {
+            reason: "no map name supplied",
+            source: None,
+        });
     }
 
-    if fast_dl == "" {
-        return Err(Error::new("no fastdl server configured"));
+    if fast_dl.is_empty() {
+        return Err(Error::Download {
+            reason: "no fastdl server configured",
+            source: None,
+        });
     }
 
     info!(
@@ -166,14 +178,16 @@ fn download_fastdl(map_name: &str, fast_dl: &str) -> Result<()> {
     let mut bz2_file_resp = client
         .get(&url_bz2)
         .send()
-        .map_err(|_| Error::new("http request failed"))?;
+        .map_err(Error::download("http request failed"))?;
 
     if bz2_file_resp.status() == reqwest::StatusCode::OK {
         // write bz2 to buffer
         let mut bz2_buffer = Vec::new();
         {
             let mut bz2_writer = BufWriter::new(&mut bz2_buffer);
-            bz2_file_resp.copy_to(&mut bz2_writer).unwrap();
+            bz2_file_resp
+                .copy_to(&mut bz2_writer)
+                .map_err(Error::download("unable to read the response body"))?;
         }
 
         // unpack bz2
@@ -197,17 +211,22 @@ fn download_fastdl(map_name: &str, fast_dl: &str) -> Result<()> {
     let mut file_bsp = client
         .get(&url_bsp)
         .send()
-        .map_err(|_| Error::new("http request failed"))?;
+        .map_err(Error::download("http request failed"))?;
 
     if file_bsp.status() != reqwest::StatusCode::OK {
-        return Err(Error::new("unable to download file from fastdl server"));
+        return Err(Error::Download {
+            reason: "unable to download file from fastdl server",
+            source: None,
+        });
     }
 
     // download file
     let file_dest_path = file_dest_folder.join(map_name.to_string() + ".bsp");
 
     let mut file_dest = File::create(file_dest_path.clone())?;
-    file_bsp.copy_to(&mut file_dest).unwrap();
+    file_bsp
+        .copy_to(&mut file_dest)
+        .map_err(Error::download("unable to read the response body"))?;
 
     Ok(())
 }
@@ -234,7 +253,10 @@ pub fn try_download_default_map(map_name: &str) -> Result<()> {
         "cs_office" => download_workshop("125444404", false),
         "de_shortdust" => download_workshop("344476023", false),
         "de_shorttrain" => download_workshop("125439738", false),
-        _ => Err(Error::new("map is not a default map")),
+        _ => Err(Error::Download {
+            reason: "map is not a default map",
+            source: None,
+        }),
     }
 }
 */
@@ -246,15 +268,18 @@ pub fn map_path(map_name: &str) -> PathBuf {
 }
 
 pub fn map_exists(map_name: &str) -> bool {
-    if map_name == "" {
+    if map_name.is_empty() {
         return false;
     }
-    return Path::new(&map_path(map_name)).exists();
+    Path::new(&map_path(map_name)).exists()
 }
 
 pub fn download(map_name: &str, fast_dl: &str) -> Result<()> {
-    if map_name == "" {
-        return Err(Error::new("invalid map name"));
+    if map_name.is_empty() {
+        return Err(Error::Download {
+            reason: "invalid map name",
+            source: None,
+        });
     }
 
     /*if try_download_default_map(map_name).is_ok() {
@@ -264,16 +289,22 @@ pub fn download(map_name: &str, fast_dl: &str) -> Result<()> {
         let map_name_split = map_name.split("/").collect::<Vec<_>>();
         if map_name_split.len() != 3 {
             warn!("unable to parse workshop map name: {}", map_name);
-            return Err(Error::new("unable to parse map name"));
+            return Err(Error::Download {
+                reason: "unable to parse map name",
+                source: None,
+            });
         }
         info!("downloading workshop map: {}", map_name_split[1]);
         download_workshop(map_name_split[1], true)
-    } else if fast_dl != "" {
+    } else if !fast_dl.is_empty() {
         info!("downloading map via fastdl: {} -> {}", fast_dl, map_name);
         download_fastdl(map_name, fast_dl)
     } else {
         debug!("no map download path found for map {}", map_name);
-        Err(Error::new("backup not implemented"))
+        Err(Error::Download {
+            reason: "backup not implemented",
+            source: None,
+        })
     }
 }
 